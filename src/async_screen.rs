@@ -0,0 +1,375 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+use crate::lock::DeviceLock;
+use crate::{screen_rev_a, screen_rev_b, screen_rev_c};
+use crate::{Coord, Error, Image, Rect, Res};
+use crate::{Orientation, Revision, Screen, ScreenPort};
+use crate::{ScreenRevA, ScreenRevB, ScreenRevC};
+
+const READ_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// A screen driven from an async runtime.
+///
+/// The operations mirror [`Screen`]. Dropping an operation future, for
+/// example with `tokio::time::timeout`, returns immediately; the rest of
+/// the operation is sent before the next one starts.
+pub trait AsyncScreen {
+    fn screen_size(&self) -> (usize, usize);
+    fn init(&mut self) -> impl Future<Output = Res<()>> + Send;
+    fn clear(&mut self) -> impl Future<Output = Res<()>> + Send;
+    fn screen_on(&mut self) -> impl Future<Output = Res<()>> + Send;
+    fn screen_off(&mut self) -> impl Future<Output = Res<()>> + Send;
+    fn set_orientation(&mut self, o: Orientation) -> impl Future<Output = Res<()>> + Send;
+    fn set_brightness(&mut self, level: usize) -> impl Future<Output = Res<()>> + Send;
+    fn display_image(
+        &mut self,
+        img888: &Image,
+        rect: &Rect,
+        pos: &Coord,
+    ) -> impl Future<Output = Res<()>> + Send;
+}
+
+// A screen operation, run by the protocol thread
+type Op = Box<dyn FnOnce(&mut dyn Screen) -> Res<()> + Send>;
+
+// Port operations requested by the protocol thread
+enum PortRequest {
+    Write(Vec<u8>),
+    Flush,
+    Read(usize, mpsc::SyncSender<io::Result<Vec<u8>>>),
+    Done(Res<()>, (usize, usize)), // operation result and new screen size
+}
+
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "screen protocol thread stopped")
+}
+
+// The port of the protocol thread, forwarding the traffic to the stream
+// owned by the async side.
+struct BridgePort {
+    tx: UnboundedSender<PortRequest>,
+}
+
+impl Read for BridgePort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (reply_tx, reply_rx) = mpsc::sync_channel(1);
+        self.tx
+            .send(PortRequest::Read(buf.len(), reply_tx))
+            .map_err(|_| stopped())?;
+        let data = reply_rx.recv().map_err(|_| stopped())??;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+}
+
+impl Write for BridgePort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx
+            .send(PortRequest::Write(buf.to_vec()))
+            .map_err(|_| stopped())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tx.send(PortRequest::Flush).map_err(|_| stopped())
+    }
+}
+
+impl ScreenPort for BridgePort {
+    fn get_buf(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+// The protocol thread runs the blocking backend, the I/O is done by the
+// async side.
+fn run(mut screen: Box<dyn Screen>, ops: Receiver<Op>, tx: UnboundedSender<PortRequest>) {
+    while let Ok(op) = ops.recv() {
+        let res = op(screen.as_mut());
+        if tx
+            .send(PortRequest::Done(res, screen.screen_size()))
+            .is_err()
+        {
+            break;
+        }
+    }
+    log::debug!("screen protocol thread stopped");
+}
+
+/// An [`AsyncScreen`] on a serial port or any other async stream.
+///
+/// The screen protocol runs in a background thread, while the data is
+/// transferred by the async operations.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), turing_screen::Error> {
+/// use std::time::Duration;
+/// use turing_screen::{AsyncScreen, AsyncSerialScreen, Revision};
+///
+/// let mut scr = AsyncSerialScreen::open("/dev/ttyACM0", Revision::A)?;
+/// scr.init().await?;
+/// let upload = scr.clear();
+/// if tokio::time::timeout(Duration::from_secs(2), upload).await.is_err() {
+///     eprintln!("screen is not responding");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncSerialScreen<S = SerialStream> {
+    stream: S,
+    ops: Sender<Op>,
+    rx: UnboundedReceiver<PortRequest>,
+    current: Option<PortRequest>, // request in progress, kept when cancelled
+    written: usize,               // bytes of the current write request sent
+    pending: usize,               // operations started and not done
+    size: (usize, usize),
+    read_timeout: Duration,
+    _lock: Option<DeviceLock>, // held while a serial port is open
+}
+
+impl AsyncSerialScreen<SerialStream> {
+    /// Open a screen of the given revision on a serial port.
+    ///
+    /// The device is locked as by [`SerialPort`](crate::SerialPort). Must be
+    /// called from a tokio runtime with I/O enabled.
+    pub fn open(portname: &str, rev: Revision) -> Res<Self> {
+        let baud_rate = match rev {
+            Revision::A => screen_rev_a::BAUD_RATE,
+            Revision::B => screen_rev_b::BAUD_RATE,
+            Revision::C => screen_rev_c::BAUD_RATE,
+        };
+        log::debug!("create async screen rev {:?} on {}", rev, portname);
+        let lock = DeviceLock::acquire(portname, Duration::ZERO)?;
+        let stream = tokio_serial::new(portname, baud_rate).open_native_async()?;
+        let mut scr = Self::with_stream(stream, rev);
+        scr._lock = Some(lock);
+        Ok(scr)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> AsyncSerialScreen<S> {
+    /// Create a screen on an already opened stream.
+    pub fn with_stream(stream: S, rev: Revision) -> Self {
+        let (tx, rx) = unbounded_channel();
+        let port = Box::new(BridgePort { tx: tx.clone() });
+        let screen: Box<dyn Screen> = match rev {
+            Revision::A => Box::new(ScreenRevA::with_port(port)),
+            Revision::B => Box::new(ScreenRevB::with_port(port)),
+            Revision::C => Box::new(ScreenRevC::with_port(port)),
+        };
+        let size = screen.screen_size();
+        let (ops_tx, ops_rx) = mpsc::channel();
+        thread::spawn(move || run(screen, ops_rx, tx));
+        Self {
+            stream,
+            ops: ops_tx,
+            rx,
+            current: None,
+            written: 0,
+            pending: 0,
+            size,
+            read_timeout: READ_TIMEOUT,
+            _lock: None,
+        }
+    }
+
+    /// Set how long to wait for the device responses.
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    // Run an operation in the protocol thread, serving its port requests.
+    // Operations cancelled earlier are completed first.
+    async fn run_op(&mut self, op: Op) -> Res<()> {
+        self.ops.send(op).map_err(|_| stopped())?;
+        self.pending += 1;
+        loop {
+            if self.current.is_none() {
+                self.current = Some(self.rx.recv().await.ok_or_else(stopped)?);
+                self.written = 0;
+            }
+            // cancellation safe: the progress is kept in self until done
+            match &self.current {
+                Some(PortRequest::Write(data)) => {
+                    while self.written < data.len() {
+                        match self.stream.write(&data[self.written..]).await? {
+                            0 => return Err(Error::PartialWrite(self.written, data.len())),
+                            n => self.written += n,
+                        }
+                    }
+                }
+                Some(PortRequest::Flush) => self.stream.flush().await?,
+                Some(PortRequest::Read(n, reply)) => {
+                    let mut buf = vec![0u8; *n];
+                    let res =
+                        match tokio::time::timeout(self.read_timeout, self.stream.read(&mut buf))
+                            .await
+                        {
+                            Ok(Ok(0)) => Err(io::ErrorKind::UnexpectedEof.into()),
+                            Ok(Ok(n)) => {
+                                buf.truncate(n);
+                                Ok(buf)
+                            }
+                            Ok(Err(err)) => Err(err),
+                            Err(_) => Err(io::ErrorKind::TimedOut.into()),
+                        };
+                    let _ = reply.send(res);
+                }
+                Some(PortRequest::Done(..)) | None => {}
+            }
+            if let Some(PortRequest::Done(res, size)) = self.current.take() {
+                self.size = size;
+                self.pending -= 1;
+                if self.pending == 0 {
+                    return res;
+                }
+                if let Err(err) = res {
+                    log::debug!("cancelled screen operation failed: {}", err);
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> AsyncScreen for AsyncSerialScreen<S> {
+    fn screen_size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    async fn init(&mut self) -> Res<()> {
+        self.run_op(Box::new(|scr| scr.init())).await
+    }
+
+    async fn clear(&mut self) -> Res<()> {
+        self.run_op(Box::new(|scr| scr.clear())).await
+    }
+
+    async fn screen_on(&mut self) -> Res<()> {
+        self.run_op(Box::new(|scr| scr.screen_on())).await
+    }
+
+    async fn screen_off(&mut self) -> Res<()> {
+        self.run_op(Box::new(|scr| scr.screen_off())).await
+    }
+
+    async fn set_orientation(&mut self, o: Orientation) -> Res<()> {
+        self.run_op(Box::new(|scr| scr.set_orientation(o))).await
+    }
+
+    async fn set_brightness(&mut self, level: usize) -> Res<()> {
+        self.run_op(Box::new(move |scr| scr.set_brightness(level)))
+            .await
+    }
+
+    async fn display_image(&mut self, image: &Image, crop: &Rect, pos: &Coord) -> Res<()> {
+        let (image, crop, pos) = (image.clone(), crop.clone(), pos.clone());
+        self.run_op(Box::new(move |scr| scr.display_image(&image, &crop, &pos)))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+
+    use crate::{colors, EmulatorRevA};
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    // Feed the traffic of a stream to an emulator and send back its responses
+    async fn serve(mut stream: DuplexStream, mut emu: EmulatorRevA) -> io::Result<()> {
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            emu.write_all(&buf[..n])?;
+            let mut response = [0u8; 64];
+            while let Ok(n) = emu.read(&mut response) {
+                stream.write_all(&response[..n]).await?;
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_screen() -> Res<()> {
+        block_on(async {
+            let emu = EmulatorRevA::new();
+            let (host, device) = tokio::io::duplex(1024);
+            tokio::spawn(serve(device, emu.clone()));
+
+            let mut scr = AsyncSerialScreen::with_stream(host, Revision::A);
+            scr.init().await?;
+            scr.clear().await?;
+            scr.set_orientation(Orientation::Landscape).await?;
+            assert_eq!(scr.screen_size(), (480, 320));
+
+            let mut image = Image::new(2, 2);
+            image.buffer[3] = colors::WHITE;
+            scr.display_image(&image, &image.full(), &Coord::new(0, 0))
+                .await?;
+            // after a response, everything sent before has been processed
+            scr.init().await?;
+            assert_eq!(emu.orientation(), Orientation::Landscape);
+            assert_eq!(emu.snapshot().buffer[481], colors::WHITE);
+            scr.set_brightness(10).await?;
+            scr.init().await?;
+            assert_eq!(emu.brightness(), 10);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_async_screen_timeout() -> Res<()> {
+        block_on(async {
+            // a device that does not answer
+            let (host, _device) = tokio::io::duplex(1024);
+            let mut scr = AsyncSerialScreen::with_stream(host, Revision::A);
+            scr.set_read_timeout(Duration::from_millis(10));
+            assert!(matches!(scr.init().await, Err(Error::Timeout)));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_async_screen_cancel() -> Res<()> {
+        block_on(async {
+            // the device does not read, the upload stalls
+            let emu = EmulatorRevA::new();
+            let (host, device) = tokio::io::duplex(64);
+            let mut scr = AsyncSerialScreen::with_stream(host, Revision::A);
+            let mut image = Image::new(320, 480);
+            image.buffer.fill(colors::WHITE);
+            let res = tokio::time::timeout(
+                Duration::from_millis(10),
+                scr.display_image(&image, &image.full(), &Coord::new(0, 0)),
+            )
+            .await;
+            assert!(res.is_err());
+
+            // the cancelled upload completes before the next operation
+            tokio::spawn(serve(device, emu.clone()));
+            scr.init().await?;
+            assert_eq!(emu.snapshot().buffer[320 * 480 - 1], colors::WHITE);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,132 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Turing smart screen compositor daemon.
+//!
+//! Owns the screen and lets several programs share it. Clients connect to
+//! a Unix socket, create layers with a z-order and update their contents,
+//! the daemon blends the layers and sends the modified areas to the
+//! screen. The layers of a client are removed when it disconnects.
+//!
+//! Usage: turing-compositor [--socket PATH] [--port PORT] [--orientation O]
+//!                          [--brightness LEVEL]
+
+use std::fs;
+use std::io;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use turing_screen::{default_socket_path, serve_client, Compositor, Orientation, ScreenBuilder};
+
+struct Options {
+    socket: PathBuf,
+    port: Option<String>,
+    orientation: Option<Orientation>,
+    brightness: Option<usize>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: turing-compositor [--socket PATH] [--port PORT] [--orientation O] \
+         [--brightness LEVEL]"
+    );
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        socket: default_socket_path(),
+        port: None,
+        orientation: None,
+        brightness: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--socket" => opts.socket = PathBuf::from(value),
+            "--port" => opts.port = Some(value),
+            "--orientation" => match value.parse() {
+                Ok(o) => opts.orientation = Some(o),
+                Err(err) => {
+                    eprintln!("turing-compositor: {}", err);
+                    usage();
+                }
+            },
+            "--brightness" => match value.parse() {
+                Ok(level) => opts.brightness = Some(level),
+                Err(_) => usage(),
+            },
+            _ => usage(),
+        }
+    }
+    opts
+}
+
+// Bind the socket, replacing a stale socket file left by a previous daemon.
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("a compositor is already listening on {}", path.display()),
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+fn run(opts: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let mut builder = ScreenBuilder::new().init(true).clear(true);
+    if let Some(port) = &opts.port {
+        builder = builder.port(port);
+    }
+    if let Some(o) = &opts.orientation {
+        builder = builder.orientation(o.clone());
+    }
+    if let Some(level) = opts.brightness {
+        builder = builder.brightness(level);
+    }
+    let mut compositor = Compositor::new(builder.build()?);
+    compositor.composite()?;
+    let compositor = Arc::new(Mutex::new(compositor));
+
+    let listener = bind(&opts.socket)?;
+    println!("compositor listening on {}", opts.socket.display());
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::warn!("accept failed: {}", err);
+                continue;
+            }
+        };
+        let compositor = compositor.clone();
+        thread::spawn(move || {
+            log::info!("client connected");
+            match serve_client(stream, &compositor) {
+                Ok(()) => log::info!("client disconnected"),
+                Err(err) => log::warn!("client error: {}", err),
+            }
+        });
+    }
+    Ok(())
+}
+
+fn main() {
+    simple_logger::SimpleLogger::new()
+        .with_level(log::LevelFilter::Warn)
+        .env()
+        .init()
+        .unwrap();
+
+    let opts = parse_args();
+    if let Err(err) = run(&opts) {
+        eprintln!("turing-compositor: {}", err);
+        process::exit(1);
+    }
+}
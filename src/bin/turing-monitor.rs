@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Turing smart screen system monitor.
+//!
+//! Renders the widgets described in a TOML configuration file, such as
+//! clocks, values read from files or commands, bars and images, each one
+//! refreshed at its own interval. When the device is disconnected, the
+//! monitor waits for it and redraws the screen.
+//!
+//! Themes of turing-smart-screen-python (theme.yaml files) are converted on
+//! the fly, with a warning for each unsupported part. `--print-config`
+//! prints the resulting configuration instead of running it.
+//!
+//! Usage: turing-monitor [--port PORT] [--print-config] CONFIG|THEME
+
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use turing_screen::{Error, Monitor, MonitorConfig};
+
+const REOPEN_DELAY: Duration = Duration::from_secs(5);
+
+fn usage() -> ! {
+    eprintln!("usage: turing-monitor [--port PORT] [--print-config] CONFIG|THEME");
+    process::exit(2);
+}
+
+fn load(path: &str) -> Result<MonitorConfig, Error> {
+    let ext = Path::new(path).extension().and_then(|e| e.to_str());
+    if !matches!(ext, Some("yaml" | "yml")) {
+        return MonitorConfig::load(path);
+    }
+    let (config, warnings) = MonitorConfig::load_theme(path)?;
+    for warning in warnings {
+        log::warn!("{}: {}", path, warning);
+    }
+    Ok(config)
+}
+
+fn run(port: Option<&str>, print: bool, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let config = load(path).map_err(|err| format!("{}: {}", path, err))?;
+    if print {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+    let mut builder = config.screen_builder()?;
+    if let Some(port) = port {
+        builder = builder.port(port);
+    }
+    let mut monitor = Monitor::new(&config, builder.clone().build()?)?;
+
+    loop {
+        if let Err(err) = monitor.run() {
+            log::warn!("screen error: {}, reopening the device", err);
+        }
+        // release the device lock
+        drop(monitor.take_screen());
+        loop {
+            thread::sleep(REOPEN_DELAY);
+            match builder.clone().build() {
+                Ok(screen) => break monitor.set_screen(screen),
+                Err(err) => log::warn!("cannot reopen the device: {}", err),
+            }
+        }
+    }
+}
+
+fn main() {
+    simple_logger::SimpleLogger::new()
+        .with_level(log::LevelFilter::Warn)
+        .env()
+        .init()
+        .unwrap();
+
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let print = args.iter().any(|a| a == "--print-config");
+    args.retain(|a| a != "--print-config");
+    let (port, path) = match args.as_slice() {
+        [path] if !path.starts_with("--") => (None, path),
+        [opt, port, path] if opt == "--port" => (Some(port.as_str()), path),
+        _ => usage(),
+    };
+    if let Err(err) = run(port, print, path) {
+        eprintln!("turing-monitor: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,276 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Turing smart screen rev A simulator.
+//!
+//! Creates a pseudo-terminal that behaves like a rev A screen, so programs
+//! can open it as a serial port. The screen contents are saved as PNG
+//! snapshots and the decoded commands are written to a log file.
+//!
+//! Usage: turing-sim [--output DIR] [--interval SECONDS] [--link PATH]
+
+use std::ffi::CStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::os::unix::fs::symlink;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use turing_screen::EmulatorRevA;
+
+struct Options {
+    output: PathBuf,
+    interval: Duration,
+    link: Option<PathBuf>,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: turing-sim [--output DIR] [--interval SECONDS] [--link PATH]");
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        output: PathBuf::from("."),
+        interval: Duration::from_secs(1),
+        link: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--output" => opts.output = PathBuf::from(value),
+            "--interval" => match value.parse::<f64>() {
+                Ok(secs) if secs > 0.0 => opts.interval = Duration::from_secs_f64(secs),
+                _ => usage(),
+            },
+            "--link" => opts.link = Some(PathBuf::from(value)),
+            _ => usage(),
+        }
+    }
+    opts
+}
+
+fn last_os_error<T>() -> io::Result<T> {
+    Err(io::Error::last_os_error())
+}
+
+// Create a pseudo-terminal in raw mode, return the master side and the
+// path of the slave device.
+fn open_pty() -> io::Result<(File, String)> {
+    unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if fd < 0 {
+            return last_os_error();
+        }
+        let master = File::from_raw_fd(fd);
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return last_os_error();
+        }
+        let mut name = [0 as libc::c_char; 128];
+        if libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0 {
+            return last_os_error();
+        }
+        let path = CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+
+        // The terminal settings are kept while the master is open, disable
+        // echo and line editing before any client opens the device.
+        let slave = OpenOptions::new().read(true).write(true).open(&path)?;
+        let mut tio: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(slave.as_raw_fd(), &mut tio) != 0 {
+            return last_os_error();
+        }
+        libc::cfmakeraw(&mut tio);
+        if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &tio) != 0 {
+            return last_os_error();
+        }
+
+        Ok((master, path))
+    }
+}
+
+// Wait until the master side has data to read. Returns false on timeout
+// or when no client has the device open.
+fn wait_readable(master: &File, timeout: Duration) -> io::Result<bool> {
+    let mut pfd = libc::pollfd {
+        fd: master.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let res = unsafe { libc::poll(&mut pfd, 1, timeout.as_millis() as libc::c_int) };
+    if res < 0 {
+        let err = io::Error::last_os_error();
+        return match err.kind() {
+            io::ErrorKind::Interrupted => Ok(false),
+            _ => Err(err),
+        };
+    }
+    if pfd.revents & libc::POLLIN != 0 {
+        return Ok(true);
+    }
+    if pfd.revents & libc::POLLHUP != 0 {
+        // no client connected, avoid spinning on the hangup condition
+        thread::sleep(timeout);
+    }
+    Ok(false)
+}
+
+// Point the link to the pty. A link left by a previous run is replaced,
+// anything else at that path is kept.
+fn link_pty(path: &str, link: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(link) {
+        Ok(meta) if meta.file_type().is_symlink() => fs::remove_file(link)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a symlink", link.display()),
+            ))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    symlink(path, link)
+}
+
+// Pass the data sent by the client to the emulator and send back its
+// responses.
+fn relay(master: &mut File, emu: &mut EmulatorRevA, buf: &mut [u8]) -> io::Result<()> {
+    match master.read(buf) {
+        Ok(n) => emu.write_all(&buf[..n])?,
+        // EIO when the client closes the device
+        Err(err) if err.raw_os_error() == Some(libc::EIO) => {}
+        Err(err) => return Err(err),
+    }
+
+    // the emulator times out when there is no response pending
+    let mut response = [0u8; 64];
+    while let Ok(n) = emu.read(&mut response) {
+        master.write_all(&response[..n])?;
+    }
+    Ok(())
+}
+
+fn run(opts: &Options) -> io::Result<()> {
+    fs::create_dir_all(&opts.output)?;
+    let mut log = BufWriter::new(File::create(opts.output.join("commands.log"))?);
+
+    let (mut master, path) = open_pty()?;
+    if let Some(link) = &opts.link {
+        link_pty(&path, link)?;
+    }
+    println!("rev A screen simulator listening on {}", path);
+
+    let mut emu = EmulatorRevA::new();
+    let start = Instant::now();
+    let mut last_snapshot = Instant::now();
+    let mut changed = false;
+    let mut count = 0;
+    let mut buf = vec![0u8; 64 * 1024];
+
+    loop {
+        if wait_readable(&master, opts.interval)? {
+            relay(&mut master, &mut emu, &mut buf)?;
+
+            let elapsed = start.elapsed().as_secs_f64();
+            for cmd in emu.take_commands() {
+                writeln!(log, "{:10.3} {}", elapsed, cmd)?;
+                changed = true;
+            }
+        }
+
+        if changed && last_snapshot.elapsed() >= opts.interval {
+            log.flush()?;
+            count += 1;
+            let file = opts.output.join(format!("snapshot-{:04}.png", count));
+            if let Err(err) = emu.save_png(&file) {
+                eprintln!("cannot save {}: {}", file.display(), err);
+            }
+            last_snapshot = Instant::now();
+            changed = false;
+        }
+    }
+}
+
+fn main() {
+    simple_logger::SimpleLogger::new()
+        .with_level(log::LevelFilter::Warn)
+        .env()
+        .init()
+        .unwrap();
+
+    let opts = parse_args();
+    if let Err(err) = run(&opts) {
+        eprintln!("turing-sim: {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use turing_screen::{Coord, DecodedCommand, Image, Rect, Screen, ScreenRevA};
+
+    #[test]
+    fn test_link_pty() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("turing-sim-{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let link = dir.join("ttySIM");
+
+        link_pty("/dev/pts/1", &link)?;
+        link_pty("/dev/pts/2", &link)?;
+        assert_eq!(fs::read_link(&link)?, Path::new("/dev/pts/2"));
+
+        let file = dir.join("file");
+        fs::write(&file, "data")?;
+        let err = link_pty("/dev/pts/3", &file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&file)?, "data");
+
+        fs::remove_dir_all(dir)
+    }
+
+    #[test]
+    fn test_screen_on_pty() -> io::Result<()> {
+        let (mut master, path) = open_pty()?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || -> io::Result<()> {
+            let mut emu = EmulatorRevA::new();
+            let mut buf = vec![0u8; 64 * 1024];
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(10) {
+                if wait_readable(&master, Duration::from_millis(100))? {
+                    relay(&mut master, &mut emu, &mut buf)?;
+                    for cmd in emu.take_commands() {
+                        let _ = tx.send(cmd);
+                    }
+                }
+            }
+            Ok(())
+        });
+
+        let mut scr = ScreenRevA::new(&path).unwrap();
+        scr.init().unwrap();
+        let image = Image::new(4, 2);
+        scr.display_image(&image, &image.full(), &Coord::new(10, 20))
+            .unwrap();
+
+        let timeout = Duration::from_secs(5);
+        let mut commands = Vec::new();
+        while let Ok(cmd) = rx.recv_timeout(timeout) {
+            let done = matches!(cmd, DecodedCommand::DisplayBitmap(_));
+            commands.push(cmd);
+            if done {
+                break;
+            }
+        }
+        assert_eq!(commands.first(), Some(&DecodedCommand::Hello));
+        assert_eq!(
+            commands.last(),
+            Some(&DecodedCommand::DisplayBitmap(Rect::new(10, 20, 4, 2)))
+        );
+        Ok(())
+    }
+}
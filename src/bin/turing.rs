@@ -0,0 +1,265 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Turing smart screen control tool.
+//!
+//! Sends one command to the screen, such as setting the brightness or
+//! displaying an image or a line of text. Run without arguments for the
+//! list of commands.
+//!
+//! Usage: turing [--port PORT] [--revision A|B|C] [--orientation NAME] COMMAND
+
+use std::fs;
+use std::process;
+
+use turing_screen::{colors, list_devices, Coord, Font, Image, Rgba};
+use turing_screen::{Orientation, Revision, Screen, ScreenBuilder};
+
+const USAGE: &str = "\
+usage: turing [--port PORT] [--revision A|B|C] [--orientation NAME] COMMAND
+
+commands:
+  list                          list the attached USB serial devices
+  info                          show the screen model and size
+  clear                         clear the screen
+  on, off                       turn the screen on or off
+  brightness LEVEL              set the brightness, from 0 to 255
+  orientation NAME              portrait, landscape, reverse-portrait or
+                                reverse-landscape
+  show IMAGE [--at X,Y] [--fit] display a PNG image, --fit scales it to the
+                                screen
+  text MSG --font FILE [--size N] [--color HEX] [--background HEX] [--at X,Y]
+                                draw a line of text";
+
+type CliResult = Result<(), Box<dyn std::error::Error>>;
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn fail(msg: String) -> ! {
+    eprintln!("turing: {}", msg);
+    process::exit(2);
+}
+
+fn parse<T: std::str::FromStr>(what: &str, value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| fail(format!("invalid {} '{}'", what, value)))
+}
+
+fn parse_coord(value: &str) -> Coord {
+    match value.split_once(',') {
+        Some((x, y)) => Coord::new(parse("x coordinate", x), parse("y coordinate", y)),
+        None => fail(format!("invalid position '{}', expected X,Y", value)),
+    }
+}
+
+fn parse_color(value: &str) -> Rgba {
+    colors::from_hex(value).unwrap_or_else(|| fail(format!("invalid color '{}'", value)))
+}
+
+// Command arguments, split in positional arguments and options
+struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+    // Options listed in `flags` have no value.
+    fn parse(args: &[String], flags: &[&str]) -> Self {
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                positional.push(arg.clone());
+            } else if flags.contains(&arg.as_str()) {
+                options.push((arg.clone(), None));
+            } else {
+                let value = iter.next().unwrap_or_else(|| usage());
+                options.push((arg.clone(), Some(value.clone())));
+            }
+        }
+        Self {
+            positional,
+            options,
+        }
+    }
+
+    fn check(&self, positional: usize, known: &[&str]) {
+        if self.positional.len() != positional {
+            usage();
+        }
+        if let Some((name, _)) = self
+            .options
+            .iter()
+            .find(|(n, _)| !known.contains(&n.as_str()))
+        {
+            fail(format!("unknown option {}", name));
+        }
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, v)| v.as_deref())
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(n, _)| n == name)
+    }
+}
+
+fn list() -> CliResult {
+    let devices = list_devices()?;
+    if devices.is_empty() {
+        println!("no USB serial devices attached");
+    }
+    for d in devices {
+        println!(
+            "{}  {} {}",
+            d,
+            d.manufacturer.as_deref().unwrap_or(""),
+            d.product.as_deref().unwrap_or("")
+        );
+    }
+    Ok(())
+}
+
+fn show(scr: &mut dyn Screen, args: &Args) -> CliResult {
+    let pos = args.value("--at").map_or(Coord::new(0, 0), parse_coord);
+    let path = &args.positional[0];
+    let mut image = Image::load_png(path).map_err(|err| format!("{}: {}", path, err))?;
+
+    let (width, height) = scr.screen_size();
+    if pos.x >= width || pos.y >= height {
+        fail(format!(
+            "position {} is outside the {}x{} screen",
+            pos, width, height
+        ));
+    }
+    if args.flag("--fit") {
+        // the largest size keeping the aspect ratio
+        let (w, h) = (width - pos.x, height - pos.y);
+        let (w, h) = if w * image.height <= h * image.width {
+            (w, (w * image.height / image.width).max(1))
+        } else {
+            ((h * image.width / image.height).max(1), h)
+        };
+        image = image.scale(w, h);
+    }
+    scr.display_image(&image, &image.full(), &pos)?;
+    Ok(())
+}
+
+fn text(scr: &mut dyn Screen, args: &Args) -> CliResult {
+    let path = args
+        .value("--font")
+        .unwrap_or_else(|| fail("missing --font".to_string()));
+    let data = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+    let font = Font::from_data(data)?;
+    let size: f32 = args.value("--size").map_or(24.0, |v| parse("size", v));
+    let color = args.value("--color").map_or(colors::WHITE, parse_color);
+    let background = args
+        .value("--background")
+        .map_or(colors::BLACK, parse_color);
+    let pos = args.value("--at").map_or(Coord::new(0, 0), parse_coord);
+
+    let (width, height) = scr.screen_size();
+    let mut bg = Image::new(width, height);
+    bg.buffer.fill(background);
+    let (image, crop) = font.draw(&bg, size, color, &pos, &args.positional[0]);
+    // the glyph bounding box may extend below the text image
+    let crop = crop.clip(image.width, image.height);
+    scr.display_image(&image, &crop, &pos)?;
+    Ok(())
+}
+
+fn run(args: &[String]) -> CliResult {
+    // global options, before the command
+    let mut builder = ScreenBuilder::new().init(true);
+    let mut i = 0;
+    while i < args.len() && args[i].starts_with("--") {
+        let value = args.get(i + 1).unwrap_or_else(|| usage());
+        builder = match args[i].as_str() {
+            "--port" => builder.port(value),
+            "--revision" => builder.revision(match value.to_ascii_uppercase().as_str() {
+                "A" => Revision::A,
+                "B" => Revision::B,
+                "C" => Revision::C,
+                _ => fail(format!("unknown revision '{}'", value)),
+            }),
+            "--orientation" => builder.orientation(parse_orientation(value)),
+            _ => usage(),
+        };
+        i += 2;
+    }
+    let Some(command) = args.get(i) else { usage() };
+    let args = match command.as_str() {
+        "show" => Args::parse(&args[i + 1..], &["--fit"]),
+        _ => Args::parse(&args[i + 1..], &[]),
+    };
+
+    if command == "list" {
+        args.check(0, &[]);
+        return list();
+    }
+
+    // check the arguments before opening the screen
+    let (positional, options): (usize, &[&str]) = match command.as_str() {
+        "info" | "clear" | "on" | "off" => (0, &[]),
+        "brightness" | "orientation" => (1, &[]),
+        "show" => (1, &["--at", "--fit"]),
+        "text" => (1, &["--font", "--size", "--color", "--background", "--at"]),
+        _ => usage(),
+    };
+    args.check(positional, options);
+    let level: Option<usize> = match command.as_str() {
+        "brightness" => Some(parse("brightness", &args.positional[0])),
+        _ => None,
+    };
+    if level.is_some_and(|level| level > 255) {
+        fail("brightness must be between 0 and 255".to_string());
+    }
+    if command == "orientation" {
+        builder = builder.orientation(parse_orientation(&args.positional[0]));
+    }
+
+    let mut scr = builder.build()?;
+    match command.as_str() {
+        "info" => {
+            let (width, height) = scr.screen_size();
+            println!("{}, {}x{}", scr.model(), width, height);
+        }
+        "clear" => scr.clear()?,
+        "on" => scr.screen_on()?,
+        "off" => scr.screen_off()?,
+        "brightness" => scr.set_brightness(level.unwrap_or_default())?,
+        "orientation" => {}
+        "show" => show(scr.as_mut(), &args)?,
+        "text" => text(scr.as_mut(), &args)?,
+        _ => usage(),
+    }
+    Ok(())
+}
+
+fn parse_orientation(value: &str) -> Orientation {
+    value.parse().unwrap_or_else(|err: String| fail(err))
+}
+
+fn main() {
+    simple_logger::SimpleLogger::new()
+        .with_level(log::LevelFilter::Warn)
+        .env()
+        .init()
+        .unwrap();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        eprintln!("turing: {}", err);
+        process::exit(1);
+    }
+}
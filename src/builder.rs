@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::path::Path;
+use std::time::Duration;
+
+use crate::detect::{self, PortSelection};
+use crate::serial_port::PortOptions;
+use crate::{Orientation, Res, Revision, Screen};
+
+/// Builder for screens with custom transport and startup options.
+///
+/// By default the screen is searched among all attached devices, using
+/// the baud rate of the detected revision and 1 second timeouts, and no
+/// command is sent to the device.
+///
+/// ```no_run
+/// use turing_screen::{Orientation, ScreenBuilder};
+///
+/// let scr = ScreenBuilder::new()
+///     .serial_number("USB35INCHIPSV2")
+///     .orientation(Orientation::Landscape)
+///     .brightness(128)
+///     .init(true)
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ScreenBuilder {
+    port: PortSelection,
+    revision: Option<Revision>,
+    opts: PortOptions,
+    orientation: Option<Orientation>,
+    brightness: Option<usize>,
+    init: bool,
+    clear: bool,
+}
+
+impl Default for ScreenBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScreenBuilder {
+    pub fn new() -> Self {
+        Self {
+            port: PortSelection::Auto,
+            revision: None,
+            opts: PortOptions::default(),
+            orientation: None,
+            brightness: None,
+            init: false,
+            clear: false,
+        }
+    }
+
+    /// Use the serial port at the given path.
+    pub fn port(mut self, path: &str) -> Self {
+        self.port = PortSelection::Path(path.to_string());
+        self
+    }
+
+    /// Use the device with the given USB serial number.
+    pub fn serial_number(mut self, serial: &str) -> Self {
+        self.port = PortSelection::Serial(serial.to_string());
+        self
+    }
+
+    /// Search all attached devices for a supported screen.
+    pub fn auto(mut self) -> Self {
+        self.port = PortSelection::Auto;
+        self
+    }
+
+    /// Force the screen revision instead of detecting it.
+    pub fn revision(mut self, rev: Revision) -> Self {
+        self.revision = Some(rev);
+        self
+    }
+
+    /// Override the baud rate of the screen revision.
+    pub fn baud_rate(mut self, baud_rate: u32) -> Self {
+        self.opts.baud_rate = Some(baud_rate);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.opts.read_timeout = timeout;
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.opts.write_timeout = timeout;
+        self
+    }
+
+    /// Wait for another process using the device to release it, instead
+    /// of failing with [`Error::DeviceBusy`](crate::Error::DeviceBusy).
+    pub fn lock_wait(mut self, timeout: Duration) -> Self {
+        self.opts.lock_wait = timeout;
+        self
+    }
+
+    /// Record all the traffic with the device to a trace file.
+    pub fn trace<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.opts.trace = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the orientation after opening the screen.
+    pub fn orientation(mut self, o: Orientation) -> Self {
+        self.orientation = Some(o);
+        self
+    }
+
+    /// Set the brightness after opening the screen.
+    pub fn brightness(mut self, level: usize) -> Self {
+        self.brightness = Some(level);
+        self
+    }
+
+    /// Send the hello command after opening the screen.
+    pub fn init(mut self, enable: bool) -> Self {
+        self.init = enable;
+        self
+    }
+
+    /// Clear the screen after opening it.
+    pub fn clear(mut self, enable: bool) -> Self {
+        self.clear = enable;
+        self
+    }
+
+    /// Open the screen and apply the startup options.
+    pub fn build(self) -> Res<Box<dyn Screen>> {
+        let mut scr = detect::open_with(&self.port, self.revision.as_ref(), &self.opts)?;
+        self.setup(scr.as_mut())?;
+        Ok(scr)
+    }
+
+    // The screen is cleared before setting the orientation, as some
+    // revisions reset it to portrait when clearing.
+    fn setup(&self, scr: &mut dyn Screen) -> Res<()> {
+        if self.init {
+            scr.init()?;
+        }
+        if self.clear {
+            scr.clear()?;
+        }
+        if let Some(o) = &self.orientation {
+            scr.set_orientation(o.clone())?;
+        }
+        if let Some(level) = self.brightness {
+            scr.set_brightness(level)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::FakePort;
+    use crate::ScreenRevA;
+
+    #[test]
+    fn test_builder() {
+        let b = ScreenBuilder::new()
+            .serial_number("USB35INCHIPSV2")
+            .revision(Revision::B)
+            .baud_rate(9600)
+            .read_timeout(Duration::from_millis(100))
+            .write_timeout(Duration::from_millis(200))
+            .lock_wait(Duration::from_secs(5))
+            .brightness(10)
+            .init(true);
+        assert_eq!(b.port, PortSelection::Serial("USB35INCHIPSV2".to_string()));
+        assert_eq!(b.revision, Some(Revision::B));
+        assert_eq!(
+            b.opts,
+            PortOptions {
+                baud_rate: Some(9600),
+                read_timeout: Duration::from_millis(100),
+                write_timeout: Duration::from_millis(200),
+                trace: None,
+                lock_wait: Duration::from_secs(5),
+            }
+        );
+        assert_eq!(b.brightness, Some(10));
+        assert!(b.init);
+        assert!(!b.clear);
+
+        let b = b.trace("/tmp/screen.trace");
+        assert_eq!(b.opts.trace, Some("/tmp/screen.trace".into()));
+
+        let b = b.port("/dev/ttyACM0");
+        assert_eq!(b.port, PortSelection::Path("/dev/ttyACM0".to_string()));
+        let b = b.auto();
+        assert_eq!(b.port, PortSelection::Auto);
+    }
+
+    #[test]
+    fn test_setup() -> Res<()> {
+        let b = ScreenBuilder::new()
+            .clear(true)
+            .orientation(Orientation::Landscape)
+            .brightness(0xff);
+        let mut scr = ScreenRevA::with_port(Box::new(FakePort::new(Vec::new())));
+        b.setup(&mut scr)?;
+        assert_eq!(scr.screen_size(), (480, 320));
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::damage::Damage;
+use crate::{Coord, Font, Image, Rect, Res, Rgba};
+use crate::{Orientation, Screen};
+
+// Cost in pixels of an additional display_image call. The backends only
+// send the pixels that changed, so large areas are cheap.
+const COMMIT_OVERHEAD: usize = 4096;
+
+/// An off-screen drawing surface for a screen.
+///
+/// The canvas keeps a full-screen image and records the areas modified by
+/// drawing operations. [`Canvas::commit`] sends the modified areas to the
+/// screen.
+///
+/// ```no_run
+/// use turing_screen::{colors, Canvas, Coord, Rect};
+///
+/// let mut canvas = Canvas::new(turing_screen::new("AUTO").unwrap());
+/// canvas.fill(colors::BLACK);
+/// canvas.fill_rect(&Rect::new(10, 10, 100, 20), colors::WHITE);
+/// canvas.commit().unwrap();
+/// ```
+pub struct Canvas {
+    screen: Box<dyn Screen>,
+    image: Image,
+    damage: Damage,
+}
+
+impl Canvas {
+    /// Create a black canvas with the size of the screen.
+    pub fn new(screen: Box<dyn Screen>) -> Self {
+        let (width, height) = screen.screen_size();
+        let image = Image::new(width, height);
+        let mut damage = Damage::new(&image.full());
+        damage.add_rect(&image.full());
+        Self {
+            screen,
+            image,
+            damage,
+        }
+    }
+
+    pub fn screen(&self) -> &dyn Screen {
+        self.screen.as_ref()
+    }
+
+    /// The screen, for operations other than drawing.
+    ///
+    /// If the screen size changes, the canvas is cleared on the next commit.
+    pub fn screen_mut(&mut self) -> &mut dyn Screen {
+        self.screen.as_mut()
+    }
+
+    pub fn into_screen(self) -> Box<dyn Screen> {
+        self.screen
+    }
+
+    /// The canvas contents.
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.image.width, self.image.height)
+    }
+
+    /// Set the screen orientation, reallocating the canvas if the screen
+    /// size changes.
+    pub fn set_orientation(&mut self, o: Orientation) -> Res<()> {
+        self.screen.set_orientation(o)?;
+        self.resize();
+        Ok(())
+    }
+
+    // Follow the screen size, the contents are lost when it changes.
+    fn resize(&mut self) {
+        let (width, height) = self.screen.screen_size();
+        if (width, height) != self.size() {
+            log::debug!("resize canvas to {}x{}", width, height);
+            self.image = Image::new(width, height);
+            self.damage = Damage::new(&self.image.full());
+            self.damage.add_rect(&self.image.full());
+        }
+    }
+
+    /// Whether there are modified areas not sent to the screen.
+    pub fn is_dirty(&self) -> bool {
+        !self.damage.is_empty()
+    }
+
+    /// Mark an area as modified.
+    pub fn damage(&mut self, r: &Rect) {
+        self.damage.add_rect(r);
+    }
+
+    pub fn fill(&mut self, color: Rgba) {
+        self.image.buffer.fill(color);
+        self.damage.add_rect(&self.image.full());
+    }
+
+    pub fn fill_rect(&mut self, r: &Rect, color: Rgba) {
+        let r = r.clip(self.image.width, self.image.height);
+        for y in r.y..r.y + r.h {
+            let ofs = y * self.image.width + r.x;
+            self.image.buffer[ofs..ofs + r.w].fill(color);
+        }
+        self.damage.add_rect(&r);
+    }
+
+    /// Copy the cropped area of an image to the canvas.
+    pub fn draw_image(&mut self, image: &Image, crop: &Rect, pos: &Coord) {
+        if let Some(r) = self.clip(crop, pos) {
+            self.image.copy_image(image, &r, pos);
+            self.damage.add_rect(&Rect::new(pos.x, pos.y, r.w, r.h));
+        }
+    }
+
+    /// Alpha blend the cropped area of an image on the canvas.
+    pub fn blend_image(&mut self, image: &Image, crop: &Rect, pos: &Coord) {
+        if let Some(r) = self.clip(crop, pos) {
+            self.image.blend_image(image, &r, pos);
+            self.damage.add_rect(&Rect::new(pos.x, pos.y, r.w, r.h));
+        }
+    }
+
+    /// Draw text on the canvas, returning the modified area.
+    pub fn draw_text(
+        &mut self,
+        font: &Font,
+        size: f32,
+        color: Rgba,
+        pos: &Coord,
+        msg: &str,
+    ) -> Rect {
+        let (text, crop) = font.draw(&self.image, size, color, pos, msg);
+        self.draw_image(&text, &crop, pos);
+        Rect::new(pos.x, pos.y, crop.w, crop.h).clip(self.image.width, self.image.height)
+    }
+
+    // The part of the crop area that fits in the canvas at the given position.
+    fn clip(&self, crop: &Rect, pos: &Coord) -> Option<Rect> {
+        if pos.x >= self.image.width || pos.y >= self.image.height {
+            return None;
+        }
+        let r = crop.clip(
+            crop.x + self.image.width - pos.x,
+            crop.y + self.image.height - pos.y,
+        );
+        (r.w > 0 && r.h > 0).then_some(r)
+    }
+
+    /// Send the modified areas to the screen.
+    pub fn commit(&mut self) -> Res<()> {
+        self.resize();
+        for r in self.damage.rects(COMMIT_OVERHEAD) {
+            self.screen
+                .display_image(&self.image, &r, &Coord::new(r.x, r.y))?;
+        }
+        self.damage.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{colors, VirtualScreen};
+    use std::sync::{Arc, Mutex};
+
+    // A virtual screen that logs the displayed areas
+    struct LoggingScreen {
+        screen: VirtualScreen,
+        log: Arc<Mutex<Vec<Rect>>>,
+    }
+
+    impl Screen for LoggingScreen {
+        fn screen_size(&self) -> (usize, usize) {
+            self.screen.screen_size()
+        }
+        fn write(&mut self, data: &[u8]) -> Res<usize> {
+            self.screen.write(data)
+        }
+        fn read(&mut self, n: usize) -> Res<Vec<u8>> {
+            self.screen.read(n)
+        }
+        fn init(&mut self) -> Res<()> {
+            self.screen.init()
+        }
+        fn clear(&mut self) -> Res<()> {
+            self.screen.clear()
+        }
+        fn screen_on(&mut self) -> Res<()> {
+            self.screen.screen_on()
+        }
+        fn screen_off(&mut self) -> Res<()> {
+            self.screen.screen_off()
+        }
+        fn set_orientation(&mut self, o: Orientation) -> Res<()> {
+            self.screen.set_orientation(o)
+        }
+        fn set_brightness(&mut self, level: usize) -> Res<()> {
+            self.screen.set_brightness(level)
+        }
+        fn display_image(&mut self, image: &Image, crop: &Rect, pos: &Coord) -> Res<()> {
+            self.log.lock().unwrap().push(crop.clone());
+            self.screen.display_image(image, crop, pos)
+        }
+        fn framebuffer(&self) -> &[u8] {
+            self.screen.framebuffer()
+        }
+        fn restore_framebuffer(&mut self, fb: &[u8]) -> Res<()> {
+            self.screen.restore_framebuffer(fb)
+        }
+    }
+
+    fn logging_canvas() -> (Canvas, Arc<Mutex<Vec<Rect>>>) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let screen = LoggingScreen {
+            screen: VirtualScreen::new(320, 480),
+            log: log.clone(),
+        };
+        (Canvas::new(Box::new(screen)), log)
+    }
+
+    #[test]
+    fn test_commit() -> Res<()> {
+        let (mut canvas, log) = logging_canvas();
+        assert_eq!(canvas.size(), (320, 480));
+        canvas.commit()?;
+        assert_eq!(*log.lock().unwrap(), [Rect::new(0, 0, 320, 480)]);
+
+        // nothing changed
+        assert!(!canvas.is_dirty());
+        canvas.commit()?;
+        assert_eq!(log.lock().unwrap().len(), 1);
+
+        canvas.fill_rect(&Rect::new(10, 10, 5, 5), colors::WHITE);
+        canvas.fill_rect(&Rect::new(300, 470, 50, 50), colors::WHITE);
+        canvas.commit()?;
+        assert_eq!(
+            log.lock().unwrap()[1..],
+            [Rect::new(10, 10, 5, 5), Rect::new(300, 470, 20, 10)]
+        );
+        assert_eq!(canvas.image().buffer[10 * 320 + 10], colors::WHITE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_draw_image() -> Res<()> {
+        let (mut canvas, log) = logging_canvas();
+        canvas.commit()?;
+
+        let mut image = Image::new(4, 4);
+        image.buffer.fill(colors::WHITE);
+        canvas.draw_image(&image, &Rect::new(1, 1, 3, 3), &Coord::new(318, 0));
+        canvas.blend_image(&image, &image.full(), &Coord::new(400, 0));
+        canvas.commit()?;
+        assert_eq!(log.lock().unwrap()[1..], [Rect::new(318, 0, 2, 3)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_orientation() -> Res<()> {
+        let (mut canvas, log) = logging_canvas();
+        canvas.commit()?;
+        canvas.set_orientation(Orientation::Landscape)?;
+        assert_eq!(canvas.size(), (480, 320));
+        canvas.commit()?;
+        assert_eq!(log.lock().unwrap()[1..], [Rect::new(0, 0, 480, 320)]);
+
+        // orientation changed directly on the screen
+        canvas.screen_mut().set_orientation(Orientation::Portrait)?;
+        canvas.commit()?;
+        assert_eq!(canvas.size(), (320, 480));
+        assert_eq!(log.lock().unwrap()[2..], [Rect::new(0, 0, 320, 480)]);
+        Ok(())
+    }
+}
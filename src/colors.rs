@@ -5,3 +5,31 @@ pub type Rgba = rgb::RGBA<u8>;
 pub const BLACK: Rgba = Rgba::new(0, 0, 0, 255);
 pub const WHITE: Rgba = Rgba::new(255, 255, 255, 255);
 pub const TRANSPARENT: Rgba = Rgba::new(0, 0, 0, 0);
+
+/// Parse a hex color, "#rrggbb" or "#rrggbbaa", the "#" is optional.
+pub fn from_hex(s: &str) -> Option<Rgba> {
+    let s = s.strip_prefix('#').unwrap_or(s);
+    if !(s.len() == 6 || s.len() == 8) || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(s.get(i..i + 2)?, 16).ok();
+    let a = match s.len() {
+        8 => channel(6)?,
+        _ => 255,
+    };
+    Some(Rgba::new(channel(0)?, channel(2)?, channel(4)?, a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_hex() {
+        assert_eq!(from_hex("#ff8000"), Some(Rgba::new(255, 128, 0, 255)));
+        assert_eq!(from_hex("00ff0080"), Some(Rgba::new(0, 255, 0, 128)));
+        assert_eq!(from_hex("#fff"), None);
+        assert_eq!(from_hex("#gg0000"), None);
+        assert_eq!(from_hex("#+f0000"), None);
+    }
+}
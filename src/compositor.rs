@@ -0,0 +1,559 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashSet;
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::colors;
+use crate::damage::Damage;
+use crate::{Canvas, Coord, Error, Image, Rect, Res, Rgba, Screen};
+
+// Cost in pixels of compositing another rectangle
+const COMPOSITE_OVERHEAD: usize = 4096;
+
+/// Identifier of a compositor layer.
+pub type LayerId = u32;
+
+struct Layer {
+    id: LayerId,
+    pos: Coord,
+    image: Image,
+    z: i32,
+}
+
+impl Layer {
+    fn area(&self) -> Rect {
+        Rect::new(self.pos.x, self.pos.y, self.image.width, self.image.height)
+    }
+}
+
+fn intersect(a: &Rect, b: &Rect) -> Option<Rect> {
+    let x0 = a.x.max(b.x);
+    let y0 = a.y.max(b.y);
+    let x1 = (a.x + a.w).min(b.x + b.w);
+    let y1 = (a.y + a.h).min(b.y + b.h);
+    (x0 < x1 && y0 < y1).then(|| Rect::new(x0, y0, x1 - x0, y1 - y0))
+}
+
+fn unknown_layer(id: LayerId) -> Error {
+    Error::Protocol(format!("unknown layer {}", id))
+}
+
+/// Layers of images stacked on a screen.
+///
+/// Each layer covers an area of the screen and is drawn over the layers
+/// with a lower z-order, using the alpha channel of its pixels. The
+/// areas modified since the last [`Compositor::composite`] are redrawn
+/// and sent to the screen.
+pub struct Compositor {
+    canvas: Canvas,
+    background: Rgba,
+    layers: Vec<Layer>, // sorted by z-order, then creation
+    damage: Damage,
+    next_id: LayerId,
+}
+
+impl Compositor {
+    pub fn new(screen: Box<dyn Screen>) -> Self {
+        let canvas = Canvas::new(screen);
+        let (width, height) = canvas.size();
+        let mut damage = Damage::new(&Rect::new(0, 0, width, height));
+        damage.add_rect(&Rect::new(0, 0, width, height));
+        Self {
+            canvas,
+            background: colors::BLACK,
+            layers: Vec::new(),
+            damage,
+            next_id: 1,
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        self.canvas.size()
+    }
+
+    /// Set the color shown where no layer is opaque.
+    pub fn set_background(&mut self, color: Rgba) {
+        self.background = color;
+        let (width, height) = self.size();
+        self.damage.add_rect(&Rect::new(0, 0, width, height));
+    }
+
+    fn layer_mut(&mut self, id: LayerId) -> Res<&mut Layer> {
+        self.layers
+            .iter_mut()
+            .find(|l| l.id == id)
+            .ok_or_else(|| unknown_layer(id))
+    }
+
+    /// Add a transparent layer covering a screen area.
+    pub fn add_layer(&mut self, area: &Rect, z: i32) -> LayerId {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut image = Image::new(area.w, area.h);
+        image.buffer.fill(colors::TRANSPARENT);
+        self.layers.push(Layer {
+            id,
+            pos: Coord::new(area.x, area.y),
+            image,
+            z,
+        });
+        self.layers.sort_by_key(|l| l.z);
+        id
+    }
+
+    /// Replace the pixels of a layer with the cropped area of an image,
+    /// at the given position in the layer.
+    pub fn update_layer(
+        &mut self,
+        id: LayerId,
+        image: &Image,
+        crop: &Rect,
+        pos: &Coord,
+    ) -> Res<()> {
+        let layer = self.layer_mut(id)?;
+        layer.image.copy_image(image, crop, pos);
+        let area = Rect::new(layer.pos.x + pos.x, layer.pos.y + pos.y, crop.w, crop.h);
+        let area = intersect(&area, &layer.area());
+        if let Some(area) = area {
+            self.damage.add_rect(&area);
+        }
+        Ok(())
+    }
+
+    /// Move a layer and change its z-order.
+    pub fn move_layer(&mut self, id: LayerId, pos: &Coord, z: i32) -> Res<()> {
+        let layer = self.layer_mut(id)?;
+        let old = layer.area();
+        layer.pos = pos.clone();
+        layer.z = z;
+        let new = layer.area();
+        self.damage.add_rect(&old);
+        self.damage.add_rect(&new);
+        self.layers.sort_by_key(|l| l.z);
+        Ok(())
+    }
+
+    pub fn remove_layer(&mut self, id: LayerId) -> Res<()> {
+        let i = self
+            .layers
+            .iter()
+            .position(|l| l.id == id)
+            .ok_or_else(|| unknown_layer(id))?;
+        let layer = self.layers.remove(i);
+        self.damage.add_rect(&layer.area());
+        Ok(())
+    }
+
+    /// Redraw the modified areas and send them to the screen.
+    pub fn composite(&mut self) -> Res<()> {
+        for r in self.damage.rects(COMPOSITE_OVERHEAD) {
+            self.canvas.fill_rect(&r, self.background);
+            for layer in &self.layers {
+                if let Some(i) = intersect(&r, &layer.area()) {
+                    let crop = Rect::new(i.x - layer.pos.x, i.y - layer.pos.y, i.w, i.h);
+                    self.canvas
+                        .blend_image(&layer.image, &crop, &Coord::new(i.x, i.y));
+                }
+            }
+        }
+        self.damage.clear();
+        self.canvas.commit()
+    }
+}
+
+// Client requests: an opcode byte, a u32 LE payload length and the
+// payload, made of u32 LE fields and RGBA pixels. Replies have a status
+// byte instead of the opcode, with a u32 layer ID on success or an
+// error message.
+const OP_CREATE: u8 = 1; // x, y, w, h, z -> layer ID
+const OP_UPDATE: u8 = 2; // layer, x, y, w, h, pixels
+const OP_MOVE: u8 = 3; // layer, x, y, z
+const OP_DESTROY: u8 = 4; // layer
+const OP_COMMIT: u8 = 5;
+
+const STATUS_OK: u8 = 0;
+const STATUS_ERROR: u8 = 1;
+
+const MAX_PAYLOAD: usize = 64 << 20;
+
+fn protocol_error(msg: &str) -> Error {
+    Error::Protocol(msg.to_string())
+}
+
+fn write_frame<W: Write>(w: &mut W, tag: u8, payload: &[u8]) -> Res<()> {
+    w.write_all(&[tag])?;
+    w.write_all(&(payload.len() as u32).to_le_bytes())?;
+    w.write_all(payload)?;
+    w.flush()?;
+    Ok(())
+}
+
+// Read a frame, None when the peer closed the connection.
+fn read_frame<R: Read>(r: &mut R) -> Res<Option<(u8, Vec<u8>)>> {
+    let mut header = [0u8; 5];
+    match r.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize;
+    if len > MAX_PAYLOAD {
+        return Err(protocol_error("message too large"));
+    }
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload)?;
+    Ok(Some((header[0], payload)))
+}
+
+// Payload field reader
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn u32(&mut self) -> Res<u32> {
+        if self.0.len() < 4 {
+            return Err(protocol_error("message too short"));
+        }
+        let (field, rest) = self.0.split_at(4);
+        self.0 = rest;
+        Ok(u32::from_le_bytes(field.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Res<usize> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn i32(&mut self) -> Res<i32> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn rect(&mut self) -> Res<Rect> {
+        Ok(Rect::new(
+            self.usize()?,
+            self.usize()?,
+            self.usize()?,
+            self.usize()?,
+        ))
+    }
+
+    fn pixels(&mut self, w: usize, h: usize) -> Res<Image> {
+        let len = w.checked_mul(h).and_then(|n| n.checked_mul(4));
+        if len.is_none_or(|len| len > MAX_PAYLOAD) {
+            return Err(protocol_error("image too large"));
+        }
+        if len != Some(self.0.len()) {
+            return Err(protocol_error("bad pixel data size"));
+        }
+        let mut image = Image::new(w, h);
+        for (p, c) in image.buffer.iter_mut().zip(self.0.chunks_exact(4)) {
+            *p = Rgba::new(c[0], c[1], c[2], c[3]);
+        }
+        Ok(image)
+    }
+}
+
+fn encode(fields: &[u32]) -> Vec<u8> {
+    fields.iter().flat_map(|f| f.to_le_bytes()).collect()
+}
+
+// A client panicking while holding the compositor leaves it usable.
+fn lock(compositor: &Mutex<Compositor>) -> MutexGuard<'_, Compositor> {
+    compositor.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Run a client request, returning the reply payload.
+fn handle(
+    op: u8,
+    payload: &[u8],
+    compositor: &Mutex<Compositor>,
+    owned: &mut HashSet<LayerId>,
+) -> Res<Vec<u8>> {
+    let mut f = Fields(payload);
+    let owned_layer = |f: &mut Fields| -> Res<LayerId> {
+        let id = f.u32()?;
+        match owned.contains(&id) {
+            true => Ok(id),
+            false => Err(unknown_layer(id)),
+        }
+    };
+    match op {
+        OP_CREATE => {
+            let area = f.rect()?;
+            let z = f.i32()?;
+            if area
+                .w
+                .checked_mul(area.h)
+                .is_none_or(|n| n > MAX_PAYLOAD / 4)
+            {
+                return Err(protocol_error("layer too large"));
+            }
+            let id = lock(compositor).add_layer(&area, z);
+            owned.insert(id);
+            Ok(encode(&[id]))
+        }
+        OP_UPDATE => {
+            let id = owned_layer(&mut f)?;
+            let area = f.rect()?;
+            let image = f.pixels(area.w, area.h)?;
+            let mut comp = lock(compositor);
+            let layer = comp.layer_mut(id)?;
+            if area.w > layer.image.width || area.h > layer.image.height {
+                return Err(protocol_error("image larger than the layer"));
+            }
+            comp.update_layer(id, &image, &image.full(), &Coord::new(area.x, area.y))?;
+            Ok(Vec::new())
+        }
+        OP_MOVE => {
+            let id = owned_layer(&mut f)?;
+            let pos = Coord::new(f.usize()?, f.usize()?);
+            let z = f.i32()?;
+            lock(compositor).move_layer(id, &pos, z)?;
+            Ok(Vec::new())
+        }
+        OP_DESTROY => {
+            let id = owned_layer(&mut f)?;
+            lock(compositor).remove_layer(id)?;
+            owned.remove(&id);
+            Ok(Vec::new())
+        }
+        OP_COMMIT => {
+            lock(compositor).composite()?;
+            Ok(Vec::new())
+        }
+        op => Err(Error::Protocol(format!("unknown request {}", op))),
+    }
+}
+
+/// The compositor socket path, in the user runtime directory if set.
+pub fn default_socket_path() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+        .join("turing-screen.sock")
+}
+
+/// Serve the requests of a compositor client until it disconnects.
+///
+/// The layers created by the client are removed when it disconnects.
+pub fn serve_client<S: Read + Write>(mut stream: S, compositor: &Mutex<Compositor>) -> Res<()> {
+    let mut owned = HashSet::new();
+    let res = loop {
+        let (op, payload) = match read_frame(&mut stream) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break Ok(()),
+            Err(err) => break Err(err),
+        };
+        let reply = match handle(op, &payload, compositor, &mut owned) {
+            Ok(data) => write_frame(&mut stream, STATUS_OK, &data),
+            Err(Error::Protocol(msg)) => write_frame(&mut stream, STATUS_ERROR, msg.as_bytes()),
+            Err(err) => write_frame(&mut stream, STATUS_ERROR, err.to_string().as_bytes()),
+        };
+        if let Err(err) = reply {
+            break Err(err);
+        }
+    };
+
+    if !owned.is_empty() {
+        let mut comp = lock(compositor);
+        for id in owned {
+            comp.remove_layer(id)?;
+        }
+        comp.composite()?;
+    }
+    res
+}
+
+/// A connection to a compositor daemon.
+///
+/// ```no_run
+/// use turing_screen::{colors, default_socket_path, CompositorClient, Coord, Image, Rect};
+///
+/// let mut client = CompositorClient::connect(default_socket_path()).unwrap();
+/// let layer = client.create_layer(&Rect::new(0, 0, 100, 20), 10).unwrap();
+/// let mut image = Image::new(100, 20);
+/// image.buffer.fill(colors::WHITE);
+/// client.update_layer(layer, &image, &image.full(), &Coord::new(0, 0)).unwrap();
+/// client.commit().unwrap();
+/// ```
+pub struct CompositorClient<S = UnixStream> {
+    stream: S,
+}
+
+impl CompositorClient<UnixStream> {
+    pub fn connect<P: AsRef<Path>>(path: P) -> Res<Self> {
+        Ok(Self::new(UnixStream::connect(path)?))
+    }
+}
+
+impl<S: Read + Write> CompositorClient<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    fn request(&mut self, op: u8, payload: &[u8]) -> Res<Vec<u8>> {
+        write_frame(&mut self.stream, op, payload)?;
+        match read_frame(&mut self.stream)? {
+            Some((STATUS_OK, data)) => Ok(data),
+            Some((_, msg)) => Err(Error::Protocol(String::from_utf8_lossy(&msg).into_owned())),
+            None => Err(protocol_error("connection closed")),
+        }
+    }
+
+    /// Create a transparent layer covering a screen area.
+    pub fn create_layer(&mut self, area: &Rect, z: i32) -> Res<LayerId> {
+        let fields = [area.x, area.y, area.w, area.h].map(|v| v as u32);
+        let mut payload = encode(&fields);
+        payload.extend_from_slice(&z.to_le_bytes());
+        Fields(&self.request(OP_CREATE, &payload)?).u32()
+    }
+
+    /// Replace the pixels of a layer with the cropped area of an image,
+    /// at the given position in the layer.
+    pub fn update_layer(
+        &mut self,
+        layer: LayerId,
+        image: &Image,
+        crop: &Rect,
+        pos: &Coord,
+    ) -> Res<()> {
+        let crop = crop.clip(image.width, image.height);
+        let fields = [pos.x, pos.y, crop.w, crop.h].map(|v| v as u32);
+        let mut payload = encode(&[layer]);
+        payload.extend(encode(&fields));
+        for y in crop.y..crop.y + crop.h {
+            let ofs = y * image.width + crop.x;
+            for p in &image.buffer[ofs..ofs + crop.w] {
+                payload.extend_from_slice(&[p.r, p.g, p.b, p.a]);
+            }
+        }
+        self.request(OP_UPDATE, &payload)?;
+        Ok(())
+    }
+
+    /// Move a layer and change its z-order.
+    pub fn move_layer(&mut self, layer: LayerId, pos: &Coord, z: i32) -> Res<()> {
+        let mut payload = encode(&[layer, pos.x as u32, pos.y as u32]);
+        payload.extend_from_slice(&z.to_le_bytes());
+        self.request(OP_MOVE, &payload)?;
+        Ok(())
+    }
+
+    pub fn destroy_layer(&mut self, layer: LayerId) -> Res<()> {
+        self.request(OP_DESTROY, &encode(&[layer]))?;
+        Ok(())
+    }
+
+    /// Send the modified areas of all the layers to the screen.
+    pub fn commit(&mut self) -> Res<()> {
+        self.request(OP_COMMIT, &[])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::{EmulatorRevA, ScreenRevA};
+
+    fn emulated() -> (Compositor, EmulatorRevA) {
+        let emu = EmulatorRevA::new();
+        let screen = ScreenRevA::with_port(Box::new(emu.clone()));
+        (Compositor::new(Box::new(screen)), emu)
+    }
+
+    fn filled(w: usize, h: usize, color: Rgba) -> Image {
+        let mut image = Image::new(w, h);
+        image.buffer.fill(color);
+        image
+    }
+
+    #[test]
+    fn test_composite() -> Res<()> {
+        let (mut comp, emu) = emulated();
+        let red = filled(10, 10, Rgba::new(255, 0, 0, 255));
+        let blue = filled(10, 10, Rgba::new(0, 0, 255, 128));
+
+        let top = comp.add_layer(&Rect::new(5, 0, 10, 10), 2);
+        let bottom = comp.add_layer(&Rect::new(0, 0, 10, 10), 1);
+        comp.update_layer(top, &blue, &blue.full(), &Coord::new(0, 0))?;
+        comp.update_layer(bottom, &red, &red.full(), &Coord::new(0, 0))?;
+        comp.composite()?;
+
+        // colors after the conversion to RGB565 and back
+        let snapshot = emu.snapshot();
+        assert_eq!(snapshot.buffer[0], Rgba::new(255, 0, 0, 255));
+        assert_eq!(snapshot.buffer[7], Rgba::new(123, 0, 123, 255)); // blended
+        assert_eq!(snapshot.buffer[12], Rgba::new(0, 0, 123, 255)); // over black
+
+        // raise the red layer, then remove it
+        comp.move_layer(bottom, &Coord::new(0, 0), 3)?;
+        comp.composite()?;
+        assert_eq!(emu.snapshot().buffer[7], Rgba::new(255, 0, 0, 255));
+        comp.remove_layer(bottom)?;
+        comp.composite()?;
+        assert_eq!(emu.snapshot().buffer[0], colors::BLACK);
+        assert_eq!(emu.snapshot().buffer[7], Rgba::new(0, 0, 123, 255));
+
+        assert!(matches!(comp.remove_layer(bottom), Err(Error::Protocol(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_clients() -> Res<()> {
+        let (comp, emu) = emulated();
+        let comp = Arc::new(Mutex::new(comp));
+
+        let (client_end, server_end) = UnixStream::pair()?;
+        let server = {
+            let comp = comp.clone();
+            thread::spawn(move || serve_client(server_end, &comp))
+        };
+
+        let mut client = CompositorClient::new(client_end);
+        let layer = client.create_layer(&Rect::new(0, 0, 4, 4), 0)?;
+        let white = filled(8, 8, colors::WHITE);
+        client.update_layer(layer, &white, &Rect::new(2, 2, 2, 2), &Coord::new(1, 1))?;
+        client.commit()?;
+        assert_eq!(emu.snapshot().buffer[0], colors::BLACK);
+        assert_eq!(emu.snapshot().buffer[321], colors::WHITE);
+
+        // other clients cannot use the layer
+        let (other_end, other_server_end) = UnixStream::pair()?;
+        let other_server = {
+            let comp = comp.clone();
+            thread::spawn(move || serve_client(other_server_end, &comp))
+        };
+        let mut other = CompositorClient::new(other_end);
+        let err = other.destroy_layer(layer).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("protocol error: unknown layer {}", layer)
+        );
+        drop(other);
+        other_server.join().unwrap()?;
+
+        // the pixels must fit in the layer and the size must not overflow
+        let err = client
+            .update_layer(layer, &white, &white.full(), &Coord::new(0, 0))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "protocol error: image larger than the layer"
+        );
+        let payload = encode(&[layer, 0, 0, 1 << 31, 1 << 31]);
+        let err = client.request(OP_UPDATE, &payload).unwrap_err();
+        assert_eq!(err.to_string(), "protocol error: image too large");
+
+        // the layers are removed when the client disconnects
+        drop(client);
+        server.join().unwrap()?;
+        assert_eq!(emu.snapshot().buffer[321], colors::BLACK);
+        Ok(())
+    }
+}
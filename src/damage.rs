@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::cmp::{max, min};
+
+use crate::Rect;
+
+/// Changed pixels of a screen area, tracked as one span per row.
+#[derive(Debug, Clone)]
+pub(crate) struct Damage {
+    area: Rect,
+    spans: Vec<Option<(usize, usize)>>, // changed columns [x0, x1) of each row
+}
+
+impl Damage {
+    pub fn new(area: &Rect) -> Self {
+        Self {
+            area: area.clone(),
+            spans: vec![None; area.h],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.iter().all(|s| s.is_none())
+    }
+
+    pub fn clear(&mut self) {
+        self.spans.fill(None);
+    }
+
+    /// Mark the pixel at the given screen coordinates as changed.
+    #[inline]
+    pub fn add(&mut self, x: usize, y: usize) {
+        let span = &mut self.spans[y - self.area.y];
+        *span = Some(match *span {
+            Some((x0, x1)) => (min(x0, x), max(x1, x + 1)),
+            None => (x, x + 1),
+        });
+    }
+
+    /// Mark an area as changed, clipped to the tracked area.
+    pub fn add_rect(&mut self, r: &Rect) {
+        let x0 = max(r.x, self.area.x);
+        let x1 = min(r.x + r.w, self.area.x + self.area.w);
+        let y0 = max(r.y, self.area.y);
+        let y1 = min(r.y + r.h, self.area.y + self.area.h);
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+        for span in &mut self.spans[y0 - self.area.y..y1 - self.area.y] {
+            *span = Some(match *span {
+                Some((a, b)) => (min(a, x0), max(b, x1)),
+                None => (x0, x1),
+            });
+        }
+    }
+
+    /// Group the changed pixels in rectangles.
+    ///
+    /// Consecutive changed rows are merged while the unchanged pixels added
+    /// by the merge cost less than sending another rectangle, whose cost is
+    /// given in pixels by `overhead`.
+    pub fn rects(&self, overhead: usize) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        let mut cur: Option<Rect> = None;
+        for (i, span) in self.spans.iter().enumerate() {
+            let Some((x0, x1)) = *span else {
+                continue;
+            };
+            let row = Rect::new(x0, self.area.y + i, x1 - x0, 1);
+            cur = Some(match cur {
+                None => row,
+                Some(c) => {
+                    let u = union(&c, &row);
+                    if u.w * u.h <= c.w * c.h + row.w + overhead {
+                        u
+                    } else {
+                        rects.push(c);
+                        row
+                    }
+                }
+            });
+        }
+        rects.extend(cur);
+        rects
+    }
+}
+
+fn union(a: &Rect, b: &Rect) -> Rect {
+    let x0 = min(a.x, b.x);
+    let y0 = min(a.y, b.y);
+    let x1 = max(a.x + a.w, b.x + b.w);
+    let y1 = max(a.y + a.h, b.y + b.h);
+    Rect::new(x0, y0, x1 - x0, y1 - y0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty() {
+        let d = Damage::new(&Rect::new(10, 10, 20, 20));
+        assert!(d.is_empty());
+        assert!(d.rects(0).is_empty());
+    }
+
+    #[test]
+    fn test_add() {
+        let mut d = Damage::new(&Rect::new(10, 10, 20, 20));
+        d.add(12, 11);
+        d.add(15, 11);
+        d.add(13, 12);
+        assert_eq!(d.rects(100), [Rect::new(12, 11, 4, 2)]);
+
+        d.clear();
+        assert!(d.is_empty());
+    }
+
+    #[test]
+    fn test_add_rect_clipped() {
+        let mut d = Damage::new(&Rect::new(10, 10, 20, 20));
+        d.add_rect(&Rect::new(0, 25, 100, 100));
+        assert_eq!(d.rects(0), [Rect::new(10, 25, 20, 5)]);
+        d.add_rect(&Rect::new(40, 0, 10, 10));
+        d.add_rect(&Rect::new(0, 40, 100, 10));
+        assert_eq!(d.rects(0), [Rect::new(10, 25, 20, 5)]);
+    }
+
+    #[test]
+    fn test_merge() {
+        // two distant changes on the same column
+        let mut d = Damage::new(&Rect::new(0, 0, 100, 100));
+        d.add_rect(&Rect::new(10, 0, 10, 2));
+        d.add_rect(&Rect::new(10, 50, 10, 2));
+
+        // merging adds 48 rows of 10 unchanged pixels
+        assert_eq!(
+            d.rects(0),
+            [Rect::new(10, 0, 10, 2), Rect::new(10, 50, 10, 2)]
+        );
+        assert_eq!(d.rects(480), [Rect::new(10, 0, 10, 52)]);
+    }
+
+    #[test]
+    fn test_merge_columns() {
+        // changes on opposite sides of consecutive rows
+        let mut d = Damage::new(&Rect::new(0, 0, 100, 100));
+        d.add_rect(&Rect::new(0, 0, 4, 1));
+        d.add_rect(&Rect::new(96, 1, 4, 1));
+        assert_eq!(d.rects(0), [Rect::new(0, 0, 4, 1), Rect::new(96, 1, 4, 1)]);
+        assert_eq!(d.rects(200), [Rect::new(0, 0, 100, 2)]);
+    }
+}
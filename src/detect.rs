@@ -0,0 +1,240 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::serial_port::{self, Device, PortOptions, SerialPort};
+use crate::trace::TraceFile;
+use crate::{screen_rev_a, screen_rev_b, screen_rev_c};
+use crate::{Error, RecordingPort, Res, Revision, Screen, ScreenPort};
+use crate::{ScreenRevA, ScreenRevB, ScreenRevC};
+
+// USB serial numbers reported by known devices
+const KNOWN_SERIALS: &[(&str, Revision)] = &[
+    ("USB35INCHIPSV2", Revision::A), // Turing Smart Screen 3.5"
+    ("2017-2-25", Revision::B),      // XuanFang 3.5"
+    ("20080411", Revision::C),       // Turing Smart Screen 2.1", 5" and 8.8"
+];
+
+// USB vendor and product IDs of known devices, with candidate revisions
+// in probing order
+const KNOWN_IDS: &[(u16, u16, &[Revision])] = &[
+    (0x1a86, 0x5722, &[Revision::A, Revision::B]), // QinHeng CH552 based displays
+    (0x1d6b, 0x0121, &[Revision::C]),              // Linux gadget based displays
+];
+
+// Find the screen revisions that may drive a device. A known serial number
+// identifies the revision, otherwise the vendor and product IDs are used.
+fn classify(serial: &str, vid: u16, pid: u16) -> Vec<Revision> {
+    if let Some((_, rev)) = KNOWN_SERIALS.iter().find(|(s, _)| *s == serial) {
+        return vec![rev.clone()];
+    }
+    KNOWN_IDS
+        .iter()
+        .find(|(v, p, _)| *v == vid && *p == pid)
+        .map_or(Vec::new(), |(_, _, revs)| revs.to_vec())
+}
+
+/// How to find the serial port of a screen.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum PortSelection {
+    Auto,           // search all attached devices
+    Path(String),   // use the given port
+    Serial(String), // use the device with the given USB serial number
+}
+
+fn device_revisions(d: &Device) -> Vec<Revision> {
+    classify(d.serial.as_deref().unwrap_or(""), d.vid, d.pid)
+}
+
+pub(crate) fn open_revision(
+    portname: &str,
+    rev: &Revision,
+    opts: &PortOptions,
+    trace: Option<&TraceFile>,
+) -> Res<Box<dyn Screen>> {
+    let baud_rate = opts.baud_rate.unwrap_or(match rev {
+        Revision::A => screen_rev_a::BAUD_RATE,
+        Revision::B => screen_rev_b::BAUD_RATE,
+        Revision::C => screen_rev_c::BAUD_RATE,
+    });
+    log::debug!("create screen rev {:?} on {}", rev, portname);
+    let mut port: Box<dyn ScreenPort> =
+        Box::new(SerialPort::with_options(portname, baud_rate, opts)?);
+    if let Some(trace) = trace {
+        port = Box::new(RecordingPort::with_trace(port, trace));
+    }
+    Ok(match rev {
+        Revision::A => Box::new(ScreenRevA::with_port(port)),
+        Revision::B => Box::new(ScreenRevB::with_port(port)),
+        Revision::C => Box::new(ScreenRevC::with_port(port)),
+    })
+}
+
+// Open the device with the first candidate revision that answers the
+// hello command. A single candidate is opened without probing.
+fn probe(
+    portname: &str,
+    revs: &[Revision],
+    opts: &PortOptions,
+    trace: Option<&TraceFile>,
+) -> Res<Box<dyn Screen>> {
+    if let [rev] = revs {
+        return open_revision(portname, rev, opts, trace);
+    }
+
+    for rev in revs {
+        log::debug!("probe {} as screen revision {:?}", portname, rev);
+        let mut scr = open_revision(portname, rev, opts, trace)?;
+        match scr.init() {
+            Ok(()) => return Ok(scr),
+            Err(err) => log::debug!("revision {:?} probe failed: {}", rev, err),
+        }
+    }
+    Err(Error::DeviceNotFound(format!(
+        "no screen revision answered on {}",
+        portname
+    )))
+}
+
+/// Open a screen, detecting its model.
+///
+/// The driver is selected from the USB information of the device, unless
+/// a revision is given. Devices selected by path or serial number default
+/// to revision A if their model is unknown.
+pub(crate) fn open_with(
+    sel: &PortSelection,
+    revision: Option<&Revision>,
+    opts: &PortOptions,
+) -> Res<Box<dyn Screen>> {
+    // a port given by path needs no enumeration, which fails in some
+    // containers: its USB information only helps to select the driver
+    let devices = match sel {
+        PortSelection::Path(_) => serial_port::list_devices().unwrap_or_else(|err| {
+            log::debug!("cannot list the serial devices: {}", err);
+            Vec::new()
+        }),
+        _ => serial_port::list_devices()?,
+    };
+    // a single trace for all the probed devices and revisions
+    let trace = opts.trace.as_ref().map(TraceFile::create).transpose()?;
+    let trace = trace.as_ref();
+
+    let device = match sel {
+        PortSelection::Auto => None,
+        PortSelection::Path(path) => {
+            let d = devices.iter().find(|d| d.port_name == *path);
+            Some((path.clone(), d))
+        }
+        PortSelection::Serial(serial) => {
+            match devices.iter().find(|d| d.serial.as_deref() == Some(serial)) {
+                Some(d) => Some((d.port_name.clone(), Some(d))),
+                None => {
+                    return Err(Error::DeviceNotFound(format!(
+                        "no serial device matching {}",
+                        serial
+                    )))
+                }
+            }
+        }
+    };
+
+    if let Some((portname, d)) = device {
+        let revs = match revision {
+            Some(rev) => vec![rev.clone()],
+            None => d
+                .map(device_revisions)
+                .filter(|revs| !revs.is_empty())
+                .unwrap_or_else(|| vec![Revision::A]),
+        };
+        return probe(&portname, &revs, opts, trace);
+    }
+
+    for d in &devices {
+        let mut revs = device_revisions(d);
+        if let Some(rev) = revision {
+            revs.retain(|r| r == rev);
+        }
+        if revs.is_empty() {
+            continue;
+        }
+        log::debug!("found screen candidate {}", d);
+        match probe(&d.port_name, &revs, opts, trace) {
+            Ok(scr) => return Ok(scr),
+            Err(err) => log::debug!("{}", err),
+        }
+    }
+
+    Err(Error::DeviceNotFound(not_found(&devices)))
+}
+
+/// Open a screen, detecting its model.
+///
+/// If `portname` is "AUTO", search all attached USB serial devices for a
+/// supported screen. Otherwise use the given port, selecting the driver
+/// from its USB information and defaulting to revision A.
+pub(crate) fn open(portname: &str) -> Res<Box<dyn Screen>> {
+    let sel = match portname {
+        "AUTO" => PortSelection::Auto,
+        path => PortSelection::Path(path.to_string()),
+    };
+    open_with(&sel, None, &PortOptions::default())
+}
+
+/// Open the screen with the given USB serial number.
+pub(crate) fn open_serial(serial: &str) -> Res<Box<dyn Screen>> {
+    let sel = PortSelection::Serial(serial.to_string());
+    open_with(&sel, None, &PortOptions::default())
+}
+
+fn not_found(devices: &[Device]) -> String {
+    if devices.is_empty() {
+        return "no USB serial devices attached".to_string();
+    }
+    let list: Vec<String> = devices.iter().map(|d| d.to_string()).collect();
+    format!("no compatible screen among {}", list.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        for tc in [
+            ("USB35INCHIPSV2", 0x1a86, 0x5722, vec![Revision::A]),
+            ("2017-2-25", 0x1a86, 0x5722, vec![Revision::B]),
+            ("20080411", 0x1d6b, 0x0121, vec![Revision::C]),
+            ("", 0x1a86, 0x5722, vec![Revision::A, Revision::B]),
+            ("20080411", 0x0000, 0x0000, vec![Revision::C]),
+            ("1234", 0x0403, 0x6001, vec![]),
+        ] {
+            assert_eq!(classify(tc.0, tc.1, tc.2), tc.3);
+        }
+    }
+
+    #[test]
+    fn test_not_found() {
+        assert_eq!(not_found(&[]), "no USB serial devices attached");
+        let devices = [
+            Device {
+                port_name: "/dev/ttyUSB0".to_string(),
+                vid: 0x0403,
+                pid: 0x6001,
+                serial: Some("A10K1234".to_string()),
+                manufacturer: Some("FTDI".to_string()),
+                product: Some("FT232R USB UART".to_string()),
+            },
+            Device {
+                port_name: "/dev/ttyACM0".to_string(),
+                vid: 0x2341,
+                pid: 0x0043,
+                serial: None,
+                manufacturer: None,
+                product: None,
+            },
+        ];
+        assert_eq!(
+            not_found(&devices),
+            "no compatible screen among /dev/ttyUSB0 [0403:6001 serial 'A10K1234'], \
+             /dev/ttyACM0 [2341:0043 serial '']"
+        );
+    }
+}
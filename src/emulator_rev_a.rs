@@ -0,0 +1,421 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::screen_rev_a::{Command, HEIGHT, USBMONITOR35, WIDTH};
+use crate::{Coord, Image, Rect, Rgba, VirtualScreen};
+use crate::{Orientation, Res, Screen, ScreenPort};
+
+const HELLO: u8 = Command::Hello as u8;
+const RESET: u8 = Command::Reset as u8;
+const CLEAR: u8 = Command::Clear as u8;
+const TO_BLACK: u8 = Command::ToBlack as u8;
+const SCREEN_OFF: u8 = Command::ScreenOff as u8;
+const SCREEN_ON: u8 = Command::ScreenOn as u8;
+const SET_BRIGHTNESS: u8 = Command::SetBrightness as u8;
+const SET_ORIENTATION: u8 = Command::SetOrientation as u8;
+const DISPLAY_BITMAP: u8 = Command::DisplayBitmap as u8;
+
+const CMD_SIZE: usize = 6;
+const ORIENTATION_CMD_SIZE: usize = 16;
+
+/// A rev A command decoded by the emulator.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodedCommand {
+    Hello,
+    Reset,
+    Clear,
+    ToBlack,
+    ScreenOff,
+    ScreenOn,
+    SetBrightness(usize),
+    SetOrientation(Orientation),
+    DisplayBitmap(Rect),
+    Unknown(u8),
+}
+
+impl fmt::Display for DecodedCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodedCommand::SetBrightness(level) => write!(f, "SetBrightness {}", level),
+            DecodedCommand::SetOrientation(o) => write!(f, "SetOrientation {:?}", o),
+            DecodedCommand::DisplayBitmap(rect) => write!(f, "DisplayBitmap {}", rect),
+            DecodedCommand::Unknown(cmd) => write!(f, "Unknown {:#04x}", cmd),
+            cmd => write!(f, "{:?}", cmd),
+        }
+    }
+}
+
+fn orientation(o: u8) -> Option<Orientation> {
+    match o {
+        0 => Some(Orientation::Portrait),
+        1 => Some(Orientation::ReversePortrait),
+        2 => Some(Orientation::Landscape),
+        3 => Some(Orientation::ReverseLandscape),
+        _ => None,
+    }
+}
+
+// Unpack the 10-bit x0, y0, x1, y1 coordinates of a DisplayBitmap header.
+fn bitmap_rect(b: &[u8]) -> Option<Rect> {
+    let (b0, b1, b2, b3, b4) = (
+        b[0] as usize,
+        b[1] as usize,
+        b[2] as usize,
+        b[3] as usize,
+        b[4] as usize,
+    );
+    let x0 = (b0 << 2) | (b1 >> 6);
+    let y0 = ((b1 & 0x3f) << 4) | (b2 >> 4);
+    let x1 = ((b2 & 0x0f) << 6) | (b3 >> 2);
+    let y1 = ((b3 & 0x03) << 8) | b4;
+    if x1 < x0 || y1 < y0 {
+        return None;
+    }
+    Some(Rect::new(x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+}
+
+struct State {
+    screen: VirtualScreen,
+    input: Vec<u8>,       // bytes received and not decoded yet
+    output: VecDeque<u8>, // responses to be read by the host
+    log: Vec<DecodedCommand>,
+}
+
+impl State {
+    // Decode and execute all complete commands in the input buffer.
+    fn process(&mut self) {
+        loop {
+            if self.input.len() < CMD_SIZE {
+                return;
+            }
+            let header = &self.input[..CMD_SIZE];
+            let size = match header[5] {
+                SET_ORIENTATION => ORIENTATION_CMD_SIZE,
+                DISPLAY_BITMAP => match bitmap_rect(header) {
+                    Some(r) => CMD_SIZE + 2 * r.w * r.h,
+                    None => CMD_SIZE,
+                },
+                _ => CMD_SIZE,
+            };
+            if self.input.len() < size {
+                return;
+            }
+            let data: Vec<u8> = self.input.drain(..size).collect();
+            let cmd = self.execute(&data);
+            log::debug!("emulator: {}", cmd);
+            self.log.push(cmd);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) -> DecodedCommand {
+        // The virtual screen never fails except for out of bounds areas,
+        // which are reported as warnings.
+        let res = match data[5] {
+            HELLO => {
+                self.output.extend(USBMONITOR35);
+                return DecodedCommand::Hello;
+            }
+            RESET => return DecodedCommand::Reset,
+            CLEAR => self.screen.clear().map(|_| DecodedCommand::Clear),
+            TO_BLACK => {
+                let image = Image::new(WIDTH, HEIGHT);
+                let pos = Coord::new(0, 0);
+                let r = self.screen.display_image(&image, &image.full(), &pos);
+                r.map(|_| DecodedCommand::ToBlack)
+            }
+            SCREEN_OFF => self.screen.screen_off().map(|_| DecodedCommand::ScreenOff),
+            SCREEN_ON => self.screen.screen_on().map(|_| DecodedCommand::ScreenOn),
+            SET_BRIGHTNESS => {
+                let level = !data[0] as usize;
+                let r = self.screen.set_brightness(level);
+                r.map(|_| DecodedCommand::SetBrightness(level))
+            }
+            SET_ORIENTATION => match orientation(data[6].wrapping_sub(100)) {
+                Some(o) => {
+                    let r = self.screen.set_orientation(o.clone());
+                    r.map(|_| DecodedCommand::SetOrientation(o))
+                }
+                None => return DecodedCommand::Unknown(SET_ORIENTATION),
+            },
+            DISPLAY_BITMAP => match bitmap_rect(data) {
+                Some(r) => {
+                    let image = upmix(&data[CMD_SIZE..], r.w, r.h);
+                    let pos = Coord::new(r.x, r.y);
+                    let res = self.screen.display_image(&image, &image.full(), &pos);
+                    res.map(|_| DecodedCommand::DisplayBitmap(r))
+                }
+                None => return DecodedCommand::Unknown(DISPLAY_BITMAP),
+            },
+            cmd => return DecodedCommand::Unknown(cmd),
+        };
+
+        res.unwrap_or_else(|err| {
+            log::warn!("emulator: {}", err);
+            DecodedCommand::Unknown(data[5])
+        })
+    }
+}
+
+// Convert little endian RGB565 lines to an RGBA image.
+fn upmix(data: &[u8], width: usize, height: usize) -> Image {
+    let mut image = Image::new(width, height);
+    for (p, raw) in image.buffer.iter_mut().zip(data.chunks_exact(2)) {
+        let (lo, hi) = (raw[0], raw[1]);
+        let r = hi & 0xf8;
+        let g = ((hi & 0x07) << 5) | ((lo & 0xe0) >> 3);
+        let b = lo << 3;
+        *p = Rgba::new(r | (r >> 5), g | (g >> 6), b | (b >> 5), 255);
+    }
+    image
+}
+
+/// An emulated rev A device.
+///
+/// The emulator decodes the byte stream written by [`crate::ScreenRevA`]
+/// and keeps the device state and framebuffer in a [`VirtualScreen`].
+/// Clones share the same device, so one clone can be given to the screen
+/// as its port and another one used to inspect the results.
+///
+/// ```
+/// use turing_screen::{EmulatorRevA, Screen, ScreenRevA};
+///
+/// let emu = EmulatorRevA::new();
+/// let mut scr = ScreenRevA::with_port(Box::new(emu.clone()));
+/// scr.init().unwrap();
+/// scr.clear().unwrap();
+/// assert_eq!(emu.snapshot().buffer[0], turing_screen::colors::WHITE);
+/// ```
+#[derive(Clone)]
+pub struct EmulatorRevA {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for EmulatorRevA {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EmulatorRevA {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                screen: VirtualScreen::new(WIDTH, HEIGHT),
+                input: Vec::new(),
+                output: VecDeque::new(),
+                log: Vec::new(),
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The screen contents as seen by the user.
+    pub fn snapshot(&self) -> Image {
+        self.lock().screen.snapshot()
+    }
+
+    /// Save a snapshot of the screen to a PNG file.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Res<()> {
+        self.lock().screen.save_png(path)
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.lock().screen.orientation()
+    }
+
+    pub fn brightness(&self) -> usize {
+        self.lock().screen.brightness()
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.lock().screen.is_on()
+    }
+
+    /// Remove and return the commands decoded so far.
+    pub fn take_commands(&self) -> Vec<DecodedCommand> {
+        std::mem::take(&mut self.lock().log)
+    }
+}
+
+impl Read for EmulatorRevA {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.lock();
+        if state.output.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no response"));
+        }
+        let n = buf.len().min(state.output.len());
+        for (b, v) in buf.iter_mut().zip(state.output.drain(..n)) {
+            *b = v;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for EmulatorRevA {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.lock();
+        state.input.extend_from_slice(buf);
+        state.process();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ScreenPort for EmulatorRevA {
+    fn get_buf(&self) -> Vec<u8> {
+        self.lock().input.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{colors, Error, ScreenRevA};
+
+    const RED: Rgba = Rgba::new(0xff, 0, 0, 0xff);
+
+    fn emulated_screen() -> (ScreenRevA, EmulatorRevA) {
+        let emu = EmulatorRevA::new();
+        (ScreenRevA::with_port(Box::new(emu.clone())), emu)
+    }
+
+    #[test]
+    fn test_bitmap_rect() {
+        let header = [0x77, 0x8f, 0x87, 0x85, 0xdf, DISPLAY_BITMAP];
+        assert_eq!(bitmap_rect(&header), Some(Rect::new(478, 248, 4, 232)));
+        assert_eq!(bitmap_rect(&[0, 0, 0x10, 0, 0, DISPLAY_BITMAP]), None);
+    }
+
+    #[test]
+    fn test_init() -> Res<()> {
+        let (mut scr, emu) = emulated_screen();
+        scr.init()?;
+        assert_eq!(emu.take_commands(), [DecodedCommand::Hello]);
+
+        // no response pending
+        assert!(matches!(scr.read(1), Err(Error::Timeout)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image() -> Res<()> {
+        let (mut scr, emu) = emulated_screen();
+        scr.clear()?;
+        let mut image = Image::new(3, 2);
+        image.buffer[4] = RED;
+        scr.display_image(&image, &Rect::new(1, 1, 2, 1), &Coord::new(10, 20))?;
+
+        let snap = emu.snapshot();
+        assert_eq!(snap.buffer[20 * WIDTH + 10], RED);
+        assert_eq!(snap.buffer[20 * WIDTH + 11], colors::BLACK);
+        assert_eq!(snap.buffer[20 * WIDTH + 12], colors::WHITE);
+        assert_eq!(
+            emu.take_commands(),
+            [
+                DecodedCommand::SetOrientation(Orientation::Portrait),
+                DecodedCommand::Clear,
+                DecodedCommand::DisplayBitmap(Rect::new(10, 20, 2, 1)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_landscape() -> Res<()> {
+        let (mut scr, emu) = emulated_screen();
+        scr.set_orientation(Orientation::Landscape)?;
+        let mut image = Image::new(HEIGHT, WIDTH);
+        image.buffer[HEIGHT - 1] = RED;
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+
+        let snap = emu.snapshot();
+        assert_eq!((snap.width, snap.height), (HEIGHT, WIDTH));
+        assert_eq!(snap.buffer[HEIGHT - 1], RED);
+        assert_eq!(
+            emu.take_commands()[1],
+            DecodedCommand::DisplayBitmap(Rect::new(0, 0, HEIGHT, WIDTH))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_brightness_and_power() -> Res<()> {
+        let (mut scr, emu) = emulated_screen();
+        scr.set_brightness(100)?;
+        scr.screen_off()?;
+        assert_eq!(emu.brightness(), 100);
+        assert!(!emu.is_on());
+        scr.screen_on()?;
+        assert!(emu.is_on());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_writes() -> Res<()> {
+        let mut emu = EmulatorRevA::new();
+        let data = [
+            0,
+            0,
+            0,
+            0,
+            0,
+            SET_ORIENTATION,
+            102,
+            1,
+            224,
+            1,
+            64,
+            0,
+            0,
+            0,
+            0,
+            0, // Landscape
+            0x01,
+            0,
+            0,
+            0,
+            0,
+            SET_BRIGHTNESS, // level 0xfe
+            0x42,
+            0,
+            0,
+            0,
+            0,
+            0x42, // unknown command
+        ];
+        for b in data {
+            emu.write_all(&[b])?;
+        }
+        assert_eq!(
+            emu.take_commands(),
+            [
+                DecodedCommand::SetOrientation(Orientation::Landscape),
+                DecodedCommand::SetBrightness(0xfe),
+                DecodedCommand::Unknown(0x42),
+            ]
+        );
+        assert!(emu.get_buf().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_display() {
+        assert_eq!(
+            DecodedCommand::DisplayBitmap(Rect::new(1, 2, 3, 4)).to_string(),
+            "DisplayBitmap @1,2+3x4"
+        );
+        assert_eq!(DecodedCommand::ScreenOff.to_string(), "ScreenOff");
+        assert_eq!(DecodedCommand::Unknown(0x42).to_string(), "Unknown 0x42");
+    }
+}
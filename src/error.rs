@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt;
+use std::io;
+
+use crate::{Coord, Rect};
+
+/// Errors returned by screen operations.
+#[derive(Debug)]
+pub enum Error {
+    /// No compatible device was found, with a description of the search.
+    DeviceNotFound(String),
+    /// The device is used by another process, with its PID if known.
+    DeviceBusy(Option<u32>),
+    /// The device answered the hello command with an unexpected response.
+    IncompatibleModel(Vec<u8>),
+    /// The device did not answer in time.
+    Timeout,
+    /// The device did not acknowledge the data sent.
+    NotAcknowledged,
+    /// The device stopped accepting data, with bytes written and expected.
+    PartialWrite(usize, usize),
+    /// A serial port or file operation failed.
+    Io(io::Error),
+    /// The serial port could not be configured.
+    Serial(serialport::Error),
+    /// The font data could not be parsed.
+    FontLoad,
+    /// The image could not be encoded.
+    Encoding(String),
+    /// The image file could not be decoded.
+    Decoding(String),
+    /// The requested area is outside the screen.
+    OutOfBounds(Rect, Coord),
+    /// A framebuffer does not fit the screen, with its length and the
+    /// expected one.
+    FramebufferSize(usize, usize),
+    /// A compositor request or reply is invalid.
+    Protocol(String),
+    /// A configuration file is invalid.
+    Config(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::DeviceNotFound(msg) => write!(f, "device not found: {}", msg),
+            Error::DeviceBusy(Some(pid)) => write!(f, "device busy, held by PID {}", pid),
+            Error::DeviceBusy(None) => write!(f, "device busy"),
+            Error::IncompatibleModel(res) => {
+                write!(f, "incompatible screen model (hello response {:02x?})", res)
+            }
+            Error::Timeout => write!(f, "device timeout"),
+            Error::NotAcknowledged => write!(f, "device did not acknowledge data"),
+            Error::PartialWrite(written, expected) => write!(
+                f,
+                "device stopped accepting data after {} of {} bytes",
+                written, expected
+            ),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Serial(err) => write!(f, "serial port error: {}", err),
+            Error::FontLoad => write!(f, "cannot load font data"),
+            Error::Encoding(msg) => write!(f, "cannot encode image: {}", msg),
+            Error::Decoding(msg) => write!(f, "cannot decode image: {}", msg),
+            Error::OutOfBounds(rect, pos) => {
+                write!(f, "area {} at {} is outside the screen", rect, pos)
+            }
+            Error::FramebufferSize(len, expected) => write!(
+                f,
+                "framebuffer of {} bytes does not match the screen, expected {}",
+                len, expected
+            ),
+            Error::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            Error::Config(msg) => write!(f, "invalid configuration: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Serial(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::TimedOut => Error::Timeout,
+            _ => Error::Io(err),
+        }
+    }
+}
+
+impl From<serialport::Error> for Error {
+    fn from(err: serialport::Error) -> Self {
+        match err.kind() {
+            serialport::ErrorKind::NoDevice => Error::DeviceNotFound(err.description),
+            serialport::ErrorKind::Io(kind) => io::Error::new(kind, err.description).into(),
+            _ => Error::Serial(err),
+        }
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(err: png::EncodingError) -> Self {
+        match err {
+            png::EncodingError::IoError(err) => err.into(),
+            err => Error::Encoding(err.to_string()),
+        }
+    }
+}
+
+impl From<png::DecodingError> for Error {
+    fn from(err: png::DecodingError) -> Self {
+        match err {
+            png::DecodingError::IoError(err) => err.into(),
+            err => Error::Decoding(err.to_string()),
+        }
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Error::Config(err.to_string())
+    }
+}
+
+impl From<serde_norway::Error> for Error {
+    fn from(err: serde_norway::Error) -> Self {
+        Error::Config(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_io_error() {
+        let err: Error = io::Error::new(io::ErrorKind::TimedOut, "timeout").into();
+        assert!(matches!(err, Error::Timeout));
+        let err: Error = io::Error::new(io::ErrorKind::BrokenPipe, "broken").into();
+        assert!(matches!(err, Error::Io(_)));
+    }
+
+    #[test]
+    fn test_from_serial_error() {
+        let err: Error = serialport::Error::new(serialport::ErrorKind::NoDevice, "gone").into();
+        assert!(matches!(err, Error::DeviceNotFound(_)));
+        let err: Error = serialport::Error::new(
+            serialport::ErrorKind::Io(io::ErrorKind::TimedOut),
+            "timeout",
+        )
+        .into();
+        assert!(matches!(err, Error::Timeout));
+        let err: Error =
+            serialport::Error::new(serialport::ErrorKind::InvalidInput, "bad baud rate").into();
+        assert!(matches!(err, Error::Serial(_)));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            Error::IncompatibleModel(vec![1, 1, 1, 1, 1, 2]).to_string(),
+            "incompatible screen model (hello response [01, 01, 01, 01, 01, 02])"
+        );
+        assert_eq!(
+            Error::OutOfBounds(Rect::new(0, 0, 10, 10), Coord::new(400, 0)).to_string(),
+            "area @0,0+10x10 at @400,0 is outside the screen"
+        );
+        assert_eq!(
+            Error::FramebufferSize(10, 20).to_string(),
+            "framebuffer of 10 bytes does not match the screen, expected 20"
+        );
+    }
+}
@@ -1,7 +1,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use crate::colors;
-use crate::{Coord, Image, Rect, Res, Rgba};
+use crate::{Coord, Error, Image, Rect, Res, Rgba};
 
 macro_rules! set_min {
     ($a:expr, $b:expr) => {{
@@ -28,18 +28,12 @@ impl Font<'_> {
         if let Some(font) = rusttype::Font::try_from_vec(data) {
             Ok(Self { font })
         } else {
-            Err("cannot load font data".into())
+            Err(Error::FontLoad)
         }
     }
 
-    pub fn draw(
-        &self,
-        background: &Image,
-        size: f32,
-        color: Rgba,
-        pos: &Coord,
-        msg: &str,
-    ) -> (Image, Rect) {
+    // Lay out a line of text, returning the glyphs and the text image size.
+    fn layout(&self, size: f32, msg: &str) -> (Vec<rusttype::PositionedGlyph<'_>>, usize, usize) {
         let scale = rusttype::Scale { x: size, y: size };
 
         // From rusttype ascii.rs:
@@ -52,14 +46,34 @@ impl Font<'_> {
         let offset = rusttype::point(0.0, v_metrics.ascent);
         let glyphs: Vec<_> = self.font.layout(msg, scale, offset).collect();
 
-        let h = (v_metrics.ascent + v_metrics.descent).ceil() as usize;
+        let h = (v_metrics.ascent - v_metrics.descent).ceil() as usize;
         let w = glyphs // total width of text
             .iter()
             .rev()
             .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
             .next()
             .unwrap_or(0.0)
-            .ceil() as usize; // the text image
+            .ceil() as usize;
+        (glyphs, w, h)
+    }
+
+    /// The size of the text image drawn by [`Font::draw`].
+    pub fn text_size(&self, size: f32, msg: &str) -> (usize, usize) {
+        let (_, w, h) = self.layout(size, msg);
+        (w, h)
+    }
+
+    pub fn draw(
+        &self,
+        background: &Image,
+        size: f32,
+        color: Rgba,
+        pos: &Coord,
+        msg: &str,
+    ) -> (Image, Rect) {
+        let (glyphs, w, h) = self.layout(size, msg);
+
+        // the text image
         let mut text_img = Image {
             buffer: vec![colors::TRANSPARENT; w * h],
             width: w,
@@ -90,6 +104,10 @@ impl Font<'_> {
             }
         }
 
+        // nothing drawn, as for spaces
+        if min_y > max_y {
+            return (text_img, Rect::new(0, 0, 0, 0));
+        }
         let (min_y, max_y) = (min_y as usize, max_y as usize);
 
         let bb_rect = Rect::new(0, min_y, w, max_y - min_y + 1);
@@ -102,3 +120,67 @@ impl Font<'_> {
         (text_img, bb_rect)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A font with a single empty glyph, 500 units wide, 800 units above
+    // the baseline and 200 below.
+    fn font_data() -> Vec<u8> {
+        let mut head = vec![0u8; 54];
+        head[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes()); // version
+        head[12..16].copy_from_slice(&0x5f0f_3cf5u32.to_be_bytes()); // magic
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes()); // units per em
+        let mut hhea = vec![0u8; 36];
+        hhea[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes()); // version
+        hhea[4..6].copy_from_slice(&800i16.to_be_bytes()); // ascender
+        hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes()); // descender
+        hhea[34..36].copy_from_slice(&1u16.to_be_bytes()); // horizontal metrics
+        let hmtx = [500u16.to_be_bytes(), [0, 0]].concat(); // advance, bearing
+        let mut maxp = 0x0000_5000u32.to_be_bytes().to_vec(); // version 0.5
+        maxp.extend_from_slice(&1u16.to_be_bytes()); // glyphs
+
+        let tables = [
+            (b"head", head),
+            (b"hhea", hhea),
+            (b"hmtx", hmtx),
+            (b"maxp", maxp),
+        ];
+        let mut data = 0x0001_0000u32.to_be_bytes().to_vec();
+        data.extend_from_slice(&(tables.len() as u16).to_be_bytes());
+        data.extend_from_slice(&[0; 6]); // search hints
+        let mut offset = data.len() + 16 * tables.len();
+        for (tag, table) in &tables {
+            data.extend_from_slice(*tag);
+            data.extend_from_slice(&0u32.to_be_bytes()); // checksum
+            data.extend_from_slice(&(offset as u32).to_be_bytes());
+            data.extend_from_slice(&(table.len() as u32).to_be_bytes());
+            offset += table.len();
+        }
+        for (_, table) in &tables {
+            data.extend_from_slice(table);
+        }
+        data
+    }
+
+    #[test]
+    fn test_text_height() -> Res<()> {
+        // the descent is negative: the image covers ascent and descent
+        let font = Font::from_data(font_data())?;
+        assert_eq!(font.text_size(20.0, ""), (0, 20));
+        assert_eq!(font.text_size(15.0, ""), (0, 15));
+        Ok(())
+    }
+
+    #[test]
+    fn test_draw_blank() -> Res<()> {
+        // no glyph has a bounding box
+        let font = Font::from_data(font_data())?;
+        let background = Image::new(10, 20);
+        let (_, crop) = font.draw(&background, 20.0, colors::WHITE, &Coord::new(0, 0), "  ");
+        assert_eq!((crop.w, crop.h), (0, 0));
+        assert_eq!(font.text_size(20.0, "  "), (20, 20));
+        Ok(())
+    }
+}
@@ -81,6 +81,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_rect_clip() {
         for tc in vec![
             (Rect::new(5, 10, 15, 20), Rect::new(5, 10, 15, 20)), // fully inside
@@ -1,6 +1,9 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
 
 use crate::colors;
 use crate::{Coord, Rect, Res, Rgba, Screen};
@@ -28,14 +31,78 @@ impl Image {
         Rect::new(0, 0, self.width, self.height)
     }
 
+    /// Decode a PNG image.
+    pub fn read_png<R: Read>(r: R) -> Res<Self> {
+        let mut decoder = png::Decoder::new(r);
+        // 8 bits per channel, without palette
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+        let pixel = |c: &[u8]| match info.color_type {
+            png::ColorType::Rgba => Rgba::new(c[0], c[1], c[2], c[3]),
+            png::ColorType::Rgb => Rgba::new(c[0], c[1], c[2], 255),
+            png::ColorType::GrayscaleAlpha => Rgba::new(c[0], c[0], c[0], c[1]),
+            _ => Rgba::new(c[0], c[0], c[0], 255),
+        };
+        let channels = info.color_type.samples();
+        Ok(Self {
+            width: info.width as usize,
+            height: info.height as usize,
+            buffer: data[..info.buffer_size()]
+                .chunks_exact(channels)
+                .map(pixel)
+                .collect(),
+        })
+    }
+
+    /// Load a PNG image file.
+    pub fn load_png<P: AsRef<Path>>(path: P) -> Res<Self> {
+        Self::read_png(BufReader::new(File::open(path)?))
+    }
+
+    /// Resize the image, averaging the source pixels covered by each
+    /// destination pixel.
+    pub fn scale(&self, width: usize, height: usize) -> Image {
+        let mut scaled = Image::new(width, height);
+        if self.width == 0 || self.height == 0 {
+            return scaled;
+        }
+        // source range [start, end) covered by destination pixel i
+        let span = |i: usize, src: usize, dst: usize| {
+            let start = i * src / dst;
+            (start, max(start + 1, ((i + 1) * src).div_ceil(dst)))
+        };
+        for y in 0..height {
+            let (y0, y1) = span(y, self.height, height);
+            for x in 0..width {
+                let (x0, x1) = span(x, self.width, width);
+                let mut sum = [0usize; 4];
+                for sy in y0..y1 {
+                    for p in &self.buffer[sy * self.width + x0..sy * self.width + x1] {
+                        sum[0] += p.r as usize;
+                        sum[1] += p.g as usize;
+                        sum[2] += p.b as usize;
+                        sum[3] += p.a as usize;
+                    }
+                }
+                let n = (y1 - y0) * (x1 - x0);
+                scaled.buffer[y * width + x] = Rgba::new(
+                    (sum[0] / n) as u8,
+                    (sum[1] / n) as u8,
+                    (sum[2] / n) as u8,
+                    (sum[3] / n) as u8,
+                );
+            }
+        }
+        scaled
+    }
+
     pub fn copy_image(&mut self, image: &Image, crop: &Rect, dest: &Coord) {
-        let crop = crop.clip(
-            min(image.width, self.width - dest.x),
-            min(image.height, self.height - dest.y),
-        );
+        let crop = self.clip_crop(image, crop, dest);
 
         for y in 0..crop.h {
-            let offset = (dest.y + y) * self.width + crop.x + dest.x;
+            let offset = (dest.y + y) * self.width + dest.x;
             let src_offset = (crop.y + y) * image.width + crop.x;
             self.buffer[offset..offset + crop.w]
                 .copy_from_slice(&image.buffer[src_offset..src_offset + crop.w]);
@@ -43,10 +110,7 @@ impl Image {
     }
 
     pub fn blend_image(&mut self, image: &Image, crop: &Rect, dest: &Coord) {
-        let crop = crop.clip(
-            min(image.width, self.width - dest.x),
-            min(image.height, self.height - dest.y),
-        );
+        let crop = self.clip_crop(image, crop, dest);
 
         let mut offset = dest.y * self.width + dest.x;
         let mut src_offset = crop.y * image.width + crop.x;
@@ -74,6 +138,8 @@ impl Image {
     /// * `pos`: the coordinates inside the background image.
     /// * `background`: the background image.
     pub fn blend_to_background(&mut self, crop: &Rect, pos: &Coord, background: &Image) {
+        let crop = background.clip_crop(self, crop, pos);
+
         let mut offset = pos.y * background.width + pos.x;
         let mut src_offset = crop.y * self.width + crop.x;
 
@@ -94,6 +160,18 @@ impl Image {
         Ok(())
     }
 
+    // Clip the crop area to the source image and to this image at the
+    // given destination.
+    fn clip_crop(&self, image: &Image, crop: &Rect, dest: &Coord) -> Rect {
+        if dest.x >= self.width || dest.y >= self.height {
+            return Rect::new(crop.x, crop.y, 0, 0);
+        }
+        crop.clip(
+            min(image.width, crop.x + self.width - dest.x),
+            min(image.height, crop.y + self.height - dest.y),
+        )
+    }
+
     #[inline]
     fn blend_alpha(bg: &mut Rgba, fg: Rgba) {
         // short circuit cases
@@ -122,6 +200,47 @@ mod tests {
         let image = Image::new(20, 30);
         assert_eq!(image.full(), Rect::new(0, 0, 20, 30));
     }
+
+    #[test]
+    fn test_read_png() -> Res<()> {
+        // a 2x1 grayscale image with alpha
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, 2, 1);
+        encoder.set_color(png::ColorType::GrayscaleAlpha);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&[10, 255, 200, 128])?;
+        writer.finish()?;
+
+        let image = Image::read_png(data.as_slice())?;
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(
+            image.buffer,
+            [Rgba::new(10, 10, 10, 255), Rgba::new(200, 200, 200, 128)]
+        );
+
+        assert!(matches!(
+            Image::read_png(&b"not a png"[..]),
+            Err(crate::Error::Decoding(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_scale() {
+        let mut image = Image::new(4, 2);
+        image.buffer[0] = colors::WHITE;
+        image.buffer[1] = colors::WHITE;
+
+        let half = image.scale(2, 1);
+        assert_eq!(half.buffer, [Rgba::new(127, 127, 127, 255), colors::BLACK]);
+
+        let double = image.scale(8, 4);
+        assert_eq!(double.buffer[3], colors::WHITE);
+        assert_eq!(double.buffer[4], colors::BLACK);
+        assert_eq!(double.buffer[8 + 3], colors::WHITE);
+        assert_eq!(double.buffer[16 + 3], colors::BLACK);
+    }
     #[test]
     fn test_copy_image() {
         let image = Image {
@@ -219,6 +338,31 @@ mod tests {
     }
 
     #[test]
+    fn test_copy_image_cropped_clipped() {
+        let image = Image {
+            buffer: vec![
+                Rgba::new(1, 1, 1, 1),
+                Rgba::new(2, 2, 2, 2),
+                Rgba::new(3, 3, 3, 3),
+                Rgba::new(4, 4, 4, 4),
+            ],
+            width: 2,
+            height: 2,
+        };
+
+        let mut background = Image::new(3, 3);
+        background.copy_image(&image, &Rect::new(1, 0, 1, 2), &Coord::new(2, 2));
+        assert_eq!(background.buffer[8], Rgba::new(2, 2, 2, 2));
+        assert_eq!(background.buffer[..8], [colors::BLACK; 8]);
+
+        // nothing to copy outside the image
+        background.copy_image(&image, &image.full(), &Coord::new(3, 0));
+        background.blend_image(&image, &image.full(), &Coord::new(0, 3));
+        assert_eq!(background.buffer[..8], [colors::BLACK; 8]);
+    }
+
+    #[test]
+    #[allow(clippy::useless_vec)]
     fn test_blend_alpha() {
         for tc in vec![
             (
@@ -300,5 +444,10 @@ mod tests {
                 Rgba::new(0x00, 0x00, 0x00, 0xff),
             ]
         );
+
+        // outside of the background
+        let blended = image.clone();
+        image.blend_to_background(&Rect::new(0, 0, 2, 2), &Coord::new(3, 0), &background);
+        assert_eq!(image.buffer, blended.buffer);
     }
 }
@@ -1,26 +1,63 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::error::Error;
 use std::io::Read;
 use std::io::Write;
 
-use crate::screen_rev_a::ScreenRevA;
-
+#[cfg(feature = "async")]
+pub use crate::async_screen::{AsyncScreen, AsyncSerialScreen};
+pub use crate::builder::ScreenBuilder;
+pub use crate::canvas::Canvas;
 pub use crate::colors::Rgba;
+pub use crate::compositor::{default_socket_path, serve_client};
+pub use crate::compositor::{Compositor, CompositorClient, LayerId};
+pub use crate::emulator_rev_a::{DecodedCommand, EmulatorRevA};
+pub use crate::error::Error;
 pub use crate::fonts::Font;
 pub use crate::geometry::{Coord, Rect};
 pub use crate::image::Image;
+pub use crate::monitor::{Align, DeviceConfig, Monitor, MonitorConfig};
+pub use crate::monitor::{WidgetConfig, WidgetKind};
+pub use crate::reconnect::ReconnectingScreen;
+pub use crate::render::Renderer;
+pub use crate::screen_rev_a::ScreenRevA;
+pub use crate::screen_rev_b::ScreenRevB;
+pub use crate::screen_rev_c::ScreenRevC;
+pub use crate::serial_port::{list_devices, Device, SerialPort};
+pub use crate::shared::{ScreenSession, SharedScreen};
+pub use crate::trace::{dump, load_trace, read_trace, replay};
+pub use crate::trace::{Direction, RecordingPort, TraceEvent};
+pub use crate::virtual_screen::{PixelFormat, VirtualScreen};
 
+#[cfg(feature = "async")]
+mod async_screen;
+mod builder;
+mod canvas;
 pub mod colors;
+mod compositor;
+mod damage;
+mod detect;
+mod emulator_rev_a;
+mod error;
 mod fonts;
 mod geometry;
 mod image;
+mod lock;
+mod monitor;
+mod reconnect;
+mod render;
 mod screen_rev_a;
+mod screen_rev_b;
+mod screen_rev_c;
+pub mod sensors;
 mod serial_port;
+mod shared;
+mod theme;
+mod trace;
+mod virtual_screen;
 
-type Res<T> = Result<T, Box<dyn Error>>;
+type Res<T> = Result<T, Error>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Orientation {
     Portrait = 0,
     Landscape = 1,
@@ -28,7 +65,32 @@ pub enum Orientation {
     ReverseLandscape = 3,
 }
 
-pub trait Screen {
+impl std::str::FromStr for Orientation {
+    type Err = String;
+
+    /// Parse an orientation name, such as "landscape" or "reverse-portrait".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "portrait" => Ok(Orientation::Portrait),
+            "landscape" => Ok(Orientation::Landscape),
+            "reverse-portrait" => Ok(Orientation::ReversePortrait),
+            "reverse-landscape" => Ok(Orientation::ReverseLandscape),
+            _ => Err(format!("unknown orientation '{}'", s)),
+        }
+    }
+}
+
+/// Screen hardware revisions, each one with its own protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Revision {
+    A, // Turing Smart Screen 3.5"
+    B, // XuanFang 3.5"
+    C, // Turing Smart Screen 2.1", 5" and 8.8"
+}
+
+/// A screen backend. Backends are `Send`, see [`SharedScreen`] to share one
+/// between threads.
+pub trait Screen: Send {
     fn screen_size(&self) -> (usize, usize);
     fn write(&mut self, data: &[u8]) -> Res<usize>;
     fn read(&mut self, n: usize) -> Res<Vec<u8>>;
@@ -39,12 +101,77 @@ pub trait Screen {
     fn set_orientation(&mut self, o: Orientation) -> Res<()>;
     fn set_brightness(&mut self, level: usize) -> Res<()>;
     fn display_image(&mut self, img888: &Image, rect: &Rect, pos: &Coord) -> Res<()>;
+    /// The framebuffer mirror of the screen contents, in a backend specific format.
+    fn framebuffer(&self) -> &[u8];
+    /// Replace the framebuffer mirror and send it to the screen.
+    fn restore_framebuffer(&mut self, fb: &[u8]) -> Res<()>;
+    /// A description of the screen model, complete after [`Screen::init`].
+    fn model(&self) -> String {
+        "unknown screen".to_string()
+    }
 }
 
+/// Create a new screen.
+///
+/// Use "AUTO" as the port name to search all attached devices for a
+/// supported screen and select the matching driver. See [`ScreenBuilder`]
+/// for more options.
 pub fn new(portname: &str) -> Res<Box<dyn Screen>> {
-    Ok(Box::new(ScreenRevA::new(portname)?))
+    detect::open(portname)
 }
 
-pub trait ScreenPort: Read + Write {
+pub trait ScreenPort: Read + Write + Send {
     fn get_buf(&self) -> Vec<u8>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Cursor};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    pub type FakePort = Cursor<Vec<u8>>;
+
+    impl ScreenPort for FakePort {
+        fn get_buf(&self) -> Vec<u8> {
+            self.get_ref().to_vec()
+        }
+    }
+
+    // A port keeping the data written, whose writes fail while `fail` is
+    // set. Reads return an acknowledgement.
+    #[derive(Clone, Default)]
+    pub struct FailingPort {
+        pub written: Arc<Mutex<Vec<u8>>>,
+        pub fail: Arc<AtomicBool>,
+    }
+
+    impl Read for FailingPort {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(2);
+            buf[..n].copy_from_slice(&b"ok"[..n]);
+            Ok(n)
+        }
+    }
+
+    impl Write for FailingPort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ScreenPort for FailingPort {
+        fn get_buf(&self) -> Vec<u8> {
+            self.written.lock().unwrap().clone()
+        }
+    }
+}
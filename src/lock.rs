@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::env;
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::serial_port;
+use crate::{Error, Res};
+
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// An advisory lock on a device, shared by all the processes using this
+/// library. The lock is released when dropped.
+///
+/// The lock file is kept in `/run/lock`, or the runtime directory of the
+/// user when not writable, named after the USB serial number of the device
+/// or the port name, and contains the PID of the owner.
+#[derive(Debug)]
+pub(crate) struct DeviceLock {
+    _file: File, // the flock is held while the file is open
+}
+
+// The lock name of a port: the serial number of the device, or the port
+// name when unknown.
+fn lock_key(portname: &str) -> String {
+    let serial = serial_port::list_devices()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|d| d.port_name == portname)
+        .and_then(|d| d.serial)
+        .filter(|s| !s.is_empty());
+    let key = match serial {
+        Some(serial) => serial,
+        None => {
+            // follow links such as /dev/serial/by-id to the device
+            let path = fs::canonicalize(portname).unwrap_or_else(|_| PathBuf::from(portname));
+            path.to_string_lossy().trim_start_matches('/').to_string()
+        }
+    };
+    key.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '_' | '-' => c,
+            _ => '-',
+        })
+        .collect()
+}
+
+fn writable(dir: &Path) -> bool {
+    let Ok(dir) = CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(dir.as_ptr(), libc::W_OK) == 0 }
+}
+
+// The directory of the lock files: the system lock directory, shared by
+// all the users, or the private runtime directory of the user.
+fn default_lock_dir() -> PathBuf {
+    let runtime = env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+    [Some(PathBuf::from("/run/lock")), runtime]
+        .into_iter()
+        .flatten()
+        .find(|dir| dir.is_dir() && writable(dir))
+        .unwrap_or_else(env::temp_dir)
+}
+
+fn try_flock(file: &File) -> io::Result<bool> {
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Ok(true);
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EWOULDBLOCK) => return Ok(false),
+            Some(libc::EINTR) => continue,
+            _ => return Err(err),
+        }
+    }
+}
+
+// Open a lock file without following links, readable and writable by all
+// the users of the device.
+fn open(path: &Path) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o666)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path);
+    match file {
+        Ok(file) => {
+            // the mode is restricted by the umask; only the owner can fix it
+            let _ = file.set_permissions(fs::Permissions::from_mode(0o666));
+            Ok(file)
+        }
+        // created by another user with a restrictive mode: a read-only
+        // file is enough to lock it
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(path),
+        Err(err) => Err(err),
+    }
+}
+
+fn owner(file: &mut File) -> Option<u32> {
+    let mut pid = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut pid).ok()?;
+    pid.trim().parse().ok()
+}
+
+impl DeviceLock {
+    /// Lock the device on a port, waiting up to `wait` for another process
+    /// to release it.
+    pub fn acquire(portname: &str, wait: Duration) -> Res<Self> {
+        Self::acquire_in(&default_lock_dir(), &lock_key(portname), wait)
+    }
+
+    pub(crate) fn acquire_in(dir: &Path, key: &str, wait: Duration) -> Res<Self> {
+        let path = dir.join(format!("turing-screen-{}.lock", key));
+        let mut file = open(&path)?;
+
+        let start = Instant::now();
+        while !try_flock(&file)? {
+            if start.elapsed() >= wait {
+                return Err(Error::DeviceBusy(owner(&mut file)));
+            }
+            thread::sleep(RETRY_DELAY);
+        }
+
+        log::debug!("locked {}", path.display());
+        let pid = file
+            .set_len(0)
+            .and_then(|_| file.rewind())
+            .and_then(|_| writeln!(file, "{}", process::id()));
+        if let Err(err) = pid {
+            log::debug!("cannot write the PID to {}: {}", path.display(), err);
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("turing-lock-test-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_lock_busy() -> Res<()> {
+        let dir = lock_dir("busy");
+        let lock = DeviceLock::acquire_in(&dir, "USB35INCHIPSV2", Duration::ZERO)?;
+        let err = DeviceLock::acquire_in(&dir, "USB35INCHIPSV2", Duration::ZERO).unwrap_err();
+        assert!(matches!(err, Error::DeviceBusy(Some(pid)) if pid == process::id()));
+        assert_eq!(
+            err.to_string(),
+            format!("device busy, held by PID {}", process::id())
+        );
+
+        // other devices are not affected
+        DeviceLock::acquire_in(&dir, "20080411", Duration::ZERO)?;
+
+        drop(lock);
+        DeviceLock::acquire_in(&dir, "USB35INCHIPSV2", Duration::ZERO)?;
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_wait() -> Res<()> {
+        let dir = lock_dir("wait");
+        let lock = DeviceLock::acquire_in(&dir, "dev-ttyACM0", Duration::ZERO)?;
+        let release = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            drop(lock);
+        });
+        DeviceLock::acquire_in(&dir, "dev-ttyACM0", Duration::from_secs(5))?;
+        release.join().unwrap();
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_symlink() -> Res<()> {
+        let dir = lock_dir("symlink");
+        let target = dir.join("target");
+        fs::write(&target, "data")?;
+        std::os::unix::fs::symlink(&target, dir.join("turing-screen-dev-ttyACM0.lock"))?;
+        assert!(DeviceLock::acquire_in(&dir, "dev-ttyACM0", Duration::ZERO).is_err());
+        assert_eq!(fs::read_to_string(&target)?, "data");
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_key() {
+        assert_eq!(lock_key("/nonexistent/tty:0"), "nonexistent-tty-0");
+    }
+}
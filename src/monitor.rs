@@ -0,0 +1,1044 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::colors;
+use crate::sensors::{self, Sensors};
+use crate::{Canvas, Coord, Error, Font, Image, Rect, Res, Rgba};
+use crate::{Revision, Screen, ScreenBuilder, VirtualScreen};
+
+const DEFAULT_INTERVAL: f64 = 1.0;
+const DEFAULT_FONT: &str = "default";
+const DEFAULT_FONT_SIZE: f32 = 20.0;
+const DEFAULT_CLOCK_FORMAT: &str = "%H:%M:%S";
+
+/// Configuration of a [`Monitor`], usually read from a TOML file.
+///
+/// ```toml
+/// background = "#000000"
+/// background_image = "background.png"
+///
+/// [device]
+/// port = "AUTO"
+/// orientation = "landscape"
+/// brightness = 128
+///
+/// [fonts]
+/// default = "fonts/DejaVuSans.ttf"
+///
+/// [[widget]]
+/// type = "text"
+/// source = "clock"
+/// format = "%H:%M"
+/// x = 10
+/// y = 10
+/// size = 40
+///
+/// [[widget]]
+/// type = "text"
+/// source = "cpu.percent"
+/// format = "CPU {:.0}%"
+/// x = 10
+/// y = 60
+///
+/// [[widget]]
+/// type = "bar"
+/// source = "file"
+/// path = "/sys/class/thermal/thermal_zone0/temp"
+/// scale = 0.001
+/// x = 10
+/// y = 90
+/// width = 200
+/// height = 10
+/// interval = 2.0
+/// ```
+///
+/// Relative paths are relative to the directory of the configuration file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MonitorConfig {
+    /// Background color, black by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    /// Background image, drawn over the background color.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_image: Option<String>,
+    #[serde(default)]
+    pub device: DeviceConfig,
+    /// Font files by name, widgets use the font named "default" unless
+    /// they select another one.
+    #[serde(default)]
+    pub fonts: BTreeMap<String, String>,
+    #[serde(default, rename = "widget")]
+    pub widgets: Vec<WidgetConfig>,
+    #[serde(skip)]
+    pub(crate) base_dir: PathBuf,
+}
+
+/// Device selection and startup settings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// Serial port path, or "AUTO" to search all attached devices.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
+    /// USB serial number of the device.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    /// Hardware revision: A, B or C.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WidgetKind {
+    /// A line of text.
+    #[default]
+    Text,
+    /// A PNG image.
+    Image,
+    /// A horizontal bar showing a value between `min` and `max`.
+    Bar,
+    /// A ring around `x`, `y` showing a value between `min` and `max`.
+    Radial,
+}
+
+/// Horizontal text alignment, within the widget width if set, else
+/// relative to the widget position.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// A widget placed on the screen.
+///
+/// The displayed value comes from a source: "clock" for the local time
+/// formatted with `format` as in strftime, "file" for the first line of the
+/// file at `path`, "command" for the first line printed by the shell
+/// `command`, or the name of a system statistic such as "cpu.percent" (see
+/// [`Sensors::value`](crate::sensors::Sensors::value), `path` selects the
+/// file system of the disk usage). Without a source, the widget displays
+/// `text`. Numeric values are multiplied by `scale` if set.
+///
+/// Text widgets substitute the value in `format` at the first "{}",
+/// "{:.N}" for a number with N decimals, or "{:bytes}" and "{:rate}" for a
+/// number of bytes or bytes per second with a binary unit, as "1.5 MiB/s".
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WidgetConfig {
+    #[serde(rename = "type")]
+    pub kind: WidgetKind,
+    pub x: usize,
+    pub y: usize,
+    /// Size of the widget area, required for bars. Images are scaled to it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<usize>,
+    /// Refresh interval in seconds, 1 second by default for widgets with
+    /// a source. Widgets without a source are drawn once, as part of the
+    /// background.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// Image file, file read by the "file" source, or mount point of the
+    /// disk usage.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f64>,
+    /// Font name from the fonts table, or font file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// Background color of the widget area, the screen background is
+    /// restored by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub align: Option<Align>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// Bar outline color.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outline: Option<String>,
+    /// Outer radius and thickness of a ring.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub radius: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thickness: Option<usize>,
+    /// Angles of the ring ends in degrees, clockwise from 3 o'clock. The
+    /// ring is full by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub angle_start: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub angle_end: Option<f64>,
+    /// Whether the ring fills clockwise from its start, true by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clockwise: Option<bool>,
+    /// Number of segments of a ring, and gap between them in degrees.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub steps: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gap: Option<f64>,
+}
+
+impl MonitorConfig {
+    /// Parse a configuration, with relative paths relative to `base_dir`.
+    pub fn from_toml<P: AsRef<Path>>(s: &str, base_dir: P) -> Res<Self> {
+        let mut config: Self = toml::from_str(s)?;
+        config.base_dir = base_dir.as_ref().to_path_buf();
+        Ok(config)
+    }
+
+    /// Read a configuration file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Res<Self> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path)?;
+        Self::from_toml(&s, path.parent().unwrap_or(Path::new(".")))
+    }
+
+    /// Format the configuration as TOML.
+    pub fn to_toml(&self) -> Res<String> {
+        toml::to_string(self).map_err(|err| Error::Config(err.to_string()))
+    }
+
+    /// A builder opening the configured device.
+    pub fn screen_builder(&self) -> Res<ScreenBuilder> {
+        let dev = &self.device;
+        let mut builder = ScreenBuilder::new().init(true);
+        if let Some(port) = dev.port.as_deref().filter(|p| *p != "AUTO") {
+            builder = builder.port(port);
+        }
+        if let Some(serial) = &dev.serial {
+            builder = builder.serial_number(serial);
+        }
+        if let Some(rev) = &dev.revision {
+            builder = builder.revision(match rev.to_ascii_uppercase().as_str() {
+                "A" => Revision::A,
+                "B" => Revision::B,
+                "C" => Revision::C,
+                _ => return Err(Error::Config(format!("unknown revision '{}'", rev))),
+            });
+        }
+        if let Some(o) = &dev.orientation {
+            builder = builder.orientation(o.parse().map_err(Error::Config)?);
+        }
+        if let Some(level) = dev.brightness {
+            builder = builder.brightness(level);
+        }
+        Ok(builder)
+    }
+
+    fn resolve(&self, path: &str) -> PathBuf {
+        self.base_dir.join(path)
+    }
+}
+
+fn parse_color(value: Option<&String>, default: Rgba) -> Res<Rgba> {
+    match value {
+        Some(s) => {
+            colors::from_hex(s).ok_or_else(|| Error::Config(format!("invalid color '{}'", s)))
+        }
+        None => Ok(default),
+    }
+}
+
+// The local time formatted as in strftime.
+fn format_time(format: &str, time: SystemTime) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as libc::time_t;
+    let mut buf = [0u8; 256];
+    let n = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&secs, &mut tm);
+        libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm)
+    };
+    String::from_utf8_lossy(&buf[..n]).into_owned()
+}
+
+// Substitute a value in a format at the first "{}", "{:.N}" to format a
+// number with N decimals, or "{:bytes}" and "{:rate}" to format a number of
+// bytes or bytes per second with a binary unit.
+fn format_value(format: &str, value: &str) -> String {
+    let Some(start) = format.find('{') else {
+        return format.to_string();
+    };
+    let Some(len) = format[start..].find('}') else {
+        return format.to_string();
+    };
+    let spec = &format[start + 1..start + len];
+    let value = match (spec.strip_prefix(":."), value.parse::<f64>()) {
+        (Some(prec), Ok(v)) => match prec.parse() {
+            Ok(prec) => format!("{:.*}", prec, v),
+            Err(_) => value.to_string(),
+        },
+        (None, Ok(v)) if spec == ":bytes" => sensors::format_bytes(v),
+        (None, Ok(v)) if spec == ":rate" => sensors::format_rate(v),
+        _ => value.to_string(),
+    };
+    format!(
+        "{}{}{}",
+        &format[..start],
+        value,
+        &format[start + len + 1..]
+    )
+}
+
+// The first line of a text, trimmed.
+fn first_line(s: &str) -> String {
+    s.lines().next().unwrap_or("").trim().to_string()
+}
+
+enum Source {
+    Static(String),
+    Clock(String),
+    File(PathBuf),
+    Command(String),
+    Sensor(String, Option<PathBuf>),
+}
+
+impl Source {
+    fn read(&self, sensors: &mut Sensors) -> Res<String> {
+        match self {
+            Source::Static(text) => Ok(text.clone()),
+            Source::Clock(format) => Ok(format_time(format, SystemTime::now())),
+            Source::File(path) => Ok(first_line(&fs::read_to_string(path)?)),
+            Source::Command(command) => {
+                let output = Command::new("sh").arg("-c").arg(command).output()?;
+                if !output.status.success() {
+                    return Err(io::Error::other(format!(
+                        "'{}' failed: {}",
+                        command, output.status
+                    ))
+                    .into());
+                }
+                Ok(first_line(&String::from_utf8_lossy(&output.stdout)))
+            }
+            Source::Sensor(name, path) => sensors.text(name, path.as_deref()),
+        }
+    }
+}
+
+enum Content {
+    Text {
+        font: usize,
+        size: f32,
+        color: Rgba,
+        align: Align,
+        format: String,
+    },
+    Image(Image),
+    Bar {
+        min: f64,
+        max: f64,
+        color: Rgba,
+        outline: Option<Rgba>,
+    },
+    Radial(Ring),
+}
+
+struct Ring {
+    min: f64,
+    max: f64,
+    color: Rgba,
+    radius: usize,
+    thickness: usize,
+    start: f64,
+    span: f64,
+    clockwise: bool,
+    steps: Option<(usize, f64)>, // number of segments and gap in degrees
+}
+
+impl Ring {
+    // Draw the ring around `center`, filled up to `value`.
+    fn draw(
+        &self,
+        canvas: &mut Canvas,
+        background: &Image,
+        bg_color: Option<Rgba>,
+        center: &Coord,
+        value: f64,
+    ) {
+        let r = self.radius;
+        let (width, height) = canvas.size();
+        let area = Rect::new(
+            center.x.saturating_sub(r),
+            center.y.saturating_sub(r),
+            center.x.min(r) + r,
+            center.y.min(r) + r,
+        )
+        .clip(width, height);
+        let mut image = Image::new(area.w, area.h);
+        image.copy_image(canvas.image(), &area, &Coord::new(0, 0));
+
+        let level = ((value - self.min) / (self.max - self.min)).clamp(0.0, 1.0);
+        let (outer, inner) = (r as f64, r.saturating_sub(self.thickness) as f64);
+        for y in 0..area.h {
+            for x in 0..area.w {
+                let dx = (area.x + x) as f64 + 0.5 - center.x as f64;
+                let dy = (area.y + y) as f64 + 0.5 - center.y as f64;
+                let d = dx.hypot(dy);
+                if d > outer || d < inner {
+                    continue;
+                }
+                // angle from the start of the ring
+                let a = dy.atan2(dx).to_degrees();
+                let a = match self.clockwise {
+                    true => a - self.start,
+                    false => self.start - a,
+                }
+                .rem_euclid(360.0);
+                if a > self.span {
+                    continue;
+                }
+                let filled = match self.steps {
+                    Some((steps, gap)) => {
+                        let seg = self.span / steps as f64;
+                        let i = (a / seg).floor();
+                        if a - i * seg > seg - gap {
+                            continue;
+                        }
+                        i < (level * steps as f64).round()
+                    }
+                    None => a < self.span * level,
+                };
+                image.buffer[y * area.w + x] = match (filled, bg_color) {
+                    (true, _) => self.color,
+                    (false, Some(color)) => color,
+                    (false, None) => {
+                        background.buffer[(area.y + y) * background.width + area.x + x]
+                    }
+                };
+            }
+        }
+        canvas.draw_image(&image, &image.full(), &Coord::new(area.x, area.y));
+    }
+}
+
+struct Widget {
+    pos: Coord,
+    area: Option<Rect>,
+    background: Option<Rgba>,
+    source: Source,
+    scale: Option<f64>,
+    content: Content,
+    interval: Option<Duration>,
+    next: Option<Instant>,
+    last: Option<Rect>, // the area drawn last time
+}
+
+// Restore the background of an area.
+fn restore(canvas: &mut Canvas, background: &Image, r: &Rect, color: Option<Rgba>) {
+    match color {
+        Some(color) => canvas.fill_rect(r, color),
+        None => canvas.draw_image(background, r, &Coord::new(r.x, r.y)),
+    }
+}
+
+impl Widget {
+    fn value(&self, sensors: &mut Sensors) -> String {
+        let value = match self.source.read(sensors) {
+            Ok(value) => value,
+            Err(err) => {
+                log::warn!("cannot read widget value: {}", err);
+                return "?".to_string();
+            }
+        };
+        match (self.scale, value.parse::<f64>()) {
+            (Some(scale), Ok(v)) => (v * scale).to_string(),
+            _ => value,
+        }
+    }
+
+    fn draw(
+        &mut self,
+        canvas: &mut Canvas,
+        background: &Image,
+        fonts: &[Font],
+        sensors: &mut Sensors,
+    ) {
+        if let Some(r) = self.last.take() {
+            restore(canvas, background, &r, self.background);
+        }
+        if let Some(r) = &self.area {
+            restore(canvas, background, r, self.background);
+        }
+
+        match &self.content {
+            Content::Text {
+                font,
+                size,
+                color,
+                align,
+                format,
+            } => {
+                let msg = format_value(format, &self.value(sensors));
+                if msg.trim().is_empty() {
+                    return;
+                }
+                let font = &fonts[*font];
+                let (w, _) = font.text_size(*size, &msg);
+                let x = match (align, self.area.as_ref()) {
+                    (Align::Left, _) => self.pos.x,
+                    (Align::Center, Some(r)) => r.x + r.w.saturating_sub(w) / 2,
+                    (Align::Right, Some(r)) => r.x + r.w.saturating_sub(w),
+                    (Align::Center, None) => self.pos.x.saturating_sub(w / 2),
+                    (Align::Right, None) => self.pos.x.saturating_sub(w),
+                };
+                let pos = Coord::new(x, self.pos.y);
+                if x >= canvas.size().0 || pos.y >= canvas.size().1 {
+                    return;
+                }
+                let (image, crop) = font.draw(canvas.image(), *size, *color, &pos, &msg);
+                // the glyph bounding box may extend below the text image
+                let crop = crop.clip(image.width, image.height);
+                canvas.draw_image(&image, &crop, &pos);
+                self.last = Some(Rect::new(pos.x, pos.y, crop.w, crop.h));
+            }
+            Content::Image(image) => {
+                canvas.blend_image(image, &image.full(), &self.pos);
+            }
+            Content::Bar {
+                min,
+                max,
+                color,
+                outline,
+            } => {
+                let Some(r) = &self.area else { return };
+                let value = self.value(sensors).parse().unwrap_or(*min);
+                let level = ((value - min) / (max - min)).clamp(0.0, 1.0);
+                let w = (r.w as f64 * level).round() as usize;
+                canvas.fill_rect(&Rect::new(r.x, r.y, w, r.h), *color);
+                if let Some(outline) = outline {
+                    canvas.fill_rect(&Rect::new(r.x, r.y, r.w, 1), *outline);
+                    canvas.fill_rect(&Rect::new(r.x, r.y + r.h - 1, r.w, 1), *outline);
+                    canvas.fill_rect(&Rect::new(r.x, r.y, 1, r.h), *outline);
+                    canvas.fill_rect(&Rect::new(r.x + r.w - 1, r.y, 1, r.h), *outline);
+                }
+            }
+            Content::Radial(ring) => {
+                let value = self.value(sensors).parse().unwrap_or(ring.min);
+                ring.draw(canvas, background, self.background, &self.pos, value);
+            }
+        }
+    }
+}
+
+/// A system monitor rendering the widgets of a configuration.
+///
+/// Each widget is redrawn at its own interval, and the modified areas
+/// are sent to the screen after each update.
+pub struct Monitor {
+    canvas: Canvas,
+    background_color: Rgba,
+    background_image: Option<Image>,
+    background: Image,
+    fonts: Vec<Font<'static>>,
+    widgets: Vec<Widget>,
+    sensors: Sensors,
+}
+
+impl Monitor {
+    /// Load the fonts and images of a configuration and draw the
+    /// background on the screen.
+    pub fn new(config: &MonitorConfig, screen: Box<dyn Screen>) -> Res<Self> {
+        let background_color = parse_color(config.background.as_ref(), colors::BLACK)?;
+        let background_image = match &config.background_image {
+            Some(path) => Some(load_image(&config.resolve(path))?),
+            None => None,
+        };
+
+        let mut fonts = Vec::new();
+        let mut font_names: BTreeMap<String, usize> = BTreeMap::new();
+        let mut widgets = Vec::new();
+        for (i, w) in config.widgets.iter().enumerate() {
+            let widget = Self::widget(config, w, &mut fonts, &mut font_names).map_err(|err| {
+                let msg = match err {
+                    Error::Config(msg) => msg,
+                    err => err.to_string(),
+                };
+                Error::Config(format!("widget {}: {}", i + 1, msg))
+            })?;
+            widgets.push(widget);
+        }
+
+        let mut monitor = Self {
+            canvas: Canvas::new(screen),
+            background_color,
+            background_image,
+            background: Image::new(0, 0),
+            fonts,
+            widgets,
+            sensors: Sensors::new(),
+        };
+        monitor.reset();
+        Ok(monitor)
+    }
+
+    fn widget(
+        config: &MonitorConfig,
+        w: &WidgetConfig,
+        fonts: &mut Vec<Font<'static>>,
+        font_names: &mut BTreeMap<String, usize>,
+    ) -> Res<Widget> {
+        let area = match (w.width, w.height) {
+            (Some(width), Some(height)) => Some(Rect::new(w.x, w.y, width, height)),
+            _ => None,
+        };
+        let missing = |key: &str| Error::Config(format!("missing '{}'", key));
+
+        let source = match w.source.as_deref() {
+            None => Source::Static(w.text.clone().unwrap_or_default()),
+            Some("clock") => Source::Clock(
+                w.format
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_CLOCK_FORMAT.to_string()),
+            ),
+            Some("file") => {
+                Source::File(config.resolve(w.path.as_ref().ok_or_else(|| missing("path"))?))
+            }
+            Some("command") => {
+                Source::Command(w.command.clone().ok_or_else(|| missing("command"))?)
+            }
+            Some(name) if Sensors::is_value(name) => {
+                Source::Sensor(name.to_string(), w.path.as_ref().map(PathBuf::from))
+            }
+            Some(source) => return Err(Error::Config(format!("unknown source '{}'", source))),
+        };
+        let interval = match (&source, w.interval) {
+            (Source::Static(_), _) => None,
+            (_, Some(secs)) if secs.is_finite() && secs > 0.0 => Some(secs),
+            (_, Some(secs)) => return Err(Error::Config(format!("invalid interval {}", secs))),
+            (_, None) => Some(DEFAULT_INTERVAL),
+        };
+
+        let content = match w.kind {
+            WidgetKind::Text => {
+                let name = w.font.as_deref().unwrap_or(DEFAULT_FONT);
+                let font = match font_names.get(name) {
+                    Some(&font) => font,
+                    None => {
+                        let path = match config.fonts.get(name) {
+                            Some(path) => config.resolve(path),
+                            None if w.font.is_none() => return Err(missing("font")),
+                            None => config.resolve(name),
+                        };
+                        let data = fs::read(&path)
+                            .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))?;
+                        fonts.push(Font::from_data(data)?);
+                        font_names.insert(name.to_string(), fonts.len() - 1);
+                        fonts.len() - 1
+                    }
+                };
+                // the clock source does the formatting
+                let format = match &source {
+                    Source::Clock(_) => "{}".to_string(),
+                    _ => w.format.clone().unwrap_or_else(|| "{}".to_string()),
+                };
+                Content::Text {
+                    font,
+                    size: w.size.unwrap_or(DEFAULT_FONT_SIZE),
+                    color: parse_color(w.color.as_ref(), colors::WHITE)?,
+                    align: w.align.unwrap_or_default(),
+                    format,
+                }
+            }
+            WidgetKind::Image => {
+                let path = config.resolve(w.path.as_ref().ok_or_else(|| missing("path"))?);
+                let image = load_image(&path)?;
+                match (w.width, w.height) {
+                    (Some(width), Some(height)) => Content::Image(image.scale(width, height)),
+                    _ => Content::Image(image),
+                }
+            }
+            WidgetKind::Bar => {
+                let Some(r) = &area else {
+                    return Err(Error::Config("bars need a width and a height".to_string()));
+                };
+                if r.w == 0 || r.h == 0 {
+                    return Err(Error::Config("empty bar".to_string()));
+                }
+                let (min, max) = range(w)?;
+                Content::Bar {
+                    min,
+                    max,
+                    color: parse_color(w.color.as_ref(), colors::WHITE)?,
+                    outline: match &w.outline {
+                        Some(_) => Some(parse_color(w.outline.as_ref(), colors::WHITE)?),
+                        None => None,
+                    },
+                }
+            }
+            WidgetKind::Radial => {
+                let (min, max) = range(w)?;
+                let radius = w.radius.ok_or_else(|| missing("radius"))?;
+                let start = w.angle_start.unwrap_or(0.0);
+                let end = w.angle_end.unwrap_or(start);
+                let clockwise = w.clockwise.unwrap_or(true);
+                let span = match clockwise {
+                    true => end - start,
+                    false => start - end,
+                }
+                .rem_euclid(360.0);
+                Content::Radial(Ring {
+                    min,
+                    max,
+                    color: parse_color(w.color.as_ref(), colors::WHITE)?,
+                    radius,
+                    thickness: w.thickness.unwrap_or(radius),
+                    start,
+                    span: if span == 0.0 { 360.0 } else { span },
+                    clockwise,
+                    steps: w
+                        .steps
+                        .filter(|n| *n > 0)
+                        .map(|n| (n, w.gap.unwrap_or(0.0))),
+                })
+            }
+        };
+
+        Ok(Widget {
+            pos: Coord::new(w.x, w.y),
+            area,
+            background: match &w.background {
+                Some(_) => Some(parse_color(w.background.as_ref(), colors::BLACK)?),
+                None => None,
+            },
+            source,
+            scale: w.scale,
+            content,
+            interval: interval.map(Duration::from_secs_f64),
+            next: None,
+            last: None,
+        })
+    }
+
+    /// Close the screen before reopening the device, which is locked while
+    /// open. The monitor draws nothing until a screen is set.
+    pub fn take_screen(&mut self) -> Box<dyn Screen> {
+        let placeholder = Canvas::new(Box::new(VirtualScreen::new(0, 0)));
+        std::mem::replace(&mut self.canvas, placeholder).into_screen()
+    }
+
+    /// Use a new screen, after reopening the device.
+    pub fn set_screen(&mut self, screen: Box<dyn Screen>) {
+        self.canvas = Canvas::new(screen);
+        self.reset();
+    }
+
+    // Draw the background with the static widgets, and schedule the
+    // other widgets. The static widgets are part of the background
+    // restored under the other ones.
+    fn reset(&mut self) {
+        let (width, height) = self.canvas.size();
+        self.background = Image::new(width, height);
+        self.background.buffer.fill(self.background_color);
+        if let Some(image) = &self.background_image {
+            self.background
+                .blend_image(image, &image.full(), &Coord::new(0, 0));
+        }
+        let full = self.background.full();
+        self.canvas
+            .draw_image(&self.background, &full, &Coord::new(0, 0));
+
+        let now = Instant::now();
+        for w in &mut self.widgets {
+            w.last = None;
+            w.next = w.interval.map(|_| now);
+            if w.interval.is_none() {
+                w.draw(
+                    &mut self.canvas,
+                    &self.background,
+                    &self.fonts,
+                    &mut self.sensors,
+                );
+            }
+        }
+        self.background = self.canvas.image().clone();
+    }
+
+    /// Redraw the widgets due at `now` and send the changes to the screen,
+    /// returning the time of the next update.
+    pub fn update(&mut self, now: Instant) -> Res<Option<Instant>> {
+        let Self {
+            canvas,
+            background,
+            fonts,
+            widgets,
+            sensors,
+            ..
+        } = self;
+        for w in widgets.iter_mut() {
+            let Some(due) = w.next.filter(|due| *due <= now) else {
+                continue;
+            };
+            w.draw(canvas, background, fonts, sensors);
+            w.next = w
+                .interval
+                .map(|d| if due + d > now { due + d } else { now + d });
+        }
+        if canvas.is_dirty() {
+            canvas.commit()?;
+        }
+        Ok(widgets.iter().filter_map(|w| w.next).min())
+    }
+
+    /// Update the screen until an error occurs.
+    pub fn run(&mut self) -> Res<()> {
+        loop {
+            let now = Instant::now();
+            match self.update(now)? {
+                Some(next) => thread::sleep(next.saturating_duration_since(Instant::now())),
+                None => thread::sleep(Duration::from_secs(3600)),
+            }
+        }
+    }
+}
+
+// The value range of a bar or ring.
+fn range(w: &WidgetConfig) -> Res<(f64, f64)> {
+    let (min, max) = (w.min.unwrap_or(0.0), w.max.unwrap_or(100.0));
+    if min >= max {
+        return Err(Error::Config(format!("invalid range {} to {}", min, max)));
+    }
+    Ok((min, max))
+}
+
+fn load_image(path: &Path) -> Res<Image> {
+    Image::load_png(path).map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PixelFormat;
+    use std::env;
+    use std::process;
+
+    const BLUE: Rgba = Rgba::new(0, 0, 0x80, 0xff);
+    const RED: Rgba = Rgba::new(0xff, 0, 0, 0xff);
+
+    #[test]
+    fn test_config() -> Res<()> {
+        let config = MonitorConfig::from_toml(
+            r##"
+            background = "#000080"
+
+            [device]
+            port = "/dev/ttyACM0"
+            orientation = "landscape"
+            brightness = 20
+
+            [fonts]
+            default = "fonts/DejaVuSans.ttf"
+
+            [[widget]]
+            type = "text"
+            source = "clock"
+            format = "%H:%M"
+            x = 10
+            y = 20
+            align = "right"
+
+            [[widget]]
+            type = "bar"
+            source = "command"
+            command = "echo 50"
+            x = 0
+            y = 100
+            width = 200
+            height = 10
+            "##,
+            "/etc/turing",
+        )?;
+        assert_eq!(config.background.as_deref(), Some("#000080"));
+        assert_eq!(config.device.brightness, Some(20));
+        assert_eq!(
+            config.resolve(&config.fonts["default"]),
+            PathBuf::from("/etc/turing/fonts/DejaVuSans.ttf")
+        );
+        assert_eq!(config.widgets.len(), 2);
+        assert_eq!(config.widgets[0].align, Some(Align::Right));
+        assert_eq!(config.widgets[1].kind, WidgetKind::Bar);
+        assert_eq!(config.widgets[1].width, Some(200));
+        config.screen_builder()?;
+
+        let err =
+            MonitorConfig::from_toml("[[widget]]\ntype = \"text\"\nx = 1\ny = 2\nsise = 3", "")
+                .unwrap_err();
+        assert!(matches!(err, Error::Config(msg) if msg.contains("sise")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_value() {
+        assert_eq!(format_value("{}", "abc"), "abc");
+        assert_eq!(format_value("CPU {:.1}%", "12.345"), "CPU 12.3%");
+        assert_eq!(format_value("{:.0} C", "abc"), "abc C");
+        assert_eq!(format_value("no value", "1"), "no value");
+        assert_eq!(format_value("down {:rate}", "3145728"), "down 3.0 MiB/s");
+        assert_eq!(format_value("{:bytes}", "?"), "?");
+    }
+
+    #[test]
+    fn test_monitor() -> Res<()> {
+        let dir = env::temp_dir().join(format!("turing-monitor-test-{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let value = dir.join("value");
+        fs::write(&value, "50000\n")?;
+
+        let config = MonitorConfig::from_toml(
+            r##"
+            background = "#000080"
+
+            [[widget]]
+            type = "bar"
+            source = "file"
+            path = "value"
+            scale = 0.001
+            x = 0
+            y = 1
+            width = 10
+            height = 2
+            color = "#ff0000"
+            interval = 0.5
+            "##,
+            &dir,
+        )?;
+        let screen = VirtualScreen::new(10, 4).format(PixelFormat::Rgba);
+        let mut monitor = Monitor::new(&config, Box::new(screen))?;
+
+        let start = Instant::now();
+        let next = monitor.update(start)?.unwrap();
+        assert!(next > start && next <= start + Duration::from_millis(500));
+        let image = monitor.canvas.image();
+        assert_eq!(image.buffer[0], BLUE);
+        assert_eq!(image.buffer[10 + 4], RED);
+        assert_eq!(image.buffer[10 + 5], BLUE);
+        assert!(!monitor.canvas.is_dirty());
+
+        // not due yet
+        fs::write(&value, "80000\n")?;
+        monitor.update(start + Duration::from_millis(100))?;
+        assert_eq!(monitor.canvas.image().buffer[10 + 5], BLUE);
+
+        monitor.update(start + Duration::from_millis(500))?;
+        let image = monitor.canvas.image();
+        assert_eq!(image.buffer[2 * 10 + 7], RED);
+        assert_eq!(image.buffer[2 * 10 + 8], BLUE);
+
+        // a missing value shows an empty bar
+        fs::remove_file(&value)?;
+        monitor.update(start + Duration::from_secs(1))?;
+        assert_eq!(monitor.canvas.image().buffer[10], BLUE);
+
+        let screen = monitor.take_screen();
+        assert_eq!(screen.screen_size(), (10, 4));
+        assert_eq!(monitor.canvas.size(), (0, 0));
+        monitor.set_screen(screen);
+        monitor.update(start + Duration::from_secs(2))?;
+        assert_eq!(monitor.canvas.image().buffer[0], BLUE);
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_static_and_radial() -> Res<()> {
+        let config = MonitorConfig::from_toml(
+            r##"
+            background = "#000080"
+
+            [[widget]]
+            type = "bar"
+            text = "100"
+            x = 0
+            y = 0
+            width = 10
+            height = 10
+            color = "#ff0000"
+
+            [[widget]]
+            type = "radial"
+            source = "command"
+            command = "echo 50"
+            x = 5
+            y = 5
+            radius = 4
+            thickness = 2
+            color = "#00ff00"
+            "##,
+            "",
+        )?;
+        let screen = VirtualScreen::new(10, 10).format(PixelFormat::Rgba);
+        let mut monitor = Monitor::new(&config, Box::new(screen))?;
+        monitor.update(Instant::now())?;
+
+        // the ring fills clockwise from 3 o'clock, over the static bar
+        let image = monitor.canvas.image();
+        let green = Rgba::new(0, 0xff, 0, 0xff);
+        assert_eq!(image.buffer[8 * 10 + 5], green);
+        assert_eq!(image.buffer[10 + 5], RED);
+        assert_eq!(image.buffer[5 * 10 + 5], RED);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_widget() {
+        let config = MonitorConfig::from_toml(
+            "[[widget]]\ntype = \"bar\"\nsource = \"gpu.percent\"\nx = 0\ny = 0",
+            "",
+        )
+        .unwrap();
+        let screen = VirtualScreen::new(10, 4);
+        let err = Monitor::new(&config, Box::new(screen)).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid configuration: widget 1: unknown source 'gpu.percent'"
+        );
+
+        let config = MonitorConfig::from_toml(
+            "[[widget]]\ntype = \"bar\"\nx = 0\ny = 0\nwidth = 10\nheight = 0",
+            "",
+        )
+        .unwrap();
+        let screen = VirtualScreen::new(10, 4);
+        let err = Monitor::new(&config, Box::new(screen)).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid configuration: widget 1: empty bar"
+        );
+    }
+}
@@ -0,0 +1,460 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::mem;
+use std::thread;
+use std::time::Duration;
+
+use crate::detect;
+use crate::{Coord, Error, Image, Orientation, Rect, Res, Screen};
+
+type Opener = Box<dyn FnMut() -> Res<Box<dyn Screen>> + Send>;
+
+// Errors caused by a lost connection to the device
+fn is_connection_error(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::Io(_)
+            | Error::Timeout
+            | Error::PartialWrite(..)
+            | Error::Serial(_)
+            | Error::DeviceNotFound(_)
+    )
+}
+
+// The state of a screen whose device was closed to be reopened: the
+// operations fail with a connection error until it is.
+struct Disconnected {
+    size: (usize, usize),
+    fb: Vec<u8>,
+    model: String,
+}
+
+impl Disconnected {
+    fn new(screen: &dyn Screen) -> Self {
+        Self {
+            size: screen.screen_size(),
+            fb: screen.framebuffer().to_vec(),
+            model: screen.model(),
+        }
+    }
+
+    fn error(&self) -> Error {
+        Error::DeviceNotFound("device disconnected".to_string())
+    }
+}
+
+impl Screen for Disconnected {
+    fn screen_size(&self) -> (usize, usize) {
+        self.size
+    }
+    fn write(&mut self, _data: &[u8]) -> Res<usize> {
+        Err(self.error())
+    }
+    fn read(&mut self, _n: usize) -> Res<Vec<u8>> {
+        Err(self.error())
+    }
+    fn init(&mut self) -> Res<()> {
+        Err(self.error())
+    }
+    fn clear(&mut self) -> Res<()> {
+        Err(self.error())
+    }
+    fn screen_on(&mut self) -> Res<()> {
+        Err(self.error())
+    }
+    fn screen_off(&mut self) -> Res<()> {
+        Err(self.error())
+    }
+    fn set_orientation(&mut self, _o: Orientation) -> Res<()> {
+        Err(self.error())
+    }
+    fn set_brightness(&mut self, _level: usize) -> Res<()> {
+        Err(self.error())
+    }
+    fn display_image(&mut self, _image: &Image, _crop: &Rect, _pos: &Coord) -> Res<()> {
+        Err(self.error())
+    }
+    fn framebuffer(&self) -> &[u8] {
+        &self.fb
+    }
+    fn restore_framebuffer(&mut self, _fb: &[u8]) -> Res<()> {
+        Err(self.error())
+    }
+    fn model(&self) -> String {
+        self.model.clone()
+    }
+}
+
+/// A screen that reopens the device when the connection is lost.
+///
+/// When an operation fails with a connection error, the device is searched
+/// again by its USB serial number, reopened and restored to its last known
+/// state, and the operation is retried once.
+pub struct ReconnectingScreen {
+    screen: Box<dyn Screen>,
+    open: Opener,
+    attempts: usize,
+    delay: Duration,
+    resend_frame: bool,
+    initialized: bool,
+    orientation: Option<Orientation>,
+    brightness: Option<usize>,
+    off: bool,
+}
+
+impl ReconnectingScreen {
+    /// Open the screen with the given USB serial number.
+    pub fn new(serial: &str) -> Res<Self> {
+        let serial = serial.to_string();
+        Self::with_opener(Box::new(move || detect::open_serial(&serial)))
+    }
+
+    fn with_opener(mut open: Opener) -> Res<Self> {
+        Ok(Self {
+            screen: open()?,
+            open,
+            attempts: 5,
+            delay: Duration::from_secs(1),
+            resend_frame: false,
+            initialized: false,
+            orientation: None,
+            brightness: None,
+            off: false,
+        })
+    }
+
+    /// Send the last frame to the screen after reconnecting.
+    pub fn resend_frame(mut self, enable: bool) -> Self {
+        self.resend_frame = enable;
+        self
+    }
+
+    /// Set the number of attempts to reopen the device and the delay
+    /// between them.
+    pub fn retries(mut self, attempts: usize, delay: Duration) -> Self {
+        self.attempts = attempts;
+        self.delay = delay;
+        self
+    }
+
+    fn reconnect(&mut self) -> Res<()> {
+        let mut attempt = 1;
+        loop {
+            match self.reopen() {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt >= self.attempts => return Err(err),
+                Err(err) => log::debug!("reconnect attempt {} failed: {}", attempt, err),
+            }
+            attempt += 1;
+            thread::sleep(self.delay);
+        }
+    }
+
+    // Open the device and replay the screen state. The old screen is
+    // closed first, releasing the device lock.
+    fn reopen(&mut self) -> Res<()> {
+        let disconnected = Box::new(Disconnected::new(self.screen.as_ref()));
+        drop(mem::replace(&mut self.screen, disconnected));
+        let mut screen = (self.open)()?;
+        if self.initialized {
+            screen.init()?;
+        }
+        if let Some(o) = &self.orientation {
+            screen.set_orientation(o.clone())?;
+        }
+        if let Some(level) = self.brightness {
+            screen.set_brightness(level)?;
+        }
+        if self.resend_frame {
+            screen.restore_framebuffer(self.screen.framebuffer())?;
+        }
+        if self.off {
+            screen.screen_off()?;
+        }
+        log::info!("screen reconnected");
+        self.screen = screen;
+        Ok(())
+    }
+
+    fn retry<T>(&mut self, mut f: impl FnMut(&mut dyn Screen) -> Res<T>) -> Res<T> {
+        match f(self.screen.as_mut()) {
+            Err(err) if is_connection_error(&err) => {
+                log::warn!("screen connection lost: {}", err);
+                self.reconnect()?;
+                f(self.screen.as_mut())
+            }
+            res => res,
+        }
+    }
+}
+
+impl Screen for ReconnectingScreen {
+    fn screen_size(&self) -> (usize, usize) {
+        self.screen.screen_size()
+    }
+
+    fn write(&mut self, data: &[u8]) -> Res<usize> {
+        self.retry(|s| s.write(data))
+    }
+
+    fn read(&mut self, n: usize) -> Res<Vec<u8>> {
+        self.screen.read(n)
+    }
+
+    fn init(&mut self) -> Res<()> {
+        self.retry(|s| s.init())?;
+        self.initialized = true;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Res<()> {
+        self.retry(|s| s.clear())
+    }
+
+    fn screen_on(&mut self) -> Res<()> {
+        self.retry(|s| s.screen_on())?;
+        self.off = false;
+        Ok(())
+    }
+
+    fn screen_off(&mut self) -> Res<()> {
+        self.retry(|s| s.screen_off())?;
+        self.off = true;
+        Ok(())
+    }
+
+    fn set_orientation(&mut self, o: Orientation) -> Res<()> {
+        self.retry(|s| s.set_orientation(o.clone()))?;
+        self.orientation = Some(o);
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, level: usize) -> Res<()> {
+        self.retry(|s| s.set_brightness(level))?;
+        self.brightness = Some(level);
+        Ok(())
+    }
+
+    fn display_image(&mut self, image: &Image, crop: &Rect, pos: &Coord) -> Res<()> {
+        self.retry(|s| s.display_image(image, crop, pos))
+    }
+
+    fn framebuffer(&self) -> &[u8] {
+        self.screen.framebuffer()
+    }
+
+    fn restore_framebuffer(&mut self, fb: &[u8]) -> Res<()> {
+        self.retry(|s| s.restore_framebuffer(fb))
+    }
+
+    fn model(&self) -> String {
+        self.screen.model()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lock::DeviceLock;
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::process;
+    use std::sync::{Arc, Mutex};
+
+    // A screen that logs operations and fails when the device is unplugged
+    struct MockScreen {
+        log: Log,
+        unplugged: Arc<Mutex<bool>>,
+        fb: Vec<u8>,
+        _lock: Option<DeviceLock>,
+    }
+
+    impl MockScreen {
+        fn op(&mut self, name: String) -> Res<()> {
+            if *self.unplugged.lock().unwrap() {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "unplugged").into());
+            }
+            self.log.lock().unwrap().push(name);
+            Ok(())
+        }
+    }
+
+    impl Screen for MockScreen {
+        fn screen_size(&self) -> (usize, usize) {
+            (2, 2)
+        }
+        fn write(&mut self, data: &[u8]) -> Res<usize> {
+            self.op(format!("write {:?}", data))?;
+            Ok(data.len())
+        }
+        fn read(&mut self, n: usize) -> Res<Vec<u8>> {
+            Ok(vec![0; n])
+        }
+        fn init(&mut self) -> Res<()> {
+            self.op("init".to_string())
+        }
+        fn clear(&mut self) -> Res<()> {
+            self.op("clear".to_string())
+        }
+        fn screen_on(&mut self) -> Res<()> {
+            self.op("on".to_string())
+        }
+        fn screen_off(&mut self) -> Res<()> {
+            self.op("off".to_string())
+        }
+        fn set_orientation(&mut self, o: Orientation) -> Res<()> {
+            self.op(format!("orientation {:?}", o))
+        }
+        fn set_brightness(&mut self, level: usize) -> Res<()> {
+            self.op(format!("brightness {}", level))
+        }
+        fn display_image(&mut self, _image: &Image, crop: &Rect, pos: &Coord) -> Res<()> {
+            if pos.x > 2 {
+                return Err(Error::OutOfBounds(crop.clone(), pos.clone()));
+            }
+            self.op(format!("display {} {}", crop, pos))?;
+            self.fb = vec![1, 2, 3];
+            Ok(())
+        }
+        fn framebuffer(&self) -> &[u8] {
+            &self.fb
+        }
+        fn restore_framebuffer(&mut self, fb: &[u8]) -> Res<()> {
+            self.op(format!("restore {:?}", fb))?;
+            self.fb = fb.to_vec();
+            Ok(())
+        }
+    }
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    // A screen whose device was unplugged
+    fn dead_screen(log: &Log, fb: Vec<u8>) -> Box<MockScreen> {
+        Box::new(MockScreen {
+            log: log.clone(),
+            unplugged: Arc::new(Mutex::new(true)),
+            fb,
+            _lock: None,
+        })
+    }
+
+    fn mock_screen() -> (ReconnectingScreen, Log, Arc<Mutex<bool>>) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let unplugged = Arc::new(Mutex::new(false));
+        let (l, u) = (log.clone(), unplugged.clone());
+        let scr = ReconnectingScreen::with_opener(Box::new(move || {
+            if *u.lock().unwrap() {
+                return Err(Error::DeviceNotFound("unplugged".to_string()));
+            }
+            l.lock().unwrap().push("open".to_string());
+            Ok(Box::new(MockScreen {
+                log: l.clone(),
+                unplugged: u.clone(),
+                fb: Vec::new(),
+                _lock: None,
+            }))
+        }))
+        .unwrap()
+        .retries(1, Duration::ZERO);
+        (scr, log, unplugged)
+    }
+
+    #[test]
+    fn test_reconnect() -> Res<()> {
+        let (mut scr, log, _) = mock_screen();
+        scr.init()?;
+        scr.set_orientation(Orientation::Landscape)?;
+        scr.set_brightness(100)?;
+
+        // the device is back when reconnecting
+        scr.screen = dead_screen(&log, Vec::new());
+        scr.screen_off()?;
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "open",
+                "init",
+                "orientation Landscape",
+                "brightness 100",
+                "open",
+                "init",
+                "orientation Landscape",
+                "brightness 100",
+                "off",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconnect_resend_frame() -> Res<()> {
+        let (scr, log, _) = mock_screen();
+        let mut scr = scr.resend_frame(true);
+        let image = Image::new(2, 2);
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+        scr.screen_off()?;
+
+        scr.screen = dead_screen(&log, vec![1, 2, 3]);
+        scr.clear()?;
+
+        assert_eq!(
+            log.lock().unwrap()[3..],
+            ["open", "restore [1, 2, 3]", "off", "clear"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconnect_fail() {
+        let (mut scr, log, unplugged) = mock_screen();
+        scr.screen = dead_screen(&log, Vec::new());
+        *unplugged.lock().unwrap() = true;
+        assert!(matches!(scr.clear(), Err(Error::DeviceNotFound(_))));
+
+        // reconnect on the next operation
+        *unplugged.lock().unwrap() = false;
+        assert!(scr.clear().is_ok());
+        assert_eq!(*log.lock().unwrap(), vec!["open", "open", "clear"]);
+    }
+
+    #[test]
+    fn test_reconnect_locked() -> Res<()> {
+        let dir = env::temp_dir().join(format!("turing-reconnect-test-{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let unplugged = Arc::new(Mutex::new(false));
+        let (l, u, d) = (log.clone(), unplugged.clone(), dir.clone());
+        let mut scr = ReconnectingScreen::with_opener(Box::new(move || {
+            // the same device, locked by each screen
+            let lock = DeviceLock::acquire_in(&d, "USB35INCHIPSV2", Duration::ZERO)?;
+            l.lock().unwrap().push("open".to_string());
+            *u.lock().unwrap() = false;
+            Ok(Box::new(MockScreen {
+                log: l.clone(),
+                unplugged: u.clone(),
+                fb: Vec::new(),
+                _lock: Some(lock),
+            }))
+        }))?
+        .retries(1, Duration::ZERO);
+
+        *unplugged.lock().unwrap() = true;
+        scr.clear()?;
+        assert_eq!(*log.lock().unwrap(), vec!["open", "open", "clear"]);
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_reconnect() {
+        let (mut scr, log, _) = mock_screen();
+        let image = Image::new(2, 2);
+        assert!(matches!(
+            scr.display_image(&image, &image.full(), &Coord::new(3, 0)),
+            Err(Error::OutOfBounds(..))
+        ));
+        assert_eq!(*log.lock().unwrap(), vec!["open"]);
+    }
+}
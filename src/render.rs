@@ -0,0 +1,308 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+
+use crate::{Coord, Error, Image, Rect, Res};
+use crate::{Orientation, Screen};
+
+/// An operation queued for the render thread.
+enum Request {
+    Init,
+    Clear,
+    ScreenOn,
+    ScreenOff,
+    SetOrientation(Orientation),
+    SetBrightness(usize),
+    Display(Image, Rect, Coord),
+    Sync(SyncSender<()>),
+}
+
+impl Request {
+    // The screen area updated by a display request
+    fn display_area(&self) -> Option<Rect> {
+        match self {
+            Request::Display(_, crop, pos) => Some(Rect::new(pos.x, pos.y, crop.w, crop.h)),
+            _ => None,
+        }
+    }
+}
+
+fn contains(outer: &Rect, inner: &Rect) -> bool {
+    inner.x >= outer.x
+        && inner.y >= outer.y
+        && inner.x + inner.w <= outer.x + outer.w
+        && inner.y + inner.h <= outer.y + outer.h
+}
+
+// Drop the display requests fully covered by a later display request.
+// Other requests act as barriers, updates are never moved across them.
+fn coalesce(batch: Vec<Request>) -> Vec<Request> {
+    let mut keep = vec![true; batch.len()];
+    let mut later: Vec<Rect> = Vec::new();
+    for (i, req) in batch.iter().enumerate().rev() {
+        match req.display_area() {
+            Some(area) => {
+                if later.iter().any(|r| contains(r, &area)) {
+                    keep[i] = false;
+                } else {
+                    later.push(area);
+                }
+            }
+            None => later.clear(),
+        }
+    }
+    batch
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(req, keep)| keep.then_some(req))
+        .collect()
+}
+
+fn execute(screen: &mut dyn Screen, req: Request) -> Res<()> {
+    match req {
+        Request::Init => screen.init(),
+        Request::Clear => screen.clear(),
+        Request::ScreenOn => screen.screen_on(),
+        Request::ScreenOff => screen.screen_off(),
+        Request::SetOrientation(o) => screen.set_orientation(o),
+        Request::SetBrightness(level) => screen.set_brightness(level),
+        Request::Display(image, crop, pos) => screen.display_image(&image, &crop, &pos),
+        Request::Sync(done) => {
+            let _ = done.send(());
+            Ok(())
+        }
+    }
+}
+
+fn run(mut screen: Box<dyn Screen>, rx: Receiver<Request>, on_error: &mut dyn FnMut(Error)) {
+    // Wait for a request, then take everything queued meanwhile
+    while let Ok(req) = rx.recv() {
+        let mut batch = vec![req];
+        batch.extend(rx.try_iter());
+        let n = batch.len();
+        let batch = coalesce(batch);
+        if batch.len() < n {
+            log::debug!("coalesced {} display updates", n - batch.len());
+        }
+        for req in batch {
+            if let Err(err) = execute(screen.as_mut(), req) {
+                on_error(err);
+            }
+        }
+    }
+    log::debug!("render thread stopped");
+}
+
+fn stopped() -> Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "render thread stopped").into()
+}
+
+/// A screen driven from a background thread.
+///
+/// Operations are queued and return immediately, unless the queue is full.
+/// Display updates waiting in the queue are dropped when a later update
+/// covers the same area. Errors are reported to the handler given when
+/// spawning the thread.
+///
+/// ```no_run
+/// use turing_screen::{Coord, Image, Renderer};
+///
+/// let renderer = Renderer::spawn(|| turing_screen::new("AUTO"), 4, |err| {
+///     eprintln!("screen error: {}", err);
+/// });
+/// let image = Image::new(320, 480);
+/// renderer.display_image(image.clone(), image.full(), Coord::new(0, 0)).unwrap();
+/// ```
+pub struct Renderer {
+    tx: Option<SyncSender<Request>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Renderer {
+    /// Start the render thread.
+    ///
+    /// The screen is opened in the render thread. At most `capacity`
+    /// operations are queued, further operations block until the thread
+    /// catches up.
+    pub fn spawn<O, E>(open: O, capacity: usize, mut on_error: E) -> Self
+    where
+        O: FnOnce() -> Res<Box<dyn Screen>> + Send + 'static,
+        E: FnMut(Error) + Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let thread = thread::spawn(move || match open() {
+            Ok(screen) => run(screen, rx, &mut on_error),
+            Err(err) => on_error(err),
+        });
+        Self {
+            tx: Some(tx),
+            thread: Some(thread),
+        }
+    }
+
+    fn send(&self, req: Request) -> Res<()> {
+        match &self.tx {
+            Some(tx) => tx.send(req).map_err(|_| stopped()),
+            None => Err(stopped()),
+        }
+    }
+
+    pub fn init(&self) -> Res<()> {
+        self.send(Request::Init)
+    }
+
+    pub fn clear(&self) -> Res<()> {
+        self.send(Request::Clear)
+    }
+
+    pub fn screen_on(&self) -> Res<()> {
+        self.send(Request::ScreenOn)
+    }
+
+    pub fn screen_off(&self) -> Res<()> {
+        self.send(Request::ScreenOff)
+    }
+
+    pub fn set_orientation(&self, o: Orientation) -> Res<()> {
+        self.send(Request::SetOrientation(o))
+    }
+
+    pub fn set_brightness(&self, level: usize) -> Res<()> {
+        self.send(Request::SetBrightness(level))
+    }
+
+    /// Queue a cropped portion of the image to be displayed.
+    pub fn display_image(&self, image: Image, crop: Rect, pos: Coord) -> Res<()> {
+        self.send(Request::Display(image, crop, pos))
+    }
+
+    /// Queue an image update without blocking.
+    ///
+    /// Returns false if the queue is full and the update was dropped.
+    pub fn try_display_image(&self, image: Image, crop: Rect, pos: Coord) -> Res<bool> {
+        let tx = self.tx.as_ref().ok_or_else(stopped)?;
+        match tx.try_send(Request::Display(image, crop, pos)) {
+            Ok(()) => Ok(true),
+            Err(TrySendError::Full(_)) => Ok(false),
+            Err(TrySendError::Disconnected(_)) => Err(stopped()),
+        }
+    }
+
+    /// Wait until all the queued operations are done.
+    pub fn sync(&self) -> Res<()> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.send(Request::Sync(tx))?;
+        rx.recv().map_err(|_| stopped())
+    }
+}
+
+impl Drop for Renderer {
+    // Finish the queued operations before returning.
+    fn drop(&mut self) {
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use crate::{colors, EmulatorRevA, ScreenRevA};
+
+    fn display(x: usize, y: usize, w: usize, h: usize) -> Request {
+        Request::Display(Image::new(w, h), Rect::new(0, 0, w, h), Coord::new(x, y))
+    }
+
+    fn areas(batch: &[Request]) -> Vec<Option<Rect>> {
+        batch.iter().map(|r| r.display_area()).collect()
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let batch = coalesce(vec![
+            display(0, 0, 10, 10),
+            display(20, 0, 10, 10),
+            display(2, 2, 5, 5),
+            display(0, 0, 10, 10),
+            Request::Clear,
+            display(0, 0, 10, 10),
+        ]);
+        assert_eq!(
+            areas(&batch),
+            [
+                Some(Rect::new(20, 0, 10, 10)),
+                Some(Rect::new(0, 0, 10, 10)),
+                None,
+                Some(Rect::new(0, 0, 10, 10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_renderer() -> Res<()> {
+        let emu = EmulatorRevA::new();
+        let port = emu.clone();
+        let renderer = Renderer::spawn(
+            move || Ok(Box::new(ScreenRevA::with_port(Box::new(port)))),
+            4,
+            |err| panic!("unexpected error: {}", err),
+        );
+        renderer.init()?;
+        renderer.clear()?;
+        let mut image = Image::new(2, 2);
+        image.buffer[3] = colors::WHITE;
+        renderer.display_image(image, Rect::new(0, 0, 2, 2), Coord::new(0, 0))?;
+        renderer.sync()?;
+        assert_eq!(emu.snapshot().buffer[0], colors::BLACK);
+        assert_eq!(emu.snapshot().buffer[321], colors::WHITE);
+
+        renderer.set_brightness(10)?;
+        renderer.sync()?;
+        assert_eq!(emu.brightness(), 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_renderer_errors() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let log = errors.clone();
+        let renderer = Renderer::spawn(
+            || {
+                Ok(Box::new(ScreenRevA::with_port(Box::new(
+                    EmulatorRevA::new(),
+                ))))
+            },
+            4,
+            move |err| log.lock().unwrap().push(err.to_string()),
+        );
+        let image = Image::new(2, 2);
+        renderer
+            .display_image(image, Rect::new(0, 0, 2, 2), Coord::new(400, 0))
+            .unwrap();
+        drop(renderer);
+        assert_eq!(
+            *errors.lock().unwrap(),
+            ["area @0,0+2x2 at @400,0 is outside the screen"]
+        );
+    }
+
+    #[test]
+    fn test_renderer_open_failed() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let log = errors.clone();
+        let renderer = Renderer::spawn(
+            || Err(Error::DeviceNotFound("test".to_string())),
+            4,
+            move |err| log.lock().unwrap().push(err.to_string()),
+        );
+        assert!(renderer.sync().is_err());
+        assert!(renderer.clear().is_err());
+        assert_eq!(*errors.lock().unwrap(), ["device not found: test"]);
+    }
+}
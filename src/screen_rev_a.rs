@@ -2,21 +2,27 @@
 
 use std::io::{Read, Write};
 
+use crate::damage::Damage;
 use crate::serial_port;
 use crate::{Coord, Image, Rect};
-use crate::{Orientation, Res, Screen, ScreenPort};
+use crate::{Error, Orientation, Res, Screen, ScreenPort};
 
 // Constants and protocol definitions from
 // https://github.com/mathoudebine/turing-smart-screen-python
 
-const WIDTH: usize = 320;
-const HEIGHT: usize = 480;
+pub(crate) const BAUD_RATE: u32 = 115_200;
 
-enum Command {
+pub(crate) const WIDTH: usize = 320;
+pub(crate) const HEIGHT: usize = 480;
+
+// Cost in pixels of sending an additional area
+const UPDATE_OVERHEAD: usize = 32;
+
+pub(crate) enum Command {
     Hello = 69,           // Asks the screen for its model: 3.5", 5" or 7"
-    _Reset = 101,         // Resets the display
+    Reset = 101,          // Resets the display
     Clear = 102,          // Clears the display to a white screen
-    _ToBlack = 103,       // Makes the screen go black. NOT TESTED
+    ToBlack = 103,        // Makes the screen go black. NOT TESTED
     ScreenOff = 108,      // Turns the screen off
     ScreenOn = 109,       // Turns the screen on
     SetBrightness = 110,  // Sets the screen brightness
@@ -25,7 +31,7 @@ enum Command {
 }
 
 // Subrevisions
-const USBMONITOR35: &[u8] = &[0x01, 0x01, 0x01, 0x01, 0x01, 0x01];
+pub(crate) const USBMONITOR35: &[u8] = &[0x01, 0x01, 0x01, 0x01, 0x01, 0x01];
 
 // Macro to prepare the command buffer
 macro_rules! cmd {
@@ -84,6 +90,7 @@ pub struct ScreenRevA {
     port: Box<dyn ScreenPort>,
     orientation: Orientation,
     fb565_raw: Vec<u8>,
+    fb_valid: bool, // the framebuffer mirror matches the device
 }
 
 impl ScreenRevA {
@@ -94,35 +101,72 @@ impl ScreenRevA {
         };
         log::debug!("create screen rev A on {}", name);
 
-        Ok(Self {
-            port: Box::new(serial_port::SerialPort::new(&name, 115_200)?),
+        let port = serial_port::SerialPort::new(&name, BAUD_RATE)?;
+        Ok(Self::with_port(Box::new(port)))
+    }
+
+    /// Create a screen on an already opened port.
+    pub fn with_port(port: Box<dyn ScreenPort>) -> Self {
+        Self {
+            port,
             orientation: Orientation::Portrait,
             fb565_raw: vec![0u8; 2 * WIDTH * HEIGHT],
-        })
+            fb_valid: false,
+        }
     }
 
     // RGB565 bit packing:
     // [rrrr rggg] [gggb bbbb]  =(LE)=>  [gggb bbbb] [rrrr rggg]
-    fn downmix(&mut self, image: &Image, rect: &Rect, pos: &Coord) {
+    //
+    // Returns the pixels that differ from the framebuffer mirror.
+    fn downmix(&mut self, image: &Image, rect: &Rect, pos: &Coord) -> Damage {
         let (width, _) = self.screen_size(); // screen width in pixels
-        let mut ofs888 = rect.y * image.width + rect.x; // image vector offset in pixels
-        let mut ofs565 = 2 * (pos.y * width + pos.x); // fb565 vector offset in bytes
-        let stride = width * 2; // width of rgb565 framebuffer in bytes
-
-        for _ in 0..rect.h {
-            // line start y coordinate
-            let mut src = ofs888;
-            let mut dest = ofs565;
-            for _ in 0..rect.w {
-                let p = image.buffer[src];
-                src += 1;
-                self.fb565_raw[dest] = ((p.g & 0x1c) << 3) | (p.b >> 3);
-                self.fb565_raw[dest + 1] = (p.r & 0xf8) | (p.g >> 5);
-                dest += 2;
+        let mut damage = Damage::new(&Rect::new(pos.x, pos.y, rect.w, rect.h));
+
+        for y in 0..rect.h {
+            let ofs888 = (rect.y + y) * image.width + rect.x; // image vector offset in pixels
+            let ofs565 = 2 * ((pos.y + y) * width + pos.x); // fb565 vector offset in bytes
+            for (x, p) in image.buffer[ofs888..ofs888 + rect.w].iter().enumerate() {
+                let dest = ofs565 + 2 * x;
+                let lo = ((p.g & 0x1c) << 3) | (p.b >> 3);
+                let hi = (p.r & 0xf8) | (p.g >> 5);
+                if self.fb565_raw[dest] != lo || self.fb565_raw[dest + 1] != hi {
+                    self.fb565_raw[dest] = lo;
+                    self.fb565_raw[dest + 1] = hi;
+                    damage.add(pos.x + x, pos.y + y);
+                }
             }
-            ofs888 += image.width;
-            ofs565 += stride;
         }
+        damage
+    }
+}
+
+impl ScreenRevA {
+    // Send a screen area from the framebuffer mirror.
+    fn send_area(&mut self, area: &Rect) -> Res<()> {
+        let (width, _) = self.screen_size(); // screen width in pixels
+        serial_port::write_all(
+            self.port.as_mut(),
+            cmd!(
+                Command::DisplayBitmap,
+                area.x,
+                area.y,
+                area.x + area.w - 1,
+                area.y + area.h - 1
+            ),
+        )?;
+
+        let stride = 2 * width; // width of screen in bytes
+        let mut start = area.y * stride + (2 * area.x); // line start offset in bytes
+        let mut end = start + 2 * area.w; // line end offset in bytes
+        for _ in 0..area.h {
+            serial_port::write_all(self.port.as_mut(), &self.fb565_raw[start..end])?;
+            start += stride;
+            end += stride;
+        }
+        self.port.flush()?;
+
+        Ok(())
     }
 }
 
@@ -134,8 +178,8 @@ impl Screen for ScreenRevA {
         }
     }
     fn write(&mut self, data: &[u8]) -> Res<usize> {
-        let n = self.port.write(data)?;
-        Ok(n)
+        serial_port::write_command(self.port.as_mut(), data)?;
+        Ok(data.len())
     }
 
     fn read(&mut self, n: usize) -> Res<Vec<u8>> {
@@ -150,7 +194,7 @@ impl Screen for ScreenRevA {
 
         let res = self.read(6)?;
         if res != USBMONITOR35 {
-            return Err("incompatible screen model".into());
+            return Err(Error::IncompatibleModel(res));
         }
 
         Ok(())
@@ -160,6 +204,8 @@ impl Screen for ScreenRevA {
         log::debug!("clear screen");
         self.set_orientation(Orientation::Portrait)?; // Orientation must be PORTRAIT before clearing
         self.write(cmd!(Command::Clear))?;
+        self.fb565_raw.fill(0xff);
+        self.fb_valid = true;
         Ok(())
     }
 
@@ -177,6 +223,9 @@ impl Screen for ScreenRevA {
 
     fn set_orientation(&mut self, o: Orientation) -> Res<()> {
         log::debug!("set screen orientation to {:?}", o);
+        if o != self.orientation {
+            self.fb_valid = false;
+        }
         self.orientation = o.clone();
         let (width, height) = self.screen_size();
         self.write(cmd!(Command::SetOrientation, orientation(o), width, height))?;
@@ -199,56 +248,68 @@ impl Screen for ScreenRevA {
     fn display_image(&mut self, image: &Image, crop: &Rect, pos: &Coord) -> Res<()> {
         log::debug!("display image {} {}", crop, pos);
         let (width, height) = self.screen_size(); // size of screen in pixels
-        let r = crop.clip(width - pos.x, height - pos.y);
+        if pos.x > width || pos.y > height {
+            return Err(Error::OutOfBounds(crop.clone(), pos.clone()));
+        }
+        let r = crop.clip(crop.x + width - pos.x, crop.y + height - pos.y);
 
         if r.w == 0 || r.h == 0 {
             return Ok(());
         }
 
-        self.downmix(image, &r, pos);
-        self.write(cmd!(
-            Command::DisplayBitmap,
-            pos.x,
-            pos.y,
-            pos.x + r.w - 1,
-            pos.y + r.h - 1
-        ))?;
+        let area = Rect::new(pos.x, pos.y, r.w, r.h);
+        let damage = self.downmix(image, &r, pos);
 
-        let stride = 2 * width; // width of screen in bytes
-        let mut start = pos.y * stride + (2 * pos.x); // line start offset in bytes
-        let mut end = start + 2 * r.w; // line end offset in bytes
-        for _ in 0..r.h {
-            self.write(&self.fb565_raw[start..end].to_owned())?;
-            start += stride;
-            end += stride;
+        // Unknown device contents, send the whole area
+        if !self.fb_valid {
+            self.send_area(&area)?;
+            self.fb_valid = area == Rect::new(0, 0, width, height);
+            return Ok(());
+        }
+
+        for rect in damage.rects(UPDATE_OVERHEAD) {
+            // the mirror already has the pixels the device may have missed
+            self.send_area(&rect)
+                .inspect_err(|_| self.fb_valid = false)?;
         }
+        Ok(())
+    }
+
+    fn framebuffer(&self) -> &[u8] {
+        &self.fb565_raw
+    }
 
+    fn restore_framebuffer(&mut self, fb: &[u8]) -> Res<()> {
+        if fb.len() != self.fb565_raw.len() {
+            return Err(Error::FramebufferSize(fb.len(), self.fb565_raw.len()));
+        }
+        self.fb565_raw.copy_from_slice(fb);
+        let (width, height) = self.screen_size();
+        self.send_area(&Rect::new(0, 0, width, height))
+            .inspect_err(|_| self.fb_valid = false)?;
+        self.fb_valid = true;
         Ok(())
     }
+    fn model(&self) -> String {
+        "Turing Smart Screen 3.5\" (revision A)".to_string()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
-
-    use crate::Rgba;
 
-    type FakePort = Cursor<Vec<u8>>;
-
-    impl ScreenPort for FakePort {
-        fn get_buf(&self) -> Vec<u8> {
-            self.get_ref().to_vec()
-        }
-    }
+    use crate::tests::{FailingPort, FakePort};
+    use crate::{colors, DecodedCommand, EmulatorRevA, Rgba};
+    use std::sync::atomic::Ordering;
 
     fn fake_screen(port: FakePort) -> ScreenRevA {
-        let scr = ScreenRevA {
+        ScreenRevA {
             port: Box::new(port),
             fb565_raw: Vec::<u8>::new(),
             orientation: Orientation::Portrait,
-        };
-        return scr;
+            fb_valid: false,
+        }
     }
     #[test]
     fn test_screen_size() -> Res<()> {
@@ -286,6 +347,7 @@ mod tests {
             port: Box::new(fake_port),
             fb565_raw: Vec::<u8>::new(),
             orientation: Orientation::Portrait,
+            fb_valid: false,
         };
         assert!(scr.init().is_ok());
         Ok(())
@@ -295,7 +357,10 @@ mod tests {
     fn test_init_fail() -> Res<()> {
         let fake_port = FakePort::new(vec![0u8, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 2]);
         let mut scr = fake_screen(fake_port);
-        assert!(scr.init().is_err());
+        assert!(matches!(
+            scr.init(),
+            Err(Error::IncompatibleModel(res)) if res == [1, 1, 1, 1, 1, 2]
+        ));
         Ok(())
     }
 
@@ -346,6 +411,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::identity_op)]
     fn test_downmix() -> Res<()> {
         let fake_port = FakePort::new(Vec::<u8>::new());
         let mut scr = fake_screen(fake_port);
@@ -416,4 +482,82 @@ mod tests {
         assert_eq!(scr.port.get_buf(), vec![]);
         Ok(())
     }
+
+    #[test]
+    fn test_display_image_out_of_bounds() -> Res<()> {
+        let fake_port = FakePort::new(Vec::<u8>::new());
+        let mut scr = fake_screen(fake_port);
+        let image = Image::new(10, 10);
+        assert!(matches!(
+            scr.display_image(&image, &image.full(), &Coord::new(321, 0)),
+            Err(Error::OutOfBounds(..))
+        ));
+        assert_eq!(scr.port.get_buf(), vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_crop() -> Res<()> {
+        // the bottom right pixel of a full screen image
+        let emu = EmulatorRevA::new();
+        let mut scr = ScreenRevA::with_port(Box::new(emu.clone()));
+        let image = Image::new(WIDTH, HEIGHT);
+        let corner = Rect::new(WIDTH - 1, HEIGHT - 1, 1, 1);
+        scr.display_image(&image, &corner, &Coord::new(WIDTH - 1, HEIGHT - 1))?;
+        assert_eq!(emu.take_commands(), [DecodedCommand::DisplayBitmap(corner)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_changed() -> Res<()> {
+        let emu = EmulatorRevA::new();
+        let mut scr = ScreenRevA::with_port(Box::new(emu.clone()));
+        scr.clear()?;
+
+        // only the area around the changed pixels is sent
+        let mut image = Image {
+            buffer: vec![colors::WHITE; WIDTH * HEIGHT],
+            width: WIDTH,
+            height: HEIGHT,
+        };
+        image.buffer[10 * WIDTH + 5] = Rgba::new(0xff, 0, 0, 0xff);
+        image.buffer[12 * WIDTH + 7] = Rgba::new(0xff, 0, 0, 0xff);
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+        assert_eq!(
+            emu.take_commands()[2..],
+            [DecodedCommand::DisplayBitmap(Rect::new(5, 10, 3, 3))]
+        );
+
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+        assert!(emu.take_commands().is_empty());
+
+        // the device contents are unknown after rotating
+        scr.set_orientation(Orientation::Landscape)?;
+        let image = Image::new(10, 10);
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+        assert_eq!(
+            emu.take_commands()[1..],
+            [DecodedCommand::DisplayBitmap(Rect::new(0, 0, 10, 10))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_failed() -> Res<()> {
+        let port = FailingPort::default();
+        let mut scr = ScreenRevA::with_port(Box::new(port.clone()));
+        scr.fb_valid = true;
+        let mut image = Image::new(4, 4);
+        image.buffer.fill(colors::WHITE);
+
+        // the device may have missed any of the pixels
+        port.fail.store(true, Ordering::SeqCst);
+        assert!(scr
+            .display_image(&image, &image.full(), &Coord::new(1, 1))
+            .is_err());
+        port.fail.store(false, Ordering::SeqCst);
+        scr.display_image(&image, &image.full(), &Coord::new(1, 1))?;
+        assert!(!port.written.lock().unwrap().is_empty());
+        Ok(())
+    }
 }
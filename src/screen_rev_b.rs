@@ -0,0 +1,566 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::cmp::min;
+use std::io::{Read, Write};
+
+use crate::colors;
+use crate::damage::Damage;
+use crate::serial_port;
+use crate::{Coord, Image, Rect};
+use crate::{Error, Orientation, Res, Screen, ScreenPort};
+
+// Constants and protocol definitions from
+// https://github.com/mathoudebine/turing-smart-screen-python
+
+pub(crate) const BAUD_RATE: u32 = 115_200;
+
+const WIDTH: usize = 320;
+const HEIGHT: usize = 480;
+
+// Cost in pixels of sending an additional area
+const UPDATE_OVERHEAD: usize = 32;
+
+enum Command {
+    Hello = 0xca,          // Establishes communication before driving the screen
+    SetOrientation = 0xcb, // Sets the screen orientation
+    DisplayBitmap = 0xcc,  // Displays an image on the screen
+    _SetLighting = 0xcd,   // Sets the screen backplate RGB LED color
+    SetBrightness = 0xce,  // Sets the screen brightness
+}
+
+// Hardware subrevisions, reported in the hello response
+#[derive(Debug, Clone, PartialEq)]
+enum SubRevision {
+    A01, // HW revision B - brightness 0/1
+    A02, // HW revision "flagship" - brightness 0/1
+    A11, // HW revision B - brightness 0-255
+    A12, // HW revision "flagship" - brightness 0-255
+}
+
+// Commands are 10-byte packets framed with the command code
+const HELLO: &[u8] = &[0xca, b'H', b'E', b'L', b'L', b'O', 0, 0, 0, 0xca];
+
+// Macro to prepare the command buffer
+macro_rules! cmd {
+    // 1) match cmd!(Command::..., parameter)
+    ($a:expr, $b:expr) => {{
+        &[$a as u8, $b as u8, 0, 0, 0, 0, 0, 0, 0, $a as u8]
+    }};
+    // 2) match cmd!(Command::DisplayBitmap, x0, y0, x1, y1)
+    ($a:expr, $b:expr, $c:expr, $d:expr, $e:expr) => {{
+        &[
+            $a as u8, // Command::DisplayBitmap
+            ($b >> 8) as u8,
+            ($b & 0xff) as u8,
+            ($c >> 8) as u8,
+            ($c & 0xff) as u8,
+            ($d >> 8) as u8,
+            ($d & 0xff) as u8,
+            ($e >> 8) as u8,
+            ($e & 0xff) as u8,
+            $a as u8, // Command::DisplayBitmap
+        ]
+    }};
+}
+
+// Portrait and landscape are handled by the display, reverse orientations
+// are rotated in software.
+fn orientation(o: Orientation) -> u8 {
+    match o {
+        Orientation::Portrait | Orientation::ReversePortrait => 0,
+        Orientation::Landscape | Orientation::ReverseLandscape => 1,
+    }
+}
+
+pub struct ScreenRevB {
+    port: Box<dyn ScreenPort>,
+    orientation: Orientation,
+    sub_revision: SubRevision,
+    brightness: usize,
+    fb565_raw: Vec<u8>,
+    fb_valid: bool, // the framebuffer mirror matches the device
+}
+
+impl ScreenRevB {
+    pub fn new(portname: &str) -> Res<Self> {
+        let name = match portname {
+            "AUTO" => serial_port::detect("2017-2-25")?,
+            name => name.to_string(),
+        };
+        log::debug!("create screen rev B on {}", name);
+
+        let port = serial_port::SerialPort::new(&name, BAUD_RATE)?;
+        Ok(Self::with_port(Box::new(port)))
+    }
+
+    /// Create a screen on an already opened port.
+    pub fn with_port(port: Box<dyn ScreenPort>) -> Self {
+        Self {
+            port,
+            orientation: Orientation::Portrait,
+            sub_revision: SubRevision::A01,
+            brightness: 255,
+            fb565_raw: vec![0u8; 2 * WIDTH * HEIGHT],
+            fb_valid: false,
+        }
+    }
+
+    #[inline]
+    fn is_flipped(&self) -> bool {
+        matches!(
+            self.orientation,
+            Orientation::ReversePortrait | Orientation::ReverseLandscape
+        )
+    }
+
+    // RGB565 bit packing (big endian):
+    // [rrrr rggg] [gggb bbbb]
+    //
+    // Returns the pixels that differ from the framebuffer mirror.
+    fn downmix(&mut self, image: &Image, rect: &Rect, pos: &Coord) -> Damage {
+        let (width, _) = self.screen_size(); // screen width in pixels
+        let mut damage = Damage::new(&Rect::new(pos.x, pos.y, rect.w, rect.h));
+
+        for y in 0..rect.h {
+            let ofs888 = (rect.y + y) * image.width + rect.x; // image vector offset in pixels
+            let ofs565 = 2 * ((pos.y + y) * width + pos.x); // fb565 vector offset in bytes
+            for (x, p) in image.buffer[ofs888..ofs888 + rect.w].iter().enumerate() {
+                let dest = ofs565 + 2 * x;
+                let hi = (p.r & 0xf8) | (p.g >> 5);
+                let lo = ((p.g & 0x1c) << 3) | (p.b >> 3);
+                if self.fb565_raw[dest] != hi || self.fb565_raw[dest + 1] != lo {
+                    self.fb565_raw[dest] = hi;
+                    self.fb565_raw[dest + 1] = lo;
+                    damage.add(pos.x + x, pos.y + y);
+                }
+            }
+        }
+        damage
+    }
+
+    // Send a screen area from the framebuffer mirror.
+    fn send_area(&mut self, area: &Rect) -> Res<()> {
+        let (width, height) = self.screen_size(); // size of screen in pixels
+        let flipped = self.is_flipped();
+        let (x0, y0) = if flipped {
+            (width - area.x - area.w, height - area.y - area.h)
+        } else {
+            (area.x, area.y)
+        };
+        serial_port::write_all(
+            self.port.as_mut(),
+            cmd!(
+                Command::DisplayBitmap,
+                x0,
+                y0,
+                x0 + area.w - 1,
+                y0 + area.h - 1
+            ),
+        )?;
+
+        let stride = 2 * width; // width of screen in bytes
+        let start = area.y * stride + (2 * area.x); // first line offset in bytes
+        let mut line = vec![0u8; 2 * area.w];
+        for i in 0..area.h {
+            if flipped {
+                // send lines bottom to top, pixels right to left
+                let ofs = start + (area.h - 1 - i) * stride;
+                let src = self.fb565_raw[ofs..ofs + 2 * area.w].chunks_exact(2).rev();
+                for (dest, p) in line.chunks_exact_mut(2).zip(src) {
+                    dest.copy_from_slice(p);
+                }
+            } else {
+                let ofs = start + i * stride;
+                line.copy_from_slice(&self.fb565_raw[ofs..ofs + 2 * area.w]);
+            }
+            serial_port::write_all(self.port.as_mut(), &line)?;
+        }
+        self.port.flush()?;
+
+        Ok(())
+    }
+
+    fn send_brightness(&mut self, level: usize) -> Res<()> {
+        let value = match self.sub_revision {
+            SubRevision::A11 | SubRevision::A12 => min(level, 255),
+            // Brightness is 1 (off) or 0 (full brightness)
+            SubRevision::A01 | SubRevision::A02 => usize::from(level == 0),
+        };
+        self.write(cmd!(Command::SetBrightness, value))?;
+        Ok(())
+    }
+}
+
+impl Screen for ScreenRevB {
+    fn screen_size(&self) -> (usize, usize) {
+        match self.orientation {
+            Orientation::Portrait | Orientation::ReversePortrait => (WIDTH, HEIGHT),
+            Orientation::Landscape | Orientation::ReverseLandscape => (HEIGHT, WIDTH),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Res<usize> {
+        serial_port::write_command(self.port.as_mut(), data)?;
+        Ok(data.len())
+    }
+
+    fn read(&mut self, n: usize) -> Res<Vec<u8>> {
+        let mut data = vec![0; n];
+        self.port.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn init(&mut self) -> Res<()> {
+        log::debug!("init screen");
+        self.write(HELLO)?;
+
+        let res = self.read(10)?;
+        if res[0] != Command::Hello as u8 || res[9] != Command::Hello as u8 {
+            return Err(Error::IncompatibleModel(res));
+        }
+
+        self.sub_revision = match (res[6], res[7]) {
+            (0x0a, 0x02) => SubRevision::A02,
+            (0x0a, 0x11) => SubRevision::A11,
+            (0x0a, 0x12) => SubRevision::A12,
+            _ => SubRevision::A01,
+        };
+        log::debug!("hardware sub-revision: {:?}", self.sub_revision);
+
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Res<()> {
+        log::debug!("clear screen");
+        // Revision B has no clear command, display a white image instead
+        let o = self.orientation.clone();
+        self.set_orientation(Orientation::Portrait)?;
+        let blank = Image {
+            width: WIDTH,
+            height: HEIGHT,
+            buffer: vec![colors::WHITE; WIDTH * HEIGHT],
+        };
+        self.fb_valid = false;
+        self.display_image(&blank, &blank.full(), &Coord::new(0, 0))?;
+        self.set_orientation(o)?;
+        // A blank screen looks the same in all orientations
+        self.fb_valid = true;
+        Ok(())
+    }
+
+    fn screen_on(&mut self) -> Res<()> {
+        log::debug!("screen on");
+        // No native command, restore the last brightness level instead
+        self.send_brightness(self.brightness)
+    }
+
+    fn screen_off(&mut self) -> Res<()> {
+        log::debug!("screen off");
+        // No native command, set brightness to zero instead
+        self.send_brightness(0)
+    }
+
+    fn set_orientation(&mut self, o: Orientation) -> Res<()> {
+        log::debug!("set screen orientation to {:?}", o);
+        if o != self.orientation {
+            self.fb_valid = false;
+        }
+        self.orientation = o.clone();
+        self.write(cmd!(Command::SetOrientation, orientation(o)))?;
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, level: usize) -> Res<()> {
+        log::debug!("set screen brightness to {}", level);
+        self.brightness = level;
+        self.send_brightness(level)
+    }
+
+    /// Send an image to the screen.
+    ///
+    /// Display a cropped portion of the image on the turing screen. In
+    /// reverse orientations the bitmap is rotated 180 degrees before
+    /// being sent.
+    ///
+    /// * `image`: the RGBA image.
+    /// * `crop`: the area of the image to display.
+    /// * `pos`: the screen coordinates to show the cropped area.
+    fn display_image(&mut self, image: &Image, crop: &Rect, pos: &Coord) -> Res<()> {
+        log::debug!("display image {} {}", crop, pos);
+        let (width, height) = self.screen_size(); // size of screen in pixels
+        if pos.x > width || pos.y > height {
+            return Err(Error::OutOfBounds(crop.clone(), pos.clone()));
+        }
+        let r = crop.clip(crop.x + width - pos.x, crop.y + height - pos.y);
+
+        if r.w == 0 || r.h == 0 {
+            return Ok(());
+        }
+
+        let area = Rect::new(pos.x, pos.y, r.w, r.h);
+        let damage = self.downmix(image, &r, pos);
+
+        // Unknown device contents, send the whole area
+        if !self.fb_valid {
+            self.send_area(&area)?;
+            self.fb_valid = area == Rect::new(0, 0, width, height);
+            return Ok(());
+        }
+
+        for rect in damage.rects(UPDATE_OVERHEAD) {
+            // the mirror already has the pixels the device may have missed
+            self.send_area(&rect)
+                .inspect_err(|_| self.fb_valid = false)?;
+        }
+        Ok(())
+    }
+
+    fn framebuffer(&self) -> &[u8] {
+        &self.fb565_raw
+    }
+
+    fn restore_framebuffer(&mut self, fb: &[u8]) -> Res<()> {
+        if fb.len() != self.fb565_raw.len() {
+            return Err(Error::FramebufferSize(fb.len(), self.fb565_raw.len()));
+        }
+        self.fb565_raw.copy_from_slice(fb);
+        let (width, height) = self.screen_size();
+        self.send_area(&Rect::new(0, 0, width, height))
+            .inspect_err(|_| self.fb_valid = false)?;
+        self.fb_valid = true;
+        Ok(())
+    }
+    fn model(&self) -> String {
+        format!(
+            "XuanFang 3.5\" (revision B, hardware {:?})",
+            self.sub_revision
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::{FailingPort, FakePort};
+    use crate::Rgba;
+    use std::sync::atomic::Ordering;
+
+    fn fake_screen(port: FakePort) -> ScreenRevB {
+        ScreenRevB {
+            port: Box::new(port),
+            orientation: Orientation::Portrait,
+            sub_revision: SubRevision::A01,
+            brightness: 255,
+            fb565_raw: vec![0u8; 2 * WIDTH * HEIGHT],
+            fb_valid: false,
+        }
+    }
+
+    #[test]
+    fn test_screen_size() -> Res<()> {
+        let fake_port = FakePort::new(Vec::new());
+        let mut scr = fake_screen(fake_port);
+        assert_eq!(scr.screen_size(), (WIDTH, HEIGHT));
+        scr.set_orientation(Orientation::ReverseLandscape)?;
+        assert_eq!(scr.screen_size(), (HEIGHT, WIDTH));
+        Ok(())
+    }
+
+    #[test]
+    fn test_init() -> Res<()> {
+        let fake_port = FakePort::new(vec![
+            0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, // overwritten by hello
+            0xca, 0, 0, 0, 0, 0, 0x0a, 0x11, 0, 0xca,
+        ]);
+        let mut scr = fake_screen(fake_port);
+        scr.init()?;
+        assert_eq!(scr.sub_revision, SubRevision::A11);
+        assert_eq!(
+            scr.port.get_buf()[..10],
+            [0xca, 0x48, 0x45, 0x4c, 0x4c, 0x4f, 0, 0, 0, 0xca]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_generic() -> Res<()> {
+        let fake_port = FakePort::new(vec![
+            0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, // overwritten by hello
+            0xca, 0, 0, 0, 0, 0, 0, 0, 0, 0xca,
+        ]);
+        let mut scr = fake_screen(fake_port);
+        scr.sub_revision = SubRevision::A12;
+        scr.init()?;
+        assert_eq!(scr.sub_revision, SubRevision::A01);
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_fail() -> Res<()> {
+        let fake_port = FakePort::new(vec![
+            0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, // overwritten by hello
+            1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        ]);
+        let mut scr = fake_screen(fake_port);
+        assert!(scr.init().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_orientation() -> Res<()> {
+        let fake_port = FakePort::new(Vec::new());
+        let mut scr = fake_screen(fake_port);
+        scr.set_orientation(Orientation::ReverseLandscape)?;
+        assert_eq!(scr.port.get_buf(), vec![0xcb, 1, 0, 0, 0, 0, 0, 0, 0, 0xcb]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_brightness() -> Res<()> {
+        let fake_port = FakePort::new(Vec::new());
+        let mut scr = fake_screen(fake_port);
+        scr.sub_revision = SubRevision::A11;
+        scr.set_brightness(0x55)?;
+        assert_eq!(
+            scr.port.get_buf(),
+            vec![0xce, 0x55, 0, 0, 0, 0, 0, 0, 0, 0xce]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_brightness_on_off() -> Res<()> {
+        let fake_port = FakePort::new(Vec::new());
+        let mut scr = fake_screen(fake_port);
+        scr.set_brightness(0x55)?;
+        scr.set_brightness(0)?;
+        assert_eq!(
+            scr.port.get_buf(),
+            vec![
+                0xce, 0, 0, 0, 0, 0, 0, 0, 0, 0xce, // full brightness
+                0xce, 1, 0, 0, 0, 0, 0, 0, 0, 0xce, // off
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_screen_on_off() -> Res<()> {
+        let fake_port = FakePort::new(Vec::new());
+        let mut scr = fake_screen(fake_port);
+        scr.sub_revision = SubRevision::A12;
+        scr.brightness = 0x80;
+        scr.screen_off()?;
+        scr.screen_on()?;
+        assert_eq!(
+            scr.port.get_buf(),
+            vec![
+                0xce, 0, 0, 0, 0, 0, 0, 0, 0, 0xce, // screen off
+                0xce, 0x80, 0, 0, 0, 0, 0, 0, 0, 0xce, // screen on
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image() -> Res<()> {
+        let fake_port = FakePort::new(Vec::<u8>::new());
+        let mut scr = fake_screen(fake_port);
+        let mut image = Image::new(320, 2);
+        image.buffer[321] = Rgba::new(0xff, 0x00, 0xff, 0xff);
+        image.buffer[322] = Rgba::new(0x55, 0xaa, 0xff, 0xff);
+        scr.display_image(&image, &Rect::new(1, 1, 2, 1), &Coord::new(1, 1))?;
+        assert_eq!(
+            scr.port.get_buf(),
+            vec![
+                0xcc, 0, 1, 0, 1, 0, 2, 0, 1, 0xcc, // Command::DisplayBitmap
+                0xf8, 0x1f, 0x55, 0x5f, // RGB565 big endian
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_flipped() -> Res<()> {
+        let fake_port = FakePort::new(Vec::<u8>::new());
+        let mut scr = fake_screen(fake_port);
+        scr.orientation = Orientation::ReversePortrait;
+        let mut image = Image::new(2, 2);
+        image.buffer[0] = Rgba::new(0xff, 0x00, 0xff, 0xff);
+        image.buffer[3] = Rgba::new(0x55, 0xaa, 0xff, 0xff);
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+        assert_eq!(
+            scr.port.get_buf(),
+            vec![
+                0xcc, 0x01, 0x3e, 0x01, 0xde, 0x01, 0x3f, 0x01, 0xdf,
+                0xcc, // (318,478)-(319,479)
+                0x55, 0x5f, 0x00, 0x00, // bottom line, reversed
+                0x00, 0x00, 0xf8, 0x1f, // top line, reversed
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_offscreen() -> Res<()> {
+        let fake_port = FakePort::new(Vec::<u8>::new());
+        let mut scr = fake_screen(fake_port);
+        let image = Image::new(320, 2);
+        scr.display_image(&image, &Rect::new(10, 20, 0, 0), &Coord::new(0, 0))?;
+        assert_eq!(scr.port.get_buf(), vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clear() -> Res<()> {
+        let fake_port = FakePort::new(Vec::new());
+        let mut scr = fake_screen(fake_port);
+        scr.orientation = Orientation::Landscape;
+        scr.clear()?;
+        let buf = scr.port.get_buf();
+        assert_eq!(buf.len(), 10 + 10 + 2 * WIDTH * HEIGHT + 10);
+        assert_eq!(buf[..10], [0xcb, 0, 0, 0, 0, 0, 0, 0, 0, 0xcb]);
+        assert_eq!(buf[10..20], [0xcc, 0, 0, 0, 0, 1, 0x3f, 1, 0xdf, 0xcc]);
+        assert!(buf[20..buf.len() - 10].iter().all(|&b| b == 0xff));
+        assert_eq!(buf[buf.len() - 10..], [0xcb, 1, 0, 0, 0, 0, 0, 0, 0, 0xcb]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_changed() -> Res<()> {
+        let fake_port = FakePort::new(Vec::new());
+        let mut scr = fake_screen(fake_port);
+        scr.fb_valid = true;
+        let mut image = Image::new(4, 4);
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+        assert_eq!(scr.port.get_buf(), vec![]);
+
+        image.buffer[5] = colors::WHITE;
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+        assert_eq!(
+            scr.port.get_buf(),
+            vec![0xcc, 0, 1, 0, 1, 0, 1, 0, 1, 0xcc, 0xff, 0xff]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_failed() -> Res<()> {
+        let port = FailingPort::default();
+        let mut scr = ScreenRevB::with_port(Box::new(port.clone()));
+        scr.fb_valid = true;
+        let mut image = Image::new(4, 4);
+        image.buffer.fill(colors::WHITE);
+
+        // the device may have missed any of the pixels
+        port.fail.store(true, Ordering::SeqCst);
+        assert!(scr
+            .display_image(&image, &image.full(), &Coord::new(1, 1))
+            .is_err());
+        port.fail.store(false, Ordering::SeqCst);
+        scr.display_image(&image, &image.full(), &Coord::new(1, 1))?;
+        assert!(!port.written.lock().unwrap().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,632 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::cmp::min;
+use std::io::Read;
+
+use crate::damage::Damage;
+use crate::serial_port;
+use crate::{Coord, Image, Rect};
+use crate::{Error, Orientation, Res, Screen, ScreenPort};
+
+// Constants and protocol definitions from
+// https://github.com/mathoudebine/turing-smart-screen-python
+
+pub(crate) const BAUD_RATE: u32 = 921_600;
+
+// Commands are padded to a multiple of this size
+const PACKET_SIZE: usize = 250;
+
+// Maximum size of the device status response
+const STATUS_SIZE: usize = 1024;
+
+// Maximum number of bitmap retransmissions requested by the device
+const MAX_RESEND: usize = 3;
+
+// Cost in pixels of sending an additional area, each update waits for
+// the device status
+const UPDATE_OVERHEAD: usize = 1024;
+
+const HELLO: &[u8] = &[
+    0x01, 0xef, 0x69, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xc5, 0xd3,
+];
+const OPTIONS: &[u8] = &[
+    0x7d, 0xef, 0x69, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x2d,
+];
+const _RESTART: &[u8] = &[0x84, 0xef, 0x69, 0x00, 0x00, 0x00, 0x01];
+const TURN_OFF: &[u8] = &[0x83, 0xef, 0x69, 0x00, 0x00, 0x00, 0x01];
+const SET_BRIGHTNESS: &[u8] = &[0x7b, 0xef, 0x69, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00];
+const STOP_VIDEO: &[u8] = &[0x79, 0xef, 0x69, 0x00, 0x00, 0x00, 0x01];
+const STOP_MEDIA: &[u8] = &[0x96, 0xef, 0x69, 0x00, 0x00, 0x00, 0x01];
+const QUERY_STATUS: &[u8] = &[0xcf, 0xef, 0x69, 0x00, 0x00, 0x00, 0x01];
+const PRE_UPDATE_BITMAP: &[u8] = &[0x86, 0xef, 0x69, 0x00, 0x00, 0x00, 0x01];
+const START_DISPLAY_BITMAP: &[u8] = &[0x2c];
+const DISPLAY_BITMAP: &[u8] = &[0xc8, 0xef, 0x69]; // followed by the 32-bit PNG size
+const UPDATE_BITMAP: &[u8] = &[0xcc, 0xef, 0x69, 0x00]; // followed by size and count
+const END_OF_PAYLOAD: &[u8] = &[0xef, 0x69];
+
+// Options
+const STARTMODE_DEFAULT: u8 = 0x00;
+const NO_FLIP: u8 = 0x00;
+const FLIP_180: u8 = 0x01;
+const SLEEP_OFF: u8 = 0x00;
+
+// Hardware subrevisions, reported in the hello response
+#[derive(Debug, Clone, PartialEq)]
+enum SubRevision {
+    Round21, // 2.1" round display, 480x480
+    Panel5,  // 5" display, 800x480
+    Bar88,   // 8.8" bar display, 1920x480
+}
+
+impl SubRevision {
+    fn from_hello(res: &[u8]) -> Option<Self> {
+        if res.starts_with(b"chs_21inch") {
+            Some(Self::Round21)
+        } else if res.starts_with(b"chs_5inch") {
+            Some(Self::Panel5)
+        } else if res.starts_with(b"chs_88inch") {
+            Some(Self::Bar88)
+        } else {
+            None
+        }
+    }
+
+    // Panel size in its native (landscape) orientation
+    fn native_size(&self) -> (usize, usize) {
+        match self {
+            Self::Round21 => (480, 480),
+            Self::Panel5 => (800, 480),
+            Self::Bar88 => (1920, 480),
+        }
+    }
+}
+
+fn flip(o: &Orientation) -> u8 {
+    match o {
+        Orientation::Portrait | Orientation::Landscape => NO_FLIP,
+        Orientation::ReversePortrait | Orientation::ReverseLandscape => FLIP_180,
+    }
+}
+
+pub struct ScreenRevC {
+    port: Box<dyn ScreenPort>,
+    orientation: Orientation,
+    sub_revision: SubRevision,
+    brightness: usize,
+    update_count: u32,
+    fb565_raw: Vec<u8>,
+    fb_valid: bool, // the framebuffer mirror matches the device
+}
+
+impl ScreenRevC {
+    pub fn new(portname: &str) -> Res<Self> {
+        let name = match portname {
+            "AUTO" => serial_port::detect("20080411")?,
+            name => name.to_string(),
+        };
+        log::debug!("create screen rev C on {}", name);
+
+        let port = serial_port::SerialPort::new(&name, BAUD_RATE)?;
+        Ok(Self::with_port(Box::new(port)))
+    }
+
+    /// Create a screen on an already opened port.
+    pub fn with_port(port: Box<dyn ScreenPort>) -> Self {
+        let sub_revision = SubRevision::Panel5;
+        let (width, height) = sub_revision.native_size();
+        Self {
+            port,
+            orientation: Orientation::Portrait,
+            sub_revision,
+            brightness: 255,
+            update_count: 0,
+            fb565_raw: vec![0u8; 2 * width * height],
+            fb_valid: false,
+        }
+    }
+
+    // Send a command padded to the packet size.
+    fn send_command(&mut self, cmd: &[u8], payload: &[u8], padding: u8) -> Res<()> {
+        let mut msg = Vec::with_capacity(cmd.len() + payload.len() + PACKET_SIZE);
+        msg.extend_from_slice(cmd);
+        msg.extend_from_slice(payload);
+        msg.resize(msg.len().div_ceil(PACKET_SIZE) * PACKET_SIZE, padding);
+        serial_port::write_command(self.port.as_mut(), &msg)
+    }
+
+    // Read the device status. The response length varies, so a single
+    // read is issued instead of waiting for the whole buffer.
+    fn read_status(&mut self) -> Res<String> {
+        let mut data = vec![0; STATUS_SIZE];
+        let n = self.port.read(&mut data)?;
+        data.truncate(n);
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    }
+
+    // Ask the device whether the last bitmap was received correctly.
+    fn query_status(&mut self) -> Res<bool> {
+        self.send_command(QUERY_STATUS, &[], 0)?;
+        let status = self.read_status()?;
+        log::debug!("device status: {}", status.trim_end_matches('\0'));
+        Ok(!status.is_empty() && !status.contains("needReSend:1"))
+    }
+
+    // Convert a rectangle in screen coordinates to panel coordinates.
+    // Portrait orientations are rotated 90 degrees counterclockwise, the
+    // reverse orientations are flipped by the device itself.
+    fn to_native(&self, r: &Rect) -> Rect {
+        let (_, native_height) = self.sub_revision.native_size();
+        match self.orientation {
+            Orientation::Portrait | Orientation::ReversePortrait => {
+                Rect::new(r.y, native_height - r.x - r.w, r.h, r.w)
+            }
+            Orientation::Landscape | Orientation::ReverseLandscape => r.clone(),
+        }
+    }
+
+    // RGB565 bit packing:
+    // [rrrr rggg] [gggb bbbb]  =(LE)=>  [gggb bbbb] [rrrr rggg]
+    //
+    // Returns the pixels that differ from the framebuffer mirror, in screen
+    // coordinates.
+    fn downmix(&mut self, image: &Image, rect: &Rect, pos: &Coord) -> Damage {
+        let (native_width, native_height) = self.sub_revision.native_size();
+        let portrait = matches!(
+            self.orientation,
+            Orientation::Portrait | Orientation::ReversePortrait
+        );
+        let mut damage = Damage::new(&Rect::new(pos.x, pos.y, rect.w, rect.h));
+
+        for y in 0..rect.h {
+            let ofs888 = (rect.y + y) * image.width + rect.x; // image vector offset in pixels
+            for (x, p) in image.buffer[ofs888..ofs888 + rect.w].iter().enumerate() {
+                let (sx, sy) = (pos.x + x, pos.y + y); // screen coordinates
+                let (nx, ny) = if portrait {
+                    (sy, native_height - 1 - sx)
+                } else {
+                    (sx, sy)
+                };
+                let dest = 2 * (ny * native_width + nx);
+                let lo = ((p.g & 0x1c) << 3) | (p.b >> 3);
+                let hi = (p.r & 0xf8) | (p.g >> 5);
+                if self.fb565_raw[dest] != lo || self.fb565_raw[dest + 1] != hi {
+                    self.fb565_raw[dest] = lo;
+                    self.fb565_raw[dest + 1] = hi;
+                    damage.add(sx, sy);
+                }
+            }
+        }
+        damage
+    }
+
+    // Encode the whole framebuffer as a PNG image in native orientation.
+    fn encode_png(&self) -> Res<Vec<u8>> {
+        let (width, height) = self.sub_revision.native_size();
+        let mut rgb = Vec::with_capacity(3 * width * height);
+        for p in self.fb565_raw.chunks_exact(2) {
+            let (lo, hi) = (p[0], p[1]);
+            let r = hi & 0xf8;
+            let g = ((hi & 0x07) << 5) | ((lo & 0xe0) >> 3);
+            let b = lo << 3;
+            rgb.extend_from_slice(&[r | (r >> 5), g | (g >> 6), b | (b >> 5)]);
+        }
+
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&rgb)?;
+        Ok(data)
+    }
+
+    fn send_full_frame(&mut self) -> Res<()> {
+        let png = self.encode_png()?;
+        for _ in 0..MAX_RESEND {
+            self.send_command(PRE_UPDATE_BITMAP, &[], 0)?;
+            self.send_command(START_DISPLAY_BITMAP, &[], START_DISPLAY_BITMAP[0])?;
+            self.send_command(DISPLAY_BITMAP, &(png.len() as u32).to_be_bytes(), 0)?;
+            self.send_command(&png, &[], 0)?;
+            if self.query_status()? {
+                self.fb_valid = true;
+                return Ok(());
+            }
+            log::debug!("device requested full frame retransmission");
+        }
+        Err(Error::NotAcknowledged)
+    }
+
+    // Partial updates are sent as a sequence of lines in native orientation,
+    // each one prefixed with its framebuffer address and width.
+    fn send_update(&mut self, r: &Rect) -> Res<()> {
+        let (native_width, _) = self.sub_revision.native_size();
+        let mut msg = Vec::with_capacity(r.h * (5 + 2 * r.w));
+        for y in r.y..r.y + r.h {
+            let addr = y * native_width + r.x;
+            msg.extend_from_slice(&(addr as u32).to_be_bytes()[1..]);
+            msg.extend_from_slice(&(r.w as u16).to_be_bytes());
+            let ofs = 2 * addr;
+            msg.extend_from_slice(&self.fb565_raw[ofs..ofs + 2 * r.w]);
+        }
+
+        // The update size includes the end of payload marker
+        let size = msg.len() + END_OF_PAYLOAD.len();
+
+        // Long payloads have a zero byte inserted every 249 bytes
+        let mut payload = Vec::with_capacity(size + size / (PACKET_SIZE - 1) + 1);
+        if msg.len() > PACKET_SIZE {
+            for (i, chunk) in msg.chunks(PACKET_SIZE - 1).enumerate() {
+                if i > 0 {
+                    payload.push(0);
+                }
+                payload.extend_from_slice(chunk);
+            }
+        } else {
+            payload.extend_from_slice(&msg);
+        }
+        payload.extend_from_slice(END_OF_PAYLOAD);
+
+        let mut header = Vec::with_capacity(10);
+        header.extend_from_slice(&(size as u32).to_be_bytes()[1..]);
+        header.extend_from_slice(&[0, 0, 0]);
+        header.extend_from_slice(&self.update_count.to_be_bytes());
+
+        for _ in 0..MAX_RESEND {
+            self.send_command(UPDATE_BITMAP, &header, 0)?;
+            self.send_command(&payload, &[], 0)?;
+            if self.query_status()? {
+                self.update_count = self.update_count.wrapping_add(1);
+                return Ok(());
+            }
+            log::debug!("device requested bitmap update retransmission");
+        }
+        Err(Error::NotAcknowledged)
+    }
+}
+
+impl Screen for ScreenRevC {
+    fn screen_size(&self) -> (usize, usize) {
+        let (width, height) = self.sub_revision.native_size();
+        match self.orientation {
+            Orientation::Portrait | Orientation::ReversePortrait => (height, width),
+            Orientation::Landscape | Orientation::ReverseLandscape => (width, height),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Res<usize> {
+        serial_port::write_command(self.port.as_mut(), data)?;
+        Ok(data.len())
+    }
+
+    fn read(&mut self, n: usize) -> Res<Vec<u8>> {
+        let mut data = vec![0; n];
+        self.port.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn init(&mut self) -> Res<()> {
+        log::debug!("init screen");
+        self.send_command(HELLO, &[], 0)?;
+
+        let res = self.read(23)?;
+        self.sub_revision = match SubRevision::from_hello(&res) {
+            Some(sub_revision) => sub_revision,
+            None => return Err(Error::IncompatibleModel(res)),
+        };
+        log::debug!("hardware sub-revision: {:?}", self.sub_revision);
+
+        let (width, height) = self.sub_revision.native_size();
+        self.fb565_raw = vec![0u8; 2 * width * height];
+        self.fb_valid = false;
+
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Res<()> {
+        log::debug!("clear screen");
+        // Revision C has no clear command, display a white image instead
+        self.fb565_raw.fill(0xff);
+        self.send_full_frame()
+    }
+
+    fn screen_on(&mut self) -> Res<()> {
+        log::debug!("screen on");
+        self.send_command(STOP_VIDEO, &[], 0)?;
+        self.send_command(STOP_MEDIA, &[], 0)?;
+        self.read_status()?;
+        self.send_command(SET_BRIGHTNESS, &[min(self.brightness, 255) as u8], 0)
+    }
+
+    fn screen_off(&mut self) -> Res<()> {
+        log::debug!("screen off");
+        self.send_command(STOP_VIDEO, &[], 0)?;
+        self.send_command(STOP_MEDIA, &[], 0)?;
+        self.read_status()?;
+        self.send_command(TURN_OFF, &[], 0)
+    }
+
+    fn set_orientation(&mut self, o: Orientation) -> Res<()> {
+        log::debug!("set screen orientation to {:?}", o);
+        if o != self.orientation {
+            self.fb_valid = false;
+        }
+        let options = [STARTMODE_DEFAULT, 0, flip(&o), SLEEP_OFF];
+        self.orientation = o;
+        self.send_command(OPTIONS, &options, 0)
+    }
+
+    fn set_brightness(&mut self, level: usize) -> Res<()> {
+        log::debug!("set screen brightness to {}", level);
+        self.brightness = level;
+        self.send_command(SET_BRIGHTNESS, &[min(level, 255) as u8], 0)
+    }
+
+    /// Send an image to the screen.
+    ///
+    /// Display a cropped portion of the image on the turing screen. Full
+    /// screen images are sent as PNG, smaller areas as RGB565 updates.
+    ///
+    /// * `image`: the RGBA image.
+    /// * `crop`: the area of the image to display.
+    /// * `pos`: the screen coordinates to show the cropped area.
+    fn display_image(&mut self, image: &Image, crop: &Rect, pos: &Coord) -> Res<()> {
+        log::debug!("display image {} {}", crop, pos);
+        let (width, height) = self.screen_size(); // size of screen in pixels
+        if pos.x > width || pos.y > height {
+            return Err(Error::OutOfBounds(crop.clone(), pos.clone()));
+        }
+        let r = crop.clip(crop.x + width - pos.x, crop.y + height - pos.y);
+
+        if r.w == 0 || r.h == 0 {
+            return Ok(());
+        }
+
+        let area = Rect::new(pos.x, pos.y, r.w, r.h);
+        let damage = self.downmix(image, &r, pos);
+
+        if area == Rect::new(0, 0, width, height) {
+            return self
+                .send_full_frame()
+                .inspect_err(|_| self.fb_valid = false);
+        }
+
+        // Unknown device contents, send the whole area
+        if !self.fb_valid {
+            return self.send_update(&self.to_native(&area));
+        }
+
+        for rect in damage.rects(UPDATE_OVERHEAD) {
+            // the mirror already has the pixels the device may have missed
+            self.send_update(&self.to_native(&rect))
+                .inspect_err(|_| self.fb_valid = false)?;
+        }
+        Ok(())
+    }
+
+    fn framebuffer(&self) -> &[u8] {
+        &self.fb565_raw
+    }
+
+    fn restore_framebuffer(&mut self, fb: &[u8]) -> Res<()> {
+        if fb.len() != self.fb565_raw.len() {
+            return Err(Error::FramebufferSize(fb.len(), self.fb565_raw.len()));
+        }
+        self.fb565_raw.copy_from_slice(fb);
+        self.send_full_frame()
+            .inspect_err(|_| self.fb_valid = false)
+    }
+    fn model(&self) -> String {
+        let size = match self.sub_revision {
+            SubRevision::Round21 => "2.1\" round",
+            SubRevision::Panel5 => "5\"",
+            SubRevision::Bar88 => "8.8\" bar",
+        };
+        format!("Turing Smart Screen {} (revision C)", size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colors;
+    use crate::tests::{FailingPort, FakePort};
+    use crate::Rgba;
+    use std::sync::atomic::Ordering;
+
+    fn fake_screen(port: FakePort) -> ScreenRevC {
+        ScreenRevC {
+            port: Box::new(port),
+            orientation: Orientation::Portrait,
+            sub_revision: SubRevision::Panel5,
+            brightness: 255,
+            update_count: 0,
+            fb565_raw: vec![0u8; 2 * 800 * 480],
+            fb_valid: false,
+        }
+    }
+
+    // Data written before the first read is overwritten in the fake port
+    fn fake_port(written: usize, response: &[u8]) -> FakePort {
+        let mut data = vec![0u8; written];
+        data.extend_from_slice(response);
+        FakePort::new(data)
+    }
+
+    #[test]
+    fn test_screen_size() -> Res<()> {
+        let fake_port = FakePort::new(Vec::new());
+        let mut scr = fake_screen(fake_port);
+        assert_eq!(scr.screen_size(), (480, 800));
+        scr.set_orientation(Orientation::Landscape)?;
+        assert_eq!(scr.screen_size(), (800, 480));
+        scr.sub_revision = SubRevision::Bar88;
+        assert_eq!(scr.screen_size(), (1920, 480));
+        Ok(())
+    }
+
+    #[test]
+    fn test_init() -> Res<()> {
+        let fake_port = fake_port(PACKET_SIZE, b"chs_88inch.dev1_rom1.87");
+        let mut scr = fake_screen(fake_port);
+        scr.init()?;
+        assert_eq!(scr.sub_revision, SubRevision::Bar88);
+        assert_eq!(scr.fb565_raw.len(), 2 * 1920 * 480);
+        assert_eq!(scr.port.get_buf()[..HELLO.len()], *HELLO);
+        assert!(scr.port.get_buf()[HELLO.len()..PACKET_SIZE]
+            .iter()
+            .all(|&b| b == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_fail() -> Res<()> {
+        let fake_port = fake_port(PACKET_SIZE, b"USB35INCHIPSV2 00000000");
+        let mut scr = fake_screen(fake_port);
+        assert!(scr.init().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_orientation() -> Res<()> {
+        let fake_port = FakePort::new(Vec::new());
+        let mut scr = fake_screen(fake_port);
+        scr.set_orientation(Orientation::ReversePortrait)?;
+        let buf = scr.port.get_buf();
+        assert_eq!(buf.len(), PACKET_SIZE);
+        assert_eq!(buf[..OPTIONS.len()], *OPTIONS);
+        assert_eq!(buf[OPTIONS.len()..OPTIONS.len() + 4], [0, 0, 1, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_brightness() -> Res<()> {
+        let fake_port = FakePort::new(Vec::new());
+        let mut scr = fake_screen(fake_port);
+        scr.set_brightness(0x155)?;
+        let buf = scr.port.get_buf();
+        assert_eq!(buf.len(), PACKET_SIZE);
+        assert_eq!(buf[..SET_BRIGHTNESS.len()], *SET_BRIGHTNESS);
+        assert_eq!(buf[SET_BRIGHTNESS.len()], 0xff);
+        Ok(())
+    }
+
+    #[test]
+    fn test_screen_off() -> Res<()> {
+        let fake_port = fake_port(2 * PACKET_SIZE, b"media_stop");
+        let mut scr = fake_screen(fake_port);
+        scr.screen_off()?;
+        let buf = scr.port.get_buf();
+        assert_eq!(buf[..STOP_VIDEO.len()], *STOP_VIDEO);
+        assert_eq!(
+            buf[PACKET_SIZE..PACKET_SIZE + STOP_MEDIA.len()],
+            *STOP_MEDIA
+        );
+        let ofs = 2 * PACKET_SIZE + 10; // after status response
+        assert_eq!(buf[ofs..ofs + TURN_OFF.len()], *TURN_OFF);
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image() -> Res<()> {
+        let fake_port = fake_port(3 * PACKET_SIZE, b"ok");
+        let mut scr = fake_screen(fake_port);
+        let mut image = Image::new(4, 4);
+        image.buffer[5] = Rgba::new(0xff, 0x00, 0xff, 0xff);
+        image.buffer[6] = Rgba::new(0x55, 0xaa, 0xff, 0xff);
+        scr.display_image(&image, &Rect::new(1, 1, 2, 1), &Coord::new(1, 1))?;
+
+        // portrait (1,1)+2x1 is native (1,477)+1x2
+        let buf = scr.port.get_buf();
+        assert_eq!(
+            buf[..14],
+            [0xcc, 0xef, 0x69, 0x00, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            buf[PACKET_SIZE..PACKET_SIZE + 16],
+            [
+                0x05, 0xd2, 0xa1, 0x00, 0x01, 0x5f, 0x55, // 477 * 800 + 1
+                0x05, 0xd5, 0xc1, 0x00, 0x01, 0x1f, 0xf8, // 478 * 800 + 1
+                0xef, 0x69, // end of payload
+            ]
+        );
+        let ofs = 2 * PACKET_SIZE;
+        assert_eq!(buf[ofs..ofs + QUERY_STATUS.len()], *QUERY_STATUS);
+        assert_eq!(scr.update_count, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_resend() -> Res<()> {
+        // the first status read consumes the whole response buffer
+        let mut status = b"needReSend:1".to_vec();
+        status.resize(STATUS_SIZE, 0);
+        let mut response = vec![0u8; 3 * PACKET_SIZE];
+        response.extend_from_slice(&status);
+        response.extend_from_slice(&[0u8; 3 * PACKET_SIZE]);
+        response.extend_from_slice(b"ok");
+        let fake_port = FakePort::new(response);
+        let mut scr = fake_screen(fake_port);
+        scr.orientation = Orientation::Landscape;
+        let image = Image::new(4, 4);
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+        assert_eq!(scr.update_count, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_full() -> Res<()> {
+        let fake_port = FakePort::new(Vec::new());
+        let mut scr = fake_screen(fake_port);
+        scr.sub_revision = SubRevision::Round21;
+        scr.fb565_raw = vec![0u8; 2 * 480 * 480];
+        let image = Image::new(480, 480);
+        // no acknowledge from the device
+        assert!(scr
+            .display_image(&image, &image.full(), &Coord::new(0, 0))
+            .is_err());
+
+        let buf = scr.port.get_buf();
+        assert_eq!(buf[..PRE_UPDATE_BITMAP.len()], *PRE_UPDATE_BITMAP);
+        assert!(buf[PACKET_SIZE..2 * PACKET_SIZE].iter().all(|&b| b == 0x2c));
+        let ofs = 2 * PACKET_SIZE;
+        assert_eq!(buf[ofs..ofs + 3], *DISPLAY_BITMAP);
+        let size = u32::from_be_bytes(buf[ofs + 3..ofs + 7].try_into().unwrap()) as usize;
+        let ofs = 3 * PACKET_SIZE;
+        assert_eq!(
+            buf[ofs..ofs + 8],
+            [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]
+        );
+        let ofs = ofs + size.div_ceil(PACKET_SIZE) * PACKET_SIZE;
+        assert_eq!(buf[ofs..ofs + QUERY_STATUS.len()], *QUERY_STATUS);
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_offscreen() -> Res<()> {
+        let fake_port = FakePort::new(Vec::<u8>::new());
+        let mut scr = fake_screen(fake_port);
+        let image = Image::new(320, 2);
+        scr.display_image(&image, &Rect::new(10, 20, 0, 0), &Coord::new(0, 0))?;
+        assert_eq!(scr.port.get_buf(), vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_failed() -> Res<()> {
+        let port = FailingPort::default();
+        let mut scr = ScreenRevC::with_port(Box::new(port.clone()));
+        scr.fb_valid = true;
+        let mut image = Image::new(4, 4);
+        image.buffer.fill(colors::WHITE);
+
+        // the device may have missed any of the pixels
+        port.fail.store(true, Ordering::SeqCst);
+        assert!(scr
+            .display_image(&image, &image.full(), &Coord::new(1, 1))
+            .is_err());
+        port.fail.store(false, Ordering::SeqCst);
+        scr.display_image(&image, &image.full(), &Coord::new(1, 1))?;
+        assert!(!port.written.lock().unwrap().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,1055 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Linux system statistics, read from `/proc` and `/sys`.
+//!
+//! The counters of the kernel are cumulative: CPU usage and I/O rates are
+//! computed from the difference between two samples, which [`Sensors`]
+//! keeps between calls.
+
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::{Error, Res};
+
+pub use self::hwmon::{Hwmon, HwmonKind, HwmonSensor};
+pub use self::net::{format_bytes, format_rate};
+pub use self::net::{NetCounters, NetInterface, NetRate};
+
+mod hwmon;
+mod net;
+
+// Samples taken closer than this reuse the previous values, so that
+// readers of the same value at the same time agree.
+const MIN_PERIOD: Duration = Duration::from_millis(250);
+
+// The hwmon sensors of the CPU package temperature, by driver.
+const CPU_TEMPERATURES: &[&str] = &[
+    "coretemp/Package id 0",
+    "k10temp/Tctl",
+    "zenpower/Tdie",
+    "cpu_thermal/temp1",
+];
+
+// The unit of the sector counts of /proc/diskstats, whatever the device.
+const SECTOR_SIZE: u64 = 512;
+
+fn invalid(path: &Path) -> Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("cannot parse {}", path.display()),
+    )
+    .into()
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => 100.0 * part as f64 / total as f64,
+    }
+}
+
+/// Time spent by a CPU in each state, in clock ticks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuTimes {
+    pub user: u64,
+    pub nice: u64,
+    pub system: u64,
+    pub idle: u64,
+    pub iowait: u64,
+    pub irq: u64,
+    pub softirq: u64,
+    pub steal: u64,
+}
+
+impl CpuTimes {
+    // Parse the values of a cpu line of /proc/stat. The guest times are
+    // part of the user times.
+    fn parse(fields: &[&str]) -> Option<Self> {
+        let values: Vec<u64> = fields
+            .iter()
+            .map(|f| f.parse().ok())
+            .collect::<Option<_>>()?;
+        if values.len() < 4 {
+            return None;
+        }
+        let v = |i: usize| values.get(i).copied().unwrap_or(0);
+        Some(Self {
+            user: v(0),
+            nice: v(1),
+            system: v(2),
+            idle: v(3),
+            iowait: v(4),
+            irq: v(5),
+            softirq: v(6),
+            steal: v(7),
+        })
+    }
+
+    /// Ticks spent idle, including the time waiting for I/O.
+    pub fn idle(&self) -> u64 {
+        self.idle + self.iowait
+    }
+
+    /// Ticks spent in all the states.
+    pub fn total(&self) -> u64 {
+        self.user
+            + self.nice
+            + self.system
+            + self.idle
+            + self.iowait
+            + self.irq
+            + self.softirq
+            + self.steal
+    }
+
+    /// Percentage of busy time since `prev`.
+    pub fn usage_since(&self, prev: &CpuTimes) -> f64 {
+        let total = self.total().saturating_sub(prev.total());
+        let idle = self.idle().saturating_sub(prev.idle());
+        percent(total.saturating_sub(idle), total)
+    }
+}
+
+/// CPU times of `/proc/stat`, for all the CPUs and for each one by number.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CpuStat {
+    pub total: CpuTimes,
+    pub cores: BTreeMap<usize, CpuTimes>,
+}
+
+impl CpuStat {
+    fn parse(s: &str) -> Option<Self> {
+        let mut stat = Self::default();
+        let mut total = None;
+        for line in s.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let Some(name) = fields.first().and_then(|f| f.strip_prefix("cpu")) else {
+                continue;
+            };
+            let times = CpuTimes::parse(&fields[1..])?;
+            match name {
+                "" => total = Some(times),
+                n => {
+                    stat.cores.insert(n.parse().ok()?, times);
+                }
+            }
+        }
+        stat.total = total?;
+        Some(stat)
+    }
+
+    /// CPU usage since `prev`, for the CPUs in both samples.
+    pub fn usage_since(&self, prev: &CpuStat) -> CpuUsage {
+        CpuUsage {
+            total: self.total.usage_since(&prev.total),
+            cores: self
+                .cores
+                .iter()
+                .filter_map(|(n, times)| Some((*n, times.usage_since(prev.cores.get(n)?))))
+                .collect(),
+        }
+    }
+}
+
+/// CPU usage in percent, for all the CPUs and for each one by number.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CpuUsage {
+    pub total: f64,
+    pub cores: BTreeMap<usize, f64>,
+}
+
+/// Memory and swap sizes of `/proc/meminfo`, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemInfo {
+    pub total: u64,
+    pub free: u64,
+    /// Memory available without swapping, including reclaimable caches.
+    pub available: u64,
+    pub buffers: u64,
+    pub cached: u64,
+    pub swap_total: u64,
+    pub swap_free: u64,
+}
+
+impl MemInfo {
+    fn parse(s: &str) -> Option<Self> {
+        let mut values = BTreeMap::new();
+        for line in s.lines() {
+            let (key, value) = line.split_once(':')?;
+            let mut fields = value.split_whitespace();
+            let n: u64 = fields.next()?.parse().ok()?;
+            let n = match fields.next() {
+                Some("kB") => n * 1024,
+                _ => n,
+            };
+            values.insert(key.trim(), n);
+        }
+        let get = |key| values.get(key).copied();
+        let free = get("MemFree")?;
+        let (buffers, cached) = (get("Buffers").unwrap_or(0), get("Cached").unwrap_or(0));
+        Some(Self {
+            total: get("MemTotal")?,
+            free,
+            // estimated by the kernel since Linux 3.14
+            available: get("MemAvailable").unwrap_or(free + buffers + cached),
+            buffers,
+            cached,
+            swap_total: get("SwapTotal").unwrap_or(0),
+            swap_free: get("SwapFree").unwrap_or(0),
+        })
+    }
+
+    /// Memory in use, that is not available.
+    pub fn used(&self) -> u64 {
+        self.total.saturating_sub(self.available)
+    }
+
+    /// Percentage of memory in use.
+    pub fn percent(&self) -> f64 {
+        percent(self.used(), self.total)
+    }
+
+    /// Swap space in use.
+    pub fn swap_used(&self) -> u64 {
+        self.swap_total.saturating_sub(self.swap_free)
+    }
+
+    /// Percentage of swap space in use, 0 without swap.
+    pub fn swap_percent(&self) -> f64 {
+        percent(self.swap_used(), self.swap_total)
+    }
+}
+
+/// Load averages of `/proc/loadavg`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LoadAvg {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64,
+    /// Number of runnable tasks.
+    pub running: u32,
+    /// Number of tasks.
+    pub tasks: u32,
+}
+
+impl LoadAvg {
+    fn parse(s: &str) -> Option<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let (running, tasks) = fields.get(3)?.split_once('/')?;
+        Some(Self {
+            one: fields.first()?.parse().ok()?,
+            five: fields.get(1)?.parse().ok()?,
+            fifteen: fields.get(2)?.parse().ok()?,
+            running: running.parse().ok()?,
+            tasks: tasks.parse().ok()?,
+        })
+    }
+}
+
+/// Size and free space of a file system, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub total: u64,
+    pub free: u64,
+    /// Free space available to unprivileged users.
+    pub available: u64,
+}
+
+impl DiskUsage {
+    /// Read the usage of the file system containing `path`.
+    pub fn read<P: AsRef<Path>>(path: P) -> Res<Self> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let st = unsafe {
+            let mut st: libc::statvfs = std::mem::zeroed();
+            if libc::statvfs(path.as_ptr(), &mut st) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            st
+        };
+        let size = st.f_frsize as u64;
+        Ok(Self {
+            total: st.f_blocks as u64 * size,
+            free: st.f_bfree as u64 * size,
+            available: st.f_bavail as u64 * size,
+        })
+    }
+
+    /// Space in use.
+    pub fn used(&self) -> u64 {
+        self.total.saturating_sub(self.free)
+    }
+
+    /// Percentage of the space usable by unprivileged users in use, as
+    /// shown by df.
+    pub fn percent(&self) -> f64 {
+        percent(self.used(), self.used() + self.available)
+    }
+}
+
+/// I/O counters of a block device, from `/proc/diskstats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskCounters {
+    pub reads: u64,
+    pub read_bytes: u64,
+    pub writes: u64,
+    pub written_bytes: u64,
+    /// Milliseconds spent doing I/O.
+    pub busy_ms: u64,
+}
+
+impl DiskCounters {
+    fn parse(s: &str) -> Option<BTreeMap<String, Self>> {
+        let mut disks = BTreeMap::new();
+        for line in s.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 13 {
+                return None;
+            }
+            let v = |i: usize| fields[i].parse::<u64>().ok();
+            let counters = Self {
+                reads: v(3)?,
+                read_bytes: v(5)? * SECTOR_SIZE,
+                writes: v(7)?,
+                written_bytes: v(9)? * SECTOR_SIZE,
+                busy_ms: v(12)?,
+            };
+            disks.insert(fields[2].to_string(), counters);
+        }
+        Some(disks)
+    }
+
+    /// I/O rates since `prev`, sampled `elapsed` before.
+    pub fn rate_since(&self, prev: &DiskCounters, elapsed: Duration) -> DiskRate {
+        let secs = elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return DiskRate::default();
+        }
+        let rate = |now: u64, prev: u64| now.saturating_sub(prev) as f64 / secs;
+        DiskRate {
+            reads: rate(self.reads, prev.reads),
+            read_bytes: rate(self.read_bytes, prev.read_bytes),
+            writes: rate(self.writes, prev.writes),
+            written_bytes: rate(self.written_bytes, prev.written_bytes),
+            busy: (rate(self.busy_ms, prev.busy_ms) / 10.0).min(100.0),
+        }
+    }
+}
+
+/// I/O rates of a block device, per second.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DiskRate {
+    pub reads: f64,
+    pub read_bytes: f64,
+    pub writes: f64,
+    pub written_bytes: f64,
+    /// Percentage of time spent doing I/O.
+    pub busy: f64,
+}
+
+// A value of Sensors::value.
+enum Name<'a> {
+    CpuPercent(Option<usize>),
+    CpuFrequency(Option<usize>),
+    CpuTemperature,
+    Hwmon(&'a str),
+    Memory(&'a str),
+    Swap(&'a str),
+    Load(&'a str),
+    Uptime,
+    Disk(&'a str),
+    DiskIo(Option<&'a str>, &'a str),
+    Net(Option<&'a str>, &'a str),
+    NetLink(&'a str, &'a str),
+}
+
+impl<'a> Name<'a> {
+    fn parse(name: &'a str) -> Option<Self> {
+        if let Some(id) = name.strip_prefix("hwmon/") {
+            return Some(Name::Hwmon(id));
+        }
+        // interface names may contain dots, as VLANs
+        if let Some(name) = name.strip_prefix("net.") {
+            return Some(match name.rsplit_once('.') {
+                None => match name {
+                    "rx" | "tx" | "rx_total" | "tx_total" => Name::Net(None, name),
+                    _ => return None,
+                },
+                Some((iface, v @ ("rx" | "tx" | "rx_total" | "tx_total"))) => {
+                    Name::Net(Some(iface), v)
+                }
+                Some((iface, v @ ("up" | "speed" | "state" | "ipv4" | "ipv6"))) => {
+                    Name::NetLink(iface, v)
+                }
+                _ => return None,
+            });
+        }
+        let parts: Vec<&str> = name.split('.').collect();
+        Some(match parts[..] {
+            ["cpu", "percent"] => Name::CpuPercent(None),
+            ["cpu", n, "percent"] => Name::CpuPercent(Some(n.parse().ok()?)),
+            ["cpu", "frequency"] => Name::CpuFrequency(None),
+            ["cpu", n, "frequency"] => Name::CpuFrequency(Some(n.parse().ok()?)),
+            ["cpu", "temperature"] => Name::CpuTemperature,
+            ["memory", v @ ("percent" | "used" | "available" | "total")] => Name::Memory(v),
+            ["swap", v @ ("percent" | "used" | "total")] => Name::Swap(v),
+            ["load", v @ ("1" | "5" | "15")] => Name::Load(v),
+            ["uptime"] => Name::Uptime,
+            ["disk", v @ ("percent" | "used" | "free" | "total")] => Name::Disk(v),
+            ["disk", v @ ("read" | "write")] => Name::DiskIo(None, v),
+            ["disk", dev, v @ ("read" | "write" | "busy")] => Name::DiskIo(Some(dev), v),
+            _ => return None,
+        })
+    }
+}
+
+// A sample of counters by device, with the rates since the previous one.
+struct Sample<C, R> {
+    time: Instant,
+    counters: BTreeMap<String, C>,
+    rates: BTreeMap<String, R>,
+}
+
+// Sample the counters of `read` at `now`, unless the last sample is too
+// recent, and return the rates since the previous sample.
+fn sample<C, R: Clone + Default>(
+    last: &mut Option<Sample<C, R>>,
+    now: Instant,
+    read: impl FnOnce() -> Res<BTreeMap<String, C>>,
+    rate: impl Fn(&C, &C, Duration) -> R,
+) -> Res<BTreeMap<String, R>> {
+    if let Some(last) = last {
+        if now < last.time + MIN_PERIOD {
+            return Ok(last.rates.clone());
+        }
+    }
+    let counters = read()?;
+    let rates = counters
+        .iter()
+        .map(|(name, counters)| {
+            let prev = last
+                .as_ref()
+                .and_then(|last| Some((last.time, last.counters.get(name)?)));
+            let rate = match prev {
+                Some((time, prev)) => rate(counters, prev, now - time),
+                None => R::default(),
+            };
+            (name.clone(), rate)
+        })
+        .collect::<BTreeMap<_, _>>();
+    *last = Some(Sample {
+        time: now,
+        counters,
+        rates: rates.clone(),
+    });
+    Ok(rates)
+}
+
+/// Samples of the system statistics.
+///
+/// The previous samples of the CPU times, disk and network counters are
+/// kept to compute the CPU usage and the I/O rates: the first call returns
+/// the average CPU usage since boot, and no I/O.
+pub struct Sensors {
+    root: PathBuf,
+    cpu: Option<(Instant, CpuStat, CpuUsage)>,
+    disks: Option<Sample<DiskCounters, DiskRate>>,
+    net: Option<Sample<NetCounters, NetRate>>,
+    hwmon: Hwmon,
+}
+
+impl Default for Sensors {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sensors {
+    pub fn new() -> Self {
+        Self::with_root("/")
+    }
+
+    /// Read the `proc` and `sys` directories of another root, such as a
+    /// container or a copy for tests.
+    pub fn with_root<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            cpu: None,
+            disks: None,
+            net: None,
+            hwmon: Hwmon::with_root(root),
+        }
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+
+    fn parse<T>(&self, path: &str, parse: impl FnOnce(&str) -> Option<T>) -> Res<T> {
+        let path = self.path(path);
+        parse(&fs::read_to_string(&path)?).ok_or_else(|| invalid(&path))
+    }
+
+    /// Read the current CPU times.
+    pub fn cpu_stat(&self) -> Res<CpuStat> {
+        self.parse("proc/stat", CpuStat::parse)
+    }
+
+    /// CPU usage since the previous call.
+    pub fn cpu_usage(&mut self) -> Res<CpuUsage> {
+        self.cpu_usage_at(Instant::now())
+    }
+
+    fn cpu_usage_at(&mut self, now: Instant) -> Res<CpuUsage> {
+        if let Some((time, _, usage)) = &self.cpu {
+            if now < *time + MIN_PERIOD {
+                return Ok(usage.clone());
+            }
+        }
+        let stat = self.cpu_stat()?;
+        let usage = match &self.cpu {
+            Some((_, prev, _)) => stat.usage_since(prev),
+            None => stat.usage_since(&CpuStat {
+                total: CpuTimes::default(),
+                cores: stat
+                    .cores
+                    .keys()
+                    .map(|n| (*n, CpuTimes::default()))
+                    .collect(),
+            }),
+        };
+        self.cpu = Some((now, stat, usage.clone()));
+        Ok(usage)
+    }
+
+    /// Current frequency of each CPU by number, in MHz. CPUs without
+    /// frequency scaling are missing.
+    pub fn cpu_frequencies(&self) -> Res<BTreeMap<usize, f64>> {
+        let mut freqs = BTreeMap::new();
+        for entry in fs::read_dir(self.path("sys/devices/system/cpu"))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(n) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("cpu"))
+                .and_then(|n| n.parse().ok())
+            else {
+                continue;
+            };
+            let path = entry.path().join("cpufreq/scaling_cur_freq");
+            let Ok(s) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let khz: f64 = s.trim().parse().map_err(|_| invalid(&path))?;
+            freqs.insert(n, khz / 1000.0);
+        }
+        Ok(freqs)
+    }
+
+    /// Temperature of the CPU package, in degrees Celsius, from the hwmon
+    /// sensor of the CPU driver.
+    pub fn cpu_temperature(&mut self) -> Res<f64> {
+        for id in CPU_TEMPERATURES {
+            if let Ok(temp) = self.hwmon.read(id) {
+                return Ok(temp);
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, "no CPU temperature sensor").into())
+    }
+
+    /// The hwmon sensors.
+    pub fn hwmon(&mut self) -> &mut Hwmon {
+        &mut self.hwmon
+    }
+
+    /// Read the memory and swap sizes.
+    pub fn memory(&self) -> Res<MemInfo> {
+        self.parse("proc/meminfo", MemInfo::parse)
+    }
+
+    /// Read the load averages.
+    pub fn load(&self) -> Res<LoadAvg> {
+        self.parse("proc/loadavg", LoadAvg::parse)
+    }
+
+    /// Time since boot.
+    pub fn uptime(&self) -> Res<Duration> {
+        self.parse("proc/uptime", |s| {
+            let secs: f64 = s.split_whitespace().next()?.parse().ok()?;
+            Duration::try_from_secs_f64(secs).ok()
+        })
+    }
+
+    /// Read the current I/O counters of the block devices, including
+    /// partitions.
+    pub fn disk_counters(&self) -> Res<BTreeMap<String, DiskCounters>> {
+        self.parse("proc/diskstats", DiskCounters::parse)
+    }
+
+    /// I/O rates of the block devices since the previous call.
+    pub fn disk_rates(&mut self) -> Res<BTreeMap<String, DiskRate>> {
+        self.disk_rates_at(Instant::now())
+    }
+
+    fn disk_rates_at(&mut self, now: Instant) -> Res<BTreeMap<String, DiskRate>> {
+        let mut last = self.disks.take();
+        let rates = sample(
+            &mut last,
+            now,
+            || self.disk_counters(),
+            DiskCounters::rate_since,
+        );
+        self.disks = last;
+        rates
+    }
+
+    /// Read the current traffic counters of the network interfaces.
+    pub fn net_counters(&self) -> Res<BTreeMap<String, NetCounters>> {
+        self.parse("proc/net/dev", NetCounters::parse)
+    }
+
+    /// Traffic rates of the network interfaces since the previous call.
+    pub fn net_rates(&mut self) -> Res<BTreeMap<String, NetRate>> {
+        self.net_rates_at(Instant::now())
+    }
+
+    fn net_rates_at(&mut self, now: Instant) -> Res<BTreeMap<String, NetRate>> {
+        let mut last = self.net.take();
+        let rates = sample(
+            &mut last,
+            now,
+            || self.net_counters(),
+            NetCounters::rate_since,
+        );
+        self.net = last;
+        rates
+    }
+
+    /// Read the state of a network interface. The IP addresses are the
+    /// ones of the network namespace of the process, whatever the root.
+    pub fn interface(&self, name: &str) -> Res<NetInterface> {
+        let mut addresses = net::addresses()?;
+        let dir = self.path("sys/class/net").join(name);
+        NetInterface::read(&dir, addresses.remove(name).unwrap_or_default())
+    }
+
+    /// Read the state of the network interfaces.
+    pub fn interfaces(&self) -> Res<Vec<NetInterface>> {
+        let mut addresses = net::addresses()?;
+        let mut interfaces = Vec::new();
+        for entry in fs::read_dir(self.path("sys/class/net"))? {
+            let dir = entry?.path();
+            let name = dir.file_name().unwrap_or_default().to_string_lossy();
+            let addrs = addresses.remove(name.as_ref()).unwrap_or_default();
+            // interfaces may be removed meanwhile
+            match NetInterface::read(&dir, addrs) {
+                Ok(interface) => interfaces.push(interface),
+                Err(Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(interfaces)
+    }
+
+    /// Whether `name` is a value known by [`Sensors::text`].
+    pub fn is_value(name: &str) -> bool {
+        Name::parse(name).is_some()
+    }
+
+    /// Read a value by name:
+    ///
+    /// - `cpu.percent`, `cpu.N.percent`: CPU usage of all the CPUs or of
+    ///   CPU N.
+    /// - `cpu.frequency`, `cpu.N.frequency`: average CPU frequency or
+    ///   frequency of CPU N, in MHz.
+    /// - `cpu.temperature`: temperature of the CPU package, in degrees
+    ///   Celsius.
+    /// - `hwmon/CHIP/LABEL`: a hwmon sensor (see [`Hwmon`]).
+    /// - `memory.percent`, `memory.used`, `memory.available`,
+    ///   `memory.total`, `swap.percent`, `swap.used`, `swap.total`: memory
+    ///   and swap use, in bytes.
+    /// - `load.1`, `load.5`, `load.15`: load averages.
+    /// - `uptime`: seconds since boot.
+    /// - `disk.percent`, `disk.used`, `disk.free`, `disk.total`: use of the
+    ///   file system containing `path`, `/` by default.
+    /// - `disk.read`, `disk.write`: bytes read and written per second by
+    ///   all the disks.
+    /// - `disk.DEVICE.read`, `disk.DEVICE.write`, `disk.DEVICE.busy`: bytes
+    ///   per second and percentage of time busy of a block device.
+    /// - `net.rx`, `net.tx`, `net.rx_total`, `net.tx_total`: bytes
+    ///   received and sent per second and since boot by all the network
+    ///   interfaces but loopback.
+    /// - `net.IFACE.rx`, `net.IFACE.tx`, `net.IFACE.rx_total`,
+    ///   `net.IFACE.tx_total`: the same for a network interface.
+    /// - `net.IFACE.up`, `net.IFACE.speed`: 1 if the link is up, else 0,
+    ///   and link speed in Mbit/s.
+    pub fn value(&mut self, name: &str, path: Option<&Path>) -> Res<f64> {
+        let unknown = || Error::Config(format!("unknown sensor value '{}'", name));
+        let missing =
+            || io::Error::new(io::ErrorKind::NotFound, format!("no value for '{}'", name));
+        Ok(match Name::parse(name).ok_or_else(unknown)? {
+            Name::CpuPercent(None) => self.cpu_usage()?.total,
+            Name::CpuPercent(Some(n)) => *self.cpu_usage()?.cores.get(&n).ok_or_else(missing)?,
+            Name::CpuFrequency(None) => {
+                let freqs = self.cpu_frequencies()?;
+                if freqs.is_empty() {
+                    return Err(missing().into());
+                }
+                freqs.values().sum::<f64>() / freqs.len() as f64
+            }
+            Name::CpuFrequency(Some(n)) => *self.cpu_frequencies()?.get(&n).ok_or_else(missing)?,
+            Name::CpuTemperature => self.cpu_temperature()?,
+            Name::Hwmon(id) => self.hwmon.read(id)?,
+            Name::Memory(v) => {
+                let mem = self.memory()?;
+                match v {
+                    "percent" => mem.percent(),
+                    "used" => mem.used() as f64,
+                    "available" => mem.available as f64,
+                    _ => mem.total as f64,
+                }
+            }
+            Name::Swap(v) => {
+                let mem = self.memory()?;
+                match v {
+                    "percent" => mem.swap_percent(),
+                    "used" => mem.swap_used() as f64,
+                    _ => mem.swap_total as f64,
+                }
+            }
+            Name::Load(v) => {
+                let load = self.load()?;
+                match v {
+                    "1" => load.one,
+                    "5" => load.five,
+                    _ => load.fifteen,
+                }
+            }
+            Name::Uptime => self.uptime()?.as_secs_f64(),
+            Name::Disk(v) => {
+                let usage = DiskUsage::read(path.unwrap_or(Path::new("/")))?;
+                match v {
+                    "percent" => usage.percent(),
+                    "used" => usage.used() as f64,
+                    "free" => usage.available as f64,
+                    _ => usage.total as f64,
+                }
+            }
+            Name::DiskIo(dev, v) => {
+                let rates = self.disk_rates()?;
+                let rates: Vec<&DiskRate> = match dev {
+                    Some(dev) => vec![rates.get(dev).ok_or_else(missing)?],
+                    None => rates
+                        .iter()
+                        .filter(|(name, _)| self.is_disk(name))
+                        .map(|(_, rate)| rate)
+                        .collect(),
+                };
+                rates
+                    .iter()
+                    .map(|rate| match v {
+                        "read" => rate.read_bytes,
+                        "write" => rate.written_bytes,
+                        _ => rate.busy,
+                    })
+                    .sum()
+            }
+            Name::Net(iface, v @ ("rx" | "tx")) => {
+                let rates = self.net_rates()?;
+                let rates: Vec<&NetRate> = match iface {
+                    Some(iface) => vec![rates.get(iface).ok_or_else(missing)?],
+                    None => rates
+                        .iter()
+                        .filter(|(name, _)| *name != "lo")
+                        .map(|(_, rate)| rate)
+                        .collect(),
+                };
+                rates
+                    .iter()
+                    .map(|rate| if v == "rx" { rate.rx } else { rate.tx })
+                    .sum()
+            }
+            Name::Net(iface, v) => {
+                let counters = self.net_counters()?;
+                let counters: Vec<&NetCounters> = match iface {
+                    Some(iface) => vec![counters.get(iface).ok_or_else(missing)?],
+                    None => counters
+                        .iter()
+                        .filter(|(name, _)| *name != "lo")
+                        .map(|(_, counters)| counters)
+                        .collect(),
+                };
+                counters
+                    .iter()
+                    .map(|c| if v == "rx_total" { c.rx_bytes } else { c.tx_bytes } as f64)
+                    .sum()
+            }
+            Name::NetLink(iface, "up") => self.interface(iface)?.up as u8 as f64,
+            Name::NetLink(iface, "speed") => {
+                self.interface(iface)?.speed.ok_or_else(missing)? as f64
+            }
+            Name::NetLink(..) => {
+                return Err(Error::Config(format!(
+                    "sensor value '{}' is not a number",
+                    name
+                )))
+            }
+        })
+    }
+
+    /// Read a value by name as text: the values of [`Sensors::value`], or
+    ///
+    /// - `net.IFACE.state`: operational state of a network interface, such
+    ///   as "up" or "down".
+    /// - `net.IFACE.ipv4`, `net.IFACE.ipv6`: first IP address of a network
+    ///   interface, empty without address.
+    pub fn text(&mut self, name: &str, path: Option<&Path>) -> Res<String> {
+        let Some(Name::NetLink(iface, v @ ("state" | "ipv4" | "ipv6"))) = Name::parse(name) else {
+            return Ok(self.value(name, path)?.to_string());
+        };
+        let interface = self.interface(iface)?;
+        Ok(match v {
+            "state" => interface.state,
+            "ipv4" => interface.ipv4().map(|a| a.to_string()).unwrap_or_default(),
+            _ => interface.ipv6().map(|a| a.to_string()).unwrap_or_default(),
+        })
+    }
+
+    // Whether a block device is a whole disk rather than a partition, or a
+    // loop or RAM disk, whose I/O is counted with the disks holding them.
+    fn is_disk(&self, name: &str) -> bool {
+        !name.starts_with("loop")
+            && !name.starts_with("ram")
+            && self.path("sys/block").join(name).exists()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    const STAT: &str = "\
+cpu  100 0 50 800 50 0 0 0 0 0
+cpu0 60 0 20 400 20 0 0 0 0 0
+cpu1 40 0 30 400 30 0 0 0 0 0
+intr 1234 0 0
+ctxt 5678
+";
+
+    const DISKSTATS: &str = "\
+   8       0 sda 100 0 2000 50 200 0 4000 100 0 300 150 0 0 0 0
+   8       1 sda1 90 0 1800 40 190 0 3800 90 0 280 130 0 0 0 0
+   7       0 loop0 10 0 20 1 0 0 0 0 0 1 1 0 0 0 0
+";
+
+    fn fake_root(name: &str) -> Res<PathBuf> {
+        let root = env::temp_dir().join(format!("turing-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("proc/net"))?;
+        for cpu in ["cpu0", "cpu1"] {
+            fs::create_dir_all(
+                root.join("sys/devices/system/cpu")
+                    .join(cpu)
+                    .join("cpufreq"),
+            )?;
+        }
+        fs::create_dir_all(root.join("sys/devices/system/cpu/cpufreq"))?;
+        fs::create_dir_all(root.join("sys/block/sda"))?;
+        fs::create_dir_all(root.join("sys/block/loop0"))?;
+        fs::write(root.join("proc/stat"), STAT)?;
+        fs::write(root.join("proc/diskstats"), DISKSTATS)?;
+        Ok(root)
+    }
+
+    #[test]
+    fn test_cpu() -> Res<()> {
+        let root = fake_root("cpu")?;
+        let mut sensors = Sensors::with_root(&root);
+        let start = Instant::now();
+        let usage = sensors.cpu_usage_at(start)?;
+        assert_eq!(usage.total, 15.0);
+        assert_eq!(usage.cores[&0], 100.0 * 80.0 / 500.0);
+
+        fs::write(
+            root.join("proc/stat"),
+            "cpu  150 0 100 850 100 0 0 0 0 0\n\
+             cpu0 100 0 20 420 20 0 0 0 0 0\n\
+             cpu1 50 0 80 430 80 0 0 0 0 0\n",
+        )?;
+        // reused until the minimum period
+        assert_eq!(sensors.cpu_usage_at(start + MIN_PERIOD / 2)?, usage);
+        let usage = sensors.cpu_usage_at(start + Duration::from_secs(1))?;
+        assert_eq!(usage.total, 50.0);
+        assert_eq!(usage.cores[&0], 100.0 * 40.0 / 60.0);
+        assert_eq!(usage.cores[&1], 100.0 * 60.0 / 140.0);
+
+        let cpus = root.join("sys/devices/system/cpu");
+        fs::write(cpus.join("cpu0/cpufreq/scaling_cur_freq"), "1200000\n")?;
+        fs::write(cpus.join("cpu1/cpufreq/scaling_cur_freq"), "2400000\n")?;
+        assert_eq!(sensors.value("cpu.frequency", None)?, 1800.0);
+        assert_eq!(sensors.value("cpu.1.frequency", None)?, 2400.0);
+        assert!(sensors.value("cpu.2.frequency", None).is_err());
+
+        assert!(sensors.value("cpu.temperature", None).is_err());
+        let chip = root.join("sys/class/hwmon/hwmon0");
+        fs::create_dir_all(&chip)?;
+        fs::write(chip.join("name"), "k10temp\n")?;
+        fs::write(chip.join("temp1_input"), "52125\n")?;
+        fs::write(chip.join("temp1_label"), "Tctl\n")?;
+        sensors.hwmon().scan()?;
+        assert_eq!(sensors.value("cpu.temperature", None)?, 52.125);
+        assert_eq!(sensors.value("hwmon/k10temp/Tctl", None)?, 52.125);
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_io() -> Res<()> {
+        let root = fake_root("diskio")?;
+        let mut sensors = Sensors::with_root(&root);
+        let start = Instant::now();
+        let rates = sensors.disk_rates_at(start)?;
+        assert_eq!(rates.len(), 3);
+        assert_eq!(rates["sda"], DiskRate::default());
+
+        fs::write(
+            root.join("proc/diskstats"),
+            DISKSTATS
+                .replace(" 2000 ", " 4048 ")
+                .replace(" 300 ", " 800 "),
+        )?;
+        let rates = sensors.disk_rates_at(start + Duration::from_secs(2))?;
+        assert_eq!(rates["sda"].read_bytes, 2048.0 * 512.0 / 2.0);
+        assert_eq!(rates["sda"].written_bytes, 0.0);
+        assert_eq!(rates["sda"].busy, 25.0);
+        assert_eq!(rates["sda1"], DiskRate::default());
+
+        // partitions and loop devices are not counted in the totals
+        assert_eq!(sensors.value("disk.read", None)?, 2048.0 * 512.0 / 2.0);
+        assert_eq!(sensors.value("disk.sda.busy", None)?, 25.0);
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_net() -> Res<()> {
+        let root = fake_root("net")?;
+        let dev = |eth0_rx: u64, eth0_tx: u64| {
+            format!(
+                "Inter-|   Receive                            |  Transmit\n \
+                 face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed\n    \
+                 lo: 5000 50 0 0 0 0 0 0 5000 50 0 0 0 0 0 0\n  \
+                 eth0: {} 100 0 0 0 0 0 0 {} 80 0 0 0 0 0 0\n",
+                eth0_rx, eth0_tx
+            )
+        };
+        fs::write(root.join("proc/net/dev"), dev(1000, 2000))?;
+        for (name, state, carrier, speed) in
+            [("lo", "unknown", "1", ""), ("eth0", "up", "1", "1000")]
+        {
+            let dir = root.join("sys/class/net").join(name);
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("operstate"), format!("{}\n", state))?;
+            fs::write(dir.join("carrier"), format!("{}\n", carrier))?;
+            if !speed.is_empty() {
+                fs::write(dir.join("speed"), format!("{}\n", speed))?;
+            }
+        }
+
+        let mut sensors = Sensors::with_root(&root);
+        let start = Instant::now();
+        assert_eq!(sensors.net_rates_at(start)?["eth0"], NetRate::default());
+        fs::write(
+            root.join("proc/net/dev"),
+            dev(1000 + 3 * 1536, 2000 + 3 * 100),
+        )?;
+        let rates = sensors.net_rates_at(start + Duration::from_secs(3))?;
+        assert_eq!(
+            rates["eth0"],
+            NetRate {
+                rx: 1536.0,
+                tx: 100.0
+            }
+        );
+        assert_eq!(rates["lo"], NetRate::default());
+
+        // loopback is not counted in the totals
+        assert_eq!(sensors.value("net.rx", None)?, 1536.0);
+        assert_eq!(sensors.value("net.eth0.tx", None)?, 100.0);
+        assert_eq!(sensors.value("net.tx_total", None)?, 2300.0);
+        assert_eq!(sensors.value("net.lo.rx_total", None)?, 5000.0);
+        assert!(sensors.value("net.wlan0.rx", None).is_err());
+
+        let interfaces = sensors.interfaces()?;
+        let names: Vec<&str> = interfaces.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, ["eth0", "lo"]);
+        assert!(interfaces.iter().all(|i| i.up));
+        assert_eq!(sensors.value("net.eth0.speed", None)?, 1000.0);
+        assert!(sensors.value("net.lo.speed", None).is_err());
+        assert_eq!(sensors.text("net.eth0.state", None)?, "up");
+        assert_eq!(sensors.text("net.rx", None)?, "1536");
+        assert!(matches!(
+            sensors.value("net.eth0.state", None),
+            Err(Error::Config(_))
+        ));
+
+        fs::write(root.join("sys/class/net/eth0/operstate"), "down\n")?;
+        fs::remove_file(root.join("sys/class/net/eth0/carrier"))?;
+        assert_eq!(sensors.value("net.eth0.up", None)?, 0.0);
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse() {
+        let mem = MemInfo::parse(
+            "MemTotal:       16000000 kB\n\
+             MemFree:         2000000 kB\n\
+             MemAvailable:    8000000 kB\n\
+             Buffers:          500000 kB\n\
+             Cached:          4000000 kB\n\
+             SwapTotal:       2000000 kB\n\
+             SwapFree:        1500000 kB\n\
+             HugePages_Total:       0\n",
+        )
+        .unwrap();
+        assert_eq!(mem.total, 16000000 * 1024);
+        assert_eq!(mem.used(), 8000000 * 1024);
+        assert_eq!(mem.percent(), 50.0);
+        assert_eq!(mem.swap_percent(), 25.0);
+
+        let load = LoadAvg::parse("0.52 0.58 0.59 2/1234 56789\n").unwrap();
+        assert_eq!((load.one, load.five, load.fifteen), (0.52, 0.58, 0.59));
+        assert_eq!((load.running, load.tasks), (2, 1234));
+
+        assert_eq!(CpuStat::parse("intr 1 2 3\n"), None);
+        assert_eq!(LoadAvg::parse("0.52 0.58\n"), None);
+    }
+
+    #[test]
+    fn test_system() -> Res<()> {
+        let mut sensors = Sensors::new();
+        let usage = DiskUsage::read("/")?;
+        assert!(usage.total > 0 && usage.used() <= usage.total);
+        assert!(sensors.memory()?.total > 0);
+        assert!(sensors.uptime()? > Duration::ZERO);
+        assert!((0.0..=100.0).contains(&sensors.value("cpu.percent", None)?));
+        assert!(matches!(
+            sensors.value("gpu.temperature", None),
+            Err(Error::Config(_))
+        ));
+        Ok(())
+    }
+}
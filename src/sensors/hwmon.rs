@@ -0,0 +1,374 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Temperature, fan, voltage and power sensors of the hardware monitoring
+//! chips in `/sys/class/hwmon`.
+//!
+//! The chip numbers change between boots and when devices are added, so
+//! sensors are identified by the chip name and the sensor label instead,
+//! as in "coretemp/Package id 0". Chips with the same name, such as two
+//! NVMe drives, are told apart by their device: "nvme@nvme1/Composite".
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::invalid;
+use crate::Res;
+
+// Minimum time between scans for missing sensors, whose chips may stay
+// missing for a while, such as the chips of powered down GPUs.
+const RESCAN_PERIOD: Duration = Duration::from_secs(5);
+
+/// Kind of hwmon sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HwmonKind {
+    Temperature,
+    Fan,
+    Voltage,
+    Power,
+}
+
+impl HwmonKind {
+    // The kind of the sensor files with a prefix, and the number of their
+    // units per unit of the kind: millidegrees, millivolts, microwatts.
+    fn parse(prefix: &str) -> Option<(Self, f64)> {
+        Some(match prefix {
+            "temp" => (HwmonKind::Temperature, 1000.0),
+            "fan" => (HwmonKind::Fan, 1.0),
+            "in" => (HwmonKind::Voltage, 1000.0),
+            "power" => (HwmonKind::Power, 1000000.0),
+            _ => return None,
+        })
+    }
+
+    /// The unit of the values: degrees Celsius, RPM, volts or watts.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            HwmonKind::Temperature => "°C",
+            HwmonKind::Fan => "RPM",
+            HwmonKind::Voltage => "V",
+            HwmonKind::Power => "W",
+        }
+    }
+}
+
+/// A sensor of a hwmon chip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HwmonSensor {
+    /// The chip name, followed by its device if other chips have the same
+    /// name.
+    pub chip: String,
+    /// The sensor label, or its name such as "temp1" without label.
+    pub label: String,
+    pub kind: HwmonKind,
+    name: String,
+    device: Option<String>,
+    path: PathBuf,
+    divisor: f64,
+}
+
+impl HwmonSensor {
+    /// The identifier of the sensor: "CHIP/LABEL".
+    pub fn id(&self) -> String {
+        format!("{}/{}", self.chip, self.label)
+    }
+
+    /// Read the current value, in the unit of the kind.
+    pub fn read(&self) -> Res<f64> {
+        let s = fs::read_to_string(&self.path)?;
+        let value: f64 = s.trim().parse().map_err(|_| invalid(&self.path))?;
+        Ok(value / self.divisor)
+    }
+}
+
+// The name of the device of a chip, the target of its device link.
+fn device_name(dir: &Path) -> Option<String> {
+    let path = fs::canonicalize(dir.join("device")).ok()?;
+    Some(path.file_name()?.to_string_lossy().into_owned())
+}
+
+// The sensors of a chip directory.
+fn chip_sensors(dir: &Path, chip: &str, name: &str, device: Option<&String>) -> Vec<HwmonSensor> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut sensors = Vec::new();
+    for entry in entries.flatten() {
+        let file = entry.file_name();
+        let Some(file) = file.to_str() else { continue };
+        // power meters may only have an average
+        let Some(sensor) = file.strip_suffix("_input").or_else(|| {
+            file.strip_suffix("_average")
+                .filter(|s| !dir.join(format!("{}_input", s)).exists())
+        }) else {
+            continue;
+        };
+        let prefix = sensor.trim_end_matches(|c: char| c.is_ascii_digit());
+        if prefix.len() == sensor.len() {
+            continue;
+        }
+        let Some((kind, divisor)) = HwmonKind::parse(prefix) else {
+            continue;
+        };
+        let label = fs::read_to_string(dir.join(format!("{}_label", sensor)))
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default();
+        sensors.push(HwmonSensor {
+            chip: chip.to_string(),
+            label: if label.is_empty() {
+                sensor.to_string()
+            } else {
+                label
+            },
+            kind,
+            name: name.to_string(),
+            device: device.cloned(),
+            path: entry.path(),
+            divisor,
+        });
+    }
+
+    // some drivers give the same label to several sensors
+    let labels: Vec<String> = sensors.iter().map(|s| s.label.clone()).collect();
+    for s in &mut sensors {
+        if labels.iter().filter(|l| **l == s.label).count() > 1 {
+            let file = s.path.file_name().unwrap_or_default().to_string_lossy();
+            s.label = file.split('_').next().unwrap_or_default().to_string();
+        }
+    }
+    sensors
+}
+
+/// The sensors of the hwmon chips.
+///
+/// The chips are scanned on first use. When a sensor cannot be read, its
+/// chip may have disappeared or been registered again with another number:
+/// the chips are scanned again, at most every few seconds.
+pub struct Hwmon {
+    dir: PathBuf,
+    sensors: BTreeMap<String, HwmonSensor>,
+    scanned: Option<Instant>,
+}
+
+impl Default for Hwmon {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hwmon {
+    pub fn new() -> Self {
+        Self::with_root("/")
+    }
+
+    /// Read the `sys` directory of another root.
+    pub fn with_root<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            dir: root.as_ref().join("sys/class/hwmon"),
+            sensors: BTreeMap::new(),
+            scanned: None,
+        }
+    }
+
+    /// Scan the chips for sensors. Chips disappearing during the scan are
+    /// skipped.
+    pub fn scan(&mut self) -> Res<()> {
+        self.scan_at(Instant::now())
+    }
+
+    fn scan_at(&mut self, now: Instant) -> Res<()> {
+        self.scanned = Some(now);
+        let mut chips = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let dir = entry?.path();
+            let name = match fs::read_to_string(dir.join("name")) {
+                Ok(name) => name.trim().to_string(),
+                Err(_) => dir
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            let device = device_name(&dir);
+            chips.push((dir, name, device));
+        }
+
+        self.sensors.clear();
+        for (dir, name, device) in &chips {
+            let chip = match (chips.iter().filter(|c| c.1 == *name).count(), device) {
+                (1, _) | (_, None) => name.clone(),
+                (_, Some(device)) => format!("{}@{}", name, device),
+            };
+            for sensor in chip_sensors(dir, &chip, name, device.as_ref()) {
+                self.sensors.insert(sensor.id(), sensor);
+            }
+        }
+        Ok(())
+    }
+
+    /// The sensors found by the last scan, by identifier.
+    pub fn sensors(&mut self) -> Res<&BTreeMap<String, HwmonSensor>> {
+        if self.scanned.is_none() {
+            self.scan()?;
+        }
+        Ok(&self.sensors)
+    }
+
+    // Find a sensor by identifier. A chip with a device also matches
+    // "NAME@DEVICE" when it is the only one with its name.
+    fn find(&self, id: &str) -> Option<&HwmonSensor> {
+        self.sensors.get(id).or_else(|| {
+            let (chip, label) = id.split_once('/')?;
+            let (name, device) = chip.split_once('@')?;
+            self.sensors
+                .values()
+                .find(|s| s.name == name && s.device.as_deref() == Some(device) && s.label == label)
+        })
+    }
+
+    /// Read a sensor by identifier.
+    pub fn read(&mut self, id: &str) -> Res<f64> {
+        self.read_at(id, Instant::now())
+    }
+
+    fn read_at(&mut self, id: &str, now: Instant) -> Res<f64> {
+        if let Some(value) = self.find(id).and_then(|s| s.read().ok()) {
+            return Ok(value);
+        }
+        if self.scanned.is_none_or(|time| now >= time + RESCAN_PERIOD) {
+            self.scan_at(now)?;
+        }
+        match self.find(id) {
+            Some(sensor) => sensor.read(),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no hwmon sensor '{}'", id),
+            )
+            .into()),
+        }
+    }
+
+    /// Read all the sensors, skipping the ones that cannot be read.
+    pub fn readings(&mut self) -> Res<BTreeMap<String, f64>> {
+        Ok(self
+            .sensors()?
+            .iter()
+            .filter_map(|(id, s)| Some((id.clone(), s.read().ok()?)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::os::unix::fs::symlink;
+    use std::process;
+
+    use super::*;
+
+    fn chip(root: &Path, n: usize, name: &str, files: &[(&str, &str)]) -> Res<PathBuf> {
+        let dir = root.join(format!("sys/class/hwmon/hwmon{}", n));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("name"), format!("{}\n", name))?;
+        for (file, value) in files {
+            fs::write(dir.join(file), format!("{}\n", value))?;
+        }
+        Ok(dir)
+    }
+
+    fn nvme(root: &Path, n: usize, device: &str, temp: &str) -> Res<()> {
+        let dir = chip(
+            root,
+            n,
+            "nvme",
+            &[("temp1_input", temp), ("temp1_label", "Composite")],
+        )?;
+        let device = root.join("sys/devices").join(device);
+        fs::create_dir_all(&device)?;
+        symlink(device, dir.join("device"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_hwmon() -> Res<()> {
+        let root = env::temp_dir().join(format!("turing-hwmon-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        chip(
+            &root,
+            0,
+            "coretemp",
+            &[
+                ("temp1_input", "45000"),
+                ("temp1_label", "Package id 0"),
+                ("temp1_max", "100000"),
+                ("temp2_input", "43500"),
+                ("temp2_label", "Core 0"),
+            ],
+        )?;
+        nvme(&root, 1, "nvme0", "38850")?;
+        nvme(&root, 2, "nvme1", "40850")?;
+        chip(
+            &root,
+            3,
+            "nct6775",
+            &[
+                ("fan1_input", "1200"),
+                ("fan1_min", "300"),
+                ("in0_input", "1024"),
+                ("power1_average", "15000000"),
+                ("temp1_input", "30000"),
+                ("temp7_input", "31000"),
+                ("temp7_label", "AUXTIN"),
+                ("temp8_input", "32000"),
+                ("temp8_label", "AUXTIN"),
+            ],
+        )?;
+
+        let mut hwmon = Hwmon::with_root(&root);
+        let ids: Vec<&String> = hwmon.sensors()?.keys().collect();
+        assert_eq!(
+            ids,
+            [
+                "coretemp/Core 0",
+                "coretemp/Package id 0",
+                "nct6775/fan1",
+                "nct6775/in0",
+                "nct6775/power1",
+                "nct6775/temp1",
+                "nct6775/temp7",
+                "nct6775/temp8",
+                "nvme@nvme0/Composite",
+                "nvme@nvme1/Composite",
+            ]
+        );
+        let fan = &hwmon.sensors()?["nct6775/fan1"];
+        assert_eq!((fan.kind, fan.kind.unit()), (HwmonKind::Fan, "RPM"));
+
+        assert_eq!(hwmon.read("coretemp/Package id 0")?, 45.0);
+        assert_eq!(hwmon.read("nct6775/fan1")?, 1200.0);
+        assert_eq!(hwmon.read("nct6775/in0")?, 1.024);
+        assert_eq!(hwmon.read("nct6775/power1")?, 15.0);
+        assert_eq!(hwmon.read("nvme@nvme1/Composite")?, 40.85);
+        assert!(hwmon.read("coretemp/Core 1").is_err());
+
+        // the chip of nvme1 disappears, then comes back with another number
+        let start = Instant::now();
+        fs::remove_dir_all(root.join("sys/class/hwmon/hwmon2"))?;
+        assert!(hwmon.read_at("nvme@nvme1/Composite", start).is_err());
+        assert_eq!(hwmon.readings()?.len(), 9);
+        hwmon.scan()?;
+        assert!(hwmon.sensors()?.contains_key("nvme/Composite"));
+        assert_eq!(hwmon.read("nvme@nvme0/Composite")?, 38.85);
+
+        nvme(&root, 4, "nvme1", "41850")?;
+        assert!(hwmon.read_at("nvme@nvme1/Composite", start).is_err());
+        let later = Instant::now() + RESCAN_PERIOD;
+        assert_eq!(hwmon.read_at("nvme@nvme1/Composite", later)?, 41.85);
+        assert_eq!(hwmon.readings()?.len(), 10);
+
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
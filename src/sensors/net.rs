@@ -0,0 +1,219 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Network interfaces: traffic counters of `/proc/net/dev`, link state of
+//! `/sys/class/net` and IP addresses.
+
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::ptr;
+use std::time::Duration;
+
+use crate::Res;
+
+const BYTE_UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// Format a number of bytes with a binary unit, such as "1.5 MiB".
+pub fn format_bytes(bytes: f64) -> String {
+    let mut value = bytes;
+    let mut unit = 0;
+    while value.abs() >= 1024.0 && unit + 1 < BYTE_UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{:.0} {}", value, BYTE_UNITS[0]),
+        _ => format!("{:.1} {}", value, BYTE_UNITS[unit]),
+    }
+}
+
+/// Format a rate in bytes per second with a binary unit, such as
+/// "12.3 KiB/s".
+pub fn format_rate(bytes_per_sec: f64) -> String {
+    format!("{}/s", format_bytes(bytes_per_sec))
+}
+
+/// Traffic counters of a network interface, from `/proc/net/dev`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetCounters {
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+}
+
+impl NetCounters {
+    pub(super) fn parse(s: &str) -> Option<BTreeMap<String, Self>> {
+        let mut interfaces = BTreeMap::new();
+        // two header lines
+        for line in s.lines().skip(2) {
+            let (name, values) = line.split_once(':')?;
+            let values: Vec<u64> = values
+                .split_whitespace()
+                .map(|v| v.parse().ok())
+                .collect::<Option<_>>()?;
+            if values.len() < 16 {
+                return None;
+            }
+            let counters = Self {
+                rx_bytes: values[0],
+                rx_packets: values[1],
+                tx_bytes: values[8],
+                tx_packets: values[9],
+            };
+            interfaces.insert(name.trim().to_string(), counters);
+        }
+        Some(interfaces)
+    }
+
+    /// Traffic rates since `prev`, sampled `elapsed` before.
+    pub fn rate_since(&self, prev: &NetCounters, elapsed: Duration) -> NetRate {
+        let secs = elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return NetRate::default();
+        }
+        let rate = |now: u64, prev: u64| now.saturating_sub(prev) as f64 / secs;
+        NetRate {
+            rx: rate(self.rx_bytes, prev.rx_bytes),
+            tx: rate(self.tx_bytes, prev.tx_bytes),
+        }
+    }
+}
+
+/// Traffic rates of a network interface, in bytes per second.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetRate {
+    pub rx: f64,
+    pub tx: f64,
+}
+
+/// State of a network interface.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NetInterface {
+    pub name: String,
+    /// Operational state, such as "up", "down" or "unknown".
+    pub state: String,
+    /// Whether the link is up, or has a carrier when the driver does not
+    /// report the state, as for loopback.
+    pub up: bool,
+    /// Link speed in Mbit/s, if known.
+    pub speed: Option<u32>,
+    /// IPv4 addresses first, then IPv6 addresses.
+    pub addresses: Vec<IpAddr>,
+}
+
+impl NetInterface {
+    // Read the state of an interface from its directory in /sys/class/net.
+    pub(super) fn read(dir: &Path, addresses: Vec<IpAddr>) -> Res<Self> {
+        let read = |file: &str| fs::read_to_string(dir.join(file)).map(|s| s.trim().to_string());
+        let state = read("operstate")?;
+        // the carrier cannot be read while the interface is down
+        let carrier = read("carrier").is_ok_and(|c| c == "1");
+        Ok(Self {
+            name: dir
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            up: state == "up" || (state == "unknown" && carrier),
+            state,
+            // -1 or unreadable while the link is down
+            speed: read("speed").ok().and_then(|s| s.parse().ok()),
+            addresses,
+        })
+    }
+
+    /// The first IPv4 address.
+    pub fn ipv4(&self) -> Option<Ipv4Addr> {
+        self.addresses.iter().find_map(|addr| match addr {
+            IpAddr::V4(addr) => Some(*addr),
+            IpAddr::V6(_) => None,
+        })
+    }
+
+    /// The first IPv6 address, preferring global addresses to link-local
+    /// ones.
+    pub fn ipv6(&self) -> Option<Ipv6Addr> {
+        let addrs = self.addresses.iter().filter_map(|addr| match addr {
+            IpAddr::V4(_) => None,
+            IpAddr::V6(addr) => Some(*addr),
+        });
+        addrs.min_by_key(|addr| addr.segments()[0] & 0xffc0 == 0xfe80)
+    }
+}
+
+/// The IP addresses of the interfaces, IPv4 first.
+pub(super) fn addresses() -> Res<BTreeMap<String, Vec<IpAddr>>> {
+    let mut ifaddrs: *mut libc::ifaddrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut addresses: BTreeMap<String, Vec<IpAddr>> = BTreeMap::new();
+    let mut next = ifaddrs;
+    while let Some(ifa) = unsafe { next.as_ref() } {
+        next = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        let addr = unsafe {
+            match (*ifa.ifa_addr).sa_family as libc::c_int {
+                libc::AF_INET => {
+                    let sin = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+                }
+                libc::AF_INET6 => {
+                    let sin6 = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                    IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+                }
+                _ => continue,
+            }
+        };
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) };
+        addresses
+            .entry(name.to_string_lossy().into_owned())
+            .or_default()
+            .push(addr);
+    }
+    unsafe { libc::freeifaddrs(ifaddrs) };
+    for addrs in addresses.values_mut() {
+        addrs.sort_by_key(|addr| addr.is_ipv6());
+    }
+    Ok(addresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_bytes(0.0), "0 B");
+        assert_eq!(format_bytes(1023.0), "1023 B");
+        assert_eq!(format_rate(1536.0), "1.5 KiB/s");
+        assert_eq!(format_rate(12.3 * 1024.0 * 1024.0), "12.3 MiB/s");
+        assert_eq!(format_bytes(5.0 * 1024f64.powi(5)), "5120.0 TiB");
+    }
+
+    #[test]
+    fn test_ip_selection() {
+        let eth = NetInterface {
+            addresses: ["fe80::1", "10.0.0.2", "2001:db8::2"]
+                .iter()
+                .map(|a| a.parse().unwrap())
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(eth.ipv4(), Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(eth.ipv6(), "2001:db8::2".parse().ok());
+
+        let lo = NetInterface {
+            addresses: vec![IpAddr::V6(Ipv6Addr::LOCALHOST)],
+            ..Default::default()
+        };
+        assert_eq!(lo.ipv4(), None);
+        assert_eq!(lo.ipv6(), Some(Ipv6Addr::LOCALHOST));
+    }
+}
@@ -1,29 +1,85 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::io::ErrorKind;
 use std::io::Read;
 use std::io::Write;
+use std::path::PathBuf;
+use std::thread;
 use std::time::Duration;
 
-use crate::Res;
+use crate::lock::DeviceLock;
 use crate::ScreenPort;
+use crate::{Error, Res};
 
+/// Serial port settings used to open a screen.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PortOptions {
+    pub baud_rate: Option<u32>, // overrides the screen revision default
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+    pub trace: Option<PathBuf>, // record the traffic to this file
+    pub lock_wait: Duration,    // how long to wait for another process to release the device
+}
+
+impl Default for PortOptions {
+    fn default() -> Self {
+        Self {
+            baud_rate: None,
+            read_timeout: Duration::from_millis(1000),
+            write_timeout: Duration::from_millis(1000),
+            trace: None,
+            lock_wait: Duration::ZERO,
+        }
+    }
+}
+
+/// A serial port connected to a screen.
+///
+/// The device is locked while the port is open, other processes using
+/// this library cannot open it.
 pub struct SerialPort {
     port: Box<dyn serialport::SerialPort>,
+    _lock: DeviceLock,
+    read_timeout: Duration,
+    write_timeout: Duration,
 }
 
+// Number of consecutive blocked writes before giving up, and the delay
+// between retries
+const MAX_BLOCKED_WRITES: usize = 100;
+const BLOCKED_WRITE_DELAY: Duration = Duration::from_millis(10);
+
 impl SerialPort {
     pub fn new(path: &str, baud_rate: u32) -> Res<Self> {
+        Self::with_options(path, baud_rate, &PortOptions::default())
+    }
+
+    pub(crate) fn with_options(path: &str, baud_rate: u32, opts: &PortOptions) -> Res<Self> {
+        let lock = DeviceLock::acquire(path, opts.lock_wait)?;
         Ok(Self {
             port: serialport::new(path, baud_rate)
-                .timeout(Duration::from_millis(1000))
+                .timeout(opts.read_timeout)
                 .open()?,
+            _lock: lock,
+            read_timeout: opts.read_timeout,
+            write_timeout: opts.write_timeout,
         })
     }
+
+    // The serial port has a single timeout, update it when switching
+    // between reads and writes.
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), std::io::Error> {
+        if self.port.timeout() != timeout {
+            self.port.set_timeout(timeout)?;
+        }
+        Ok(())
+    }
 }
 
 impl Read for SerialPort {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        self.set_timeout(self.read_timeout)?;
         self.port.read(buf)
     }
 }
@@ -31,6 +87,7 @@ impl Read for SerialPort {
 impl Write for SerialPort {
     #[inline]
     fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        self.set_timeout(self.write_timeout)?;
         self.port.write(buf)
     }
 
@@ -46,17 +103,223 @@ impl ScreenPort for SerialPort {
     }
 }
 
-pub fn detect(ser: &str) -> Res<String> {
-    for p in serialport::available_ports()? {
-        match p.port_type {
-            serialport::SerialPortType::UsbPort(info) => {
-                let serial = info.serial_number.as_ref().map_or("", String::as_str);
-                if serial == ser {
-                    return Ok(p.port_name);
+/// Write the whole buffer to the port.
+///
+/// Short writes are continued and interrupted or blocked writes are retried.
+/// If the device stops accepting data an error reporting how much was
+/// written is returned.
+pub(crate) fn write_all<W: Write + ?Sized>(port: &mut W, data: &[u8]) -> Res<()> {
+    let mut written = 0;
+    let mut blocked = 0;
+    while written < data.len() {
+        match port.write(&data[written..]) {
+            Ok(0) => return Err(Error::PartialWrite(written, data.len())),
+            Ok(n) => {
+                written += n;
+                blocked = 0;
+            }
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                blocked += 1;
+                if blocked > MAX_BLOCKED_WRITES {
+                    return Err(Error::PartialWrite(written, data.len()));
                 }
+                thread::sleep(BLOCKED_WRITE_DELAY);
             }
-            _ => todo!(),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Write the whole buffer to the port and flush it.
+pub(crate) fn write_command<W: Write + ?Sized>(port: &mut W, data: &[u8]) -> Res<()> {
+    write_all(port, data)?;
+    loop {
+        match port.flush() {
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            res => return Ok(res?),
+        }
+    }
+}
+
+/// A USB serial device attached to the system.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Device {
+    pub port_name: String,
+    pub vid: u16,
+    pub pid: u16,
+    pub serial: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+impl std::fmt::Display for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} [{:04x}:{:04x} serial '{}']",
+            self.port_name,
+            self.vid,
+            self.pid,
+            self.serial.as_deref().unwrap_or("")
+        )
+    }
+}
+
+// Convert the port information to a device. Only USB ports can be screens,
+// PCI, Bluetooth and unknown ports are skipped.
+fn to_device(p: serialport::SerialPortInfo) -> Option<Device> {
+    match p.port_type {
+        serialport::SerialPortType::UsbPort(info) => Some(Device {
+            port_name: p.port_name,
+            vid: info.vid,
+            pid: info.pid,
+            serial: info.serial_number,
+            manufacturer: info.manufacturer,
+            product: info.product,
+        }),
+        port_type => {
+            log::debug!("skip non-USB port {} ({:?})", p.port_name, port_type);
+            None
+        }
+    }
+}
+
+/// List the USB serial devices attached to the system.
+pub fn list_devices() -> Res<Vec<Device>> {
+    Ok(serialport::available_ports()?
+        .into_iter()
+        .filter_map(to_device)
+        .collect())
+}
+
+pub fn detect(ser: &str) -> Res<String> {
+    for d in list_devices()? {
+        if d.serial.as_deref() == Some(ser) {
+            return Ok(d.port_name);
+        }
+    }
+    Err(Error::DeviceNotFound(format!(
+        "no serial device matching {}",
+        ser
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+    use std::io;
+
+    // A port accepting a few bytes at a time, failing in between
+    struct SlowPort {
+        data: Vec<u8>,
+        chunk: usize,
+        errors: Vec<ErrorKind>,
+        capacity: usize,
+        flushed: bool,
+    }
+
+    impl Write for SlowPort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if let Some(kind) = self.errors.pop() {
+                return Err(io::Error::new(kind, "fake error"));
+            }
+            let n = buf
+                .len()
+                .min(self.chunk)
+                .min(self.capacity - self.data.len());
+            self.data.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed = true;
+            Ok(())
+        }
+    }
+
+    fn slow_port(chunk: usize, errors: Vec<ErrorKind>, capacity: usize) -> SlowPort {
+        SlowPort {
+            data: Vec::new(),
+            chunk,
+            errors,
+            capacity,
+            flushed: false,
+        }
+    }
+
+    #[test]
+    fn test_write_all() -> Res<()> {
+        let mut port = slow_port(3, vec![ErrorKind::WouldBlock, ErrorKind::Interrupted], 100);
+        write_all(&mut port, &[1, 2, 3, 4, 5, 6, 7, 8])?;
+        assert_eq!(port.data, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(!port.flushed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_all_stalled() {
+        let mut port = slow_port(3, vec![], 5);
+        assert!(matches!(
+            write_all(&mut port, &[1, 2, 3, 4, 5, 6, 7, 8]),
+            Err(Error::PartialWrite(5, 8))
+        ));
+    }
+
+    #[test]
+    fn test_write_all_error() {
+        let mut port = slow_port(3, vec![ErrorKind::BrokenPipe], 100);
+        assert!(matches!(
+            write_all(&mut port, &[1, 2, 3]),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_write_command() -> Res<()> {
+        let mut port = slow_port(2, vec![], 100);
+        write_command(&mut port, &[1, 2, 3])?;
+        assert_eq!(port.data, vec![1, 2, 3]);
+        assert!(port.flushed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_device() {
+        let usb = SerialPortInfo {
+            port_name: "/dev/ttyACM0".to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid: 0x1a86,
+                pid: 0x5722,
+                serial_number: Some("USB35INCHIPSV2".to_string()),
+                manufacturer: Some("Turing".to_string()),
+                product: Some("UsbMonitor".to_string()),
+            }),
+        };
+        assert_eq!(
+            to_device(usb),
+            Some(Device {
+                port_name: "/dev/ttyACM0".to_string(),
+                vid: 0x1a86,
+                pid: 0x5722,
+                serial: Some("USB35INCHIPSV2".to_string()),
+                manufacturer: Some("Turing".to_string()),
+                product: Some("UsbMonitor".to_string()),
+            })
+        );
+
+        for port_type in [
+            SerialPortType::PciPort,
+            SerialPortType::BluetoothPort,
+            SerialPortType::Unknown,
+        ] {
+            let info = SerialPortInfo {
+                port_name: "/dev/ttyS0".to_string(),
+                port_type,
+            };
+            assert_eq!(to_device(info), None);
         }
     }
-    Err(format!("no serial device matching {}", ser).into())
 }
@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::{Coord, Image, Rect, Res};
+use crate::{Orientation, Screen};
+
+/// A screen shared between threads.
+///
+/// Clones refer to the same screen. Each operation has exclusive access to
+/// the screen until it is done, so the commands and pixel data sent by
+/// different threads are never interleaved. Use [`SharedScreen::lock`] to
+/// run several operations without other threads updating the screen in
+/// between.
+///
+/// ```no_run
+/// use std::thread;
+/// use turing_screen::{Coord, Image, SharedScreen};
+///
+/// let screen = SharedScreen::new(turing_screen::new("AUTO").unwrap());
+/// let worker = screen.clone();
+/// thread::spawn(move || {
+///     let image = Image::new(100, 20);
+///     worker.display_image(&image, &image.full(), &Coord::new(0, 0))
+/// });
+///
+/// let mut session = screen.lock();
+/// session.clear().unwrap();
+/// session.set_brightness(100).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct SharedScreen {
+    screen: Arc<Mutex<Box<dyn Screen>>>,
+}
+
+/// Exclusive access to a [`SharedScreen`], released when dropped.
+pub struct ScreenSession<'a> {
+    guard: MutexGuard<'a, Box<dyn Screen>>,
+}
+
+impl Deref for ScreenSession<'_> {
+    type Target = dyn Screen;
+
+    fn deref(&self) -> &Self::Target {
+        self.guard.as_ref()
+    }
+}
+
+impl DerefMut for ScreenSession<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.guard.as_mut()
+    }
+}
+
+impl SharedScreen {
+    pub fn new(screen: Box<dyn Screen>) -> Self {
+        Self {
+            screen: Arc::new(Mutex::new(screen)),
+        }
+    }
+
+    /// Wait for exclusive access to the screen.
+    pub fn lock(&self) -> ScreenSession<'_> {
+        // a thread panicking in a session does not make the screen unusable
+        let guard = self.screen.lock().unwrap_or_else(|err| {
+            log::warn!("screen session ended by a panic");
+            PoisonError::into_inner(err)
+        });
+        ScreenSession { guard }
+    }
+
+    /// Run several operations with exclusive access to the screen.
+    pub fn with<T>(&self, f: impl FnOnce(&mut dyn Screen) -> Res<T>) -> Res<T> {
+        f(&mut *self.lock())
+    }
+
+    pub fn screen_size(&self) -> (usize, usize) {
+        self.lock().screen_size()
+    }
+
+    pub fn init(&self) -> Res<()> {
+        self.lock().init()
+    }
+
+    pub fn clear(&self) -> Res<()> {
+        self.lock().clear()
+    }
+
+    pub fn screen_on(&self) -> Res<()> {
+        self.lock().screen_on()
+    }
+
+    pub fn screen_off(&self) -> Res<()> {
+        self.lock().screen_off()
+    }
+
+    pub fn set_orientation(&self, o: Orientation) -> Res<()> {
+        self.lock().set_orientation(o)
+    }
+
+    pub fn set_brightness(&self, level: usize) -> Res<()> {
+        self.lock().set_brightness(level)
+    }
+
+    pub fn display_image(&self, image: &Image, crop: &Rect, pos: &Coord) -> Res<()> {
+        self.lock().display_image(image, crop, pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    use crate::{DecodedCommand, EmulatorRevA, Rgba, ScreenRevA};
+
+    #[test]
+    fn test_shared_screen() -> Res<()> {
+        let emu = EmulatorRevA::new();
+        let screen = SharedScreen::new(Box::new(ScreenRevA::with_port(Box::new(emu.clone()))));
+        screen.clear()?;
+
+        // each thread updates its own column with a new color every time
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let screen = screen.clone();
+                thread::spawn(move || -> Res<()> {
+                    for i in 0..20 {
+                        let mut image = Image::new(10, 50);
+                        image.buffer.fill(Rgba::new(8 * i, 8 * t, 0, 255));
+                        let pos = Coord::new(10 * t as usize, 0);
+                        if i % 2 == 0 {
+                            screen.display_image(&image, &image.full(), &pos)?;
+                        } else {
+                            let mut session = screen.lock();
+                            session.set_brightness(i as usize)?;
+                            session.display_image(&image, &image.full(), &pos)?;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap()?;
+        }
+
+        let commands = emu.take_commands();
+        assert!(!commands
+            .iter()
+            .any(|c| matches!(c, DecodedCommand::Unknown(_))));
+        let bitmaps = commands
+            .iter()
+            .filter(|c| matches!(c, DecodedCommand::DisplayBitmap(_)))
+            .count();
+        assert_eq!(bitmaps, 4 * 20);
+
+        screen.set_brightness(255)?;
+        let snapshot = emu.snapshot();
+        for t in 0..4 {
+            // the last color, red 152 converted to RGB565 and back
+            assert_eq!(snapshot.buffer[10 * t], Rgba::new(156, 8 * t as u8, 0, 255));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_with() -> Res<()> {
+        let emu = EmulatorRevA::new();
+        let screen = SharedScreen::new(Box::new(ScreenRevA::with_port(Box::new(emu.clone()))));
+        let size = screen.with(|scr| {
+            scr.set_orientation(Orientation::Landscape)?;
+            Ok(scr.screen_size())
+        })?;
+        assert_eq!(size, (480, 320));
+        assert_eq!(screen.clone().screen_size(), (480, 320));
+        Ok(())
+    }
+}
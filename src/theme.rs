@@ -0,0 +1,846 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs;
+use std::path::Path;
+
+use serde_norway::{Mapping, Value};
+
+use crate::{Align, MonitorConfig, Res, WidgetConfig, WidgetKind};
+
+// Themes are installed in res/themes/NAME, next to res/fonts.
+const FONTS_DIR: &str = "../../fonts";
+const DEFAULT_FONT: &str = "roboto-mono/RobotoMono-Regular.ttf";
+const DEFAULT_FONT_SIZE: f32 = 20.0;
+const DEFAULT_COLOR: &str = "#000000";
+const DEFAULT_BACKGROUND: &str = "#ffffff";
+
+const IMAGE_KEYS: &[&str] = &["PATH", "X", "Y", "WIDTH", "HEIGHT"];
+const TEXT_KEYS: &[&str] = &[
+    "SHOW",
+    "SHOW_UNIT",
+    "X",
+    "Y",
+    "WIDTH",
+    "HEIGHT",
+    "FONT",
+    "FONT_SIZE",
+    "FONT_COLOR",
+    "BACKGROUND_COLOR",
+    "BACKGROUND_IMAGE",
+    "ALIGN",
+    "ANCHOR",
+];
+const GRAPH_KEYS: &[&str] = &[
+    "SHOW",
+    "X",
+    "Y",
+    "WIDTH",
+    "HEIGHT",
+    "MIN_VALUE",
+    "MAX_VALUE",
+    "BAR_COLOR",
+    "BAR_OUTLINE",
+    "BACKGROUND_COLOR",
+    "BACKGROUND_IMAGE",
+];
+const RADIAL_KEYS: &[&str] = &[
+    "SHOW",
+    "X",
+    "Y",
+    "RADIUS",
+    "WIDTH",
+    "MIN_VALUE",
+    "MAX_VALUE",
+    "ANGLE_START",
+    "ANGLE_END",
+    "ANGLE_STEPS",
+    "ANGLE_SEP",
+    "CLOCKWISE",
+    "BAR_COLOR",
+    "SHOW_TEXT",
+    "SHOW_UNIT",
+    "FONT",
+    "FONT_SIZE",
+    "FONT_COLOR",
+    "BACKGROUND_COLOR",
+    "BACKGROUND_IMAGE",
+];
+
+// A value of the STATS section with a monitor source, scaled to the unit
+// of the theme.
+struct Stat {
+    path: &'static str, // the element path below STATS
+    source: &'static str,
+    format: &'static str,
+    unit: &'static str,
+    scale: f64,
+}
+
+const fn stat(
+    path: &'static str,
+    source: &'static str,
+    format: &'static str,
+    unit: &'static str,
+    scale: f64,
+) -> Stat {
+    Stat {
+        path,
+        source,
+        format,
+        unit,
+        scale,
+    }
+}
+
+const STATS: &[Stat] = &[
+    stat("CPU.PERCENTAGE.TEXT", "cpu.percent", "{:.0}", "%", 1.0),
+    stat("CPU.PERCENTAGE.GRAPH", "cpu.percent", "", "", 1.0),
+    stat("CPU.PERCENTAGE.RADIAL", "cpu.percent", "{:.0}", "%", 1.0),
+    stat(
+        "CPU.FREQUENCY.TEXT",
+        "cpu.frequency",
+        "{:.2}",
+        " GHz",
+        0.001,
+    ),
+    stat(
+        "CPU.TEMPERATURE.TEXT",
+        "cpu.temperature",
+        "{:.0}",
+        "°C",
+        1.0,
+    ),
+    stat("CPU.TEMPERATURE.GRAPH", "cpu.temperature", "", "", 1.0),
+    stat(
+        "CPU.TEMPERATURE.RADIAL",
+        "cpu.temperature",
+        "{:.0}",
+        "°C",
+        1.0,
+    ),
+    stat("MEMORY.SWAP.GRAPH", "swap.percent", "", "", 1.0),
+    stat("MEMORY.SWAP.RADIAL", "swap.percent", "{:.0}", "%", 1.0),
+    stat("MEMORY.VIRTUAL.GRAPH", "memory.percent", "", "", 1.0),
+    stat("MEMORY.VIRTUAL.RADIAL", "memory.percent", "{:.0}", "%", 1.0),
+    stat(
+        "MEMORY.VIRTUAL.PERCENT_TEXT",
+        "memory.percent",
+        "{:.0}",
+        "%",
+        1.0,
+    ),
+    stat("MEMORY.VIRTUAL.USED", "memory.used", "{:.0}", " M", 1e-6),
+    stat(
+        "MEMORY.VIRTUAL.FREE",
+        "memory.available",
+        "{:.0}",
+        " M",
+        1e-6,
+    ),
+    stat("DISK.USED.GRAPH", "disk.percent", "", "", 1.0),
+    stat("DISK.USED.RADIAL", "disk.percent", "{:.0}", "%", 1.0),
+    stat("DISK.USED.PERCENT_TEXT", "disk.percent", "{:.0}", "%", 1.0),
+    stat("DISK.USED.TEXT", "disk.used", "{:.0}", " G", 1e-9),
+    stat("DISK.TOTAL.TEXT", "disk.total", "{:.0}", " G", 1e-9),
+    stat("DISK.FREE.TEXT", "disk.free", "{:.0}", " G", 1e-9),
+    stat("DATE.DAY.TEXT", "clock", "", "", 1.0),
+    stat("DATE.HOUR.TEXT", "clock", "", "", 1.0),
+];
+
+// The strftime format of a date or time in a babel format: short, medium,
+// long or full.
+fn clock_format(path: &str, format: &str) -> Option<&'static str> {
+    let day = path.contains(".DAY.");
+    Some(match format {
+        "short" if day => "%d/%m/%Y",
+        "medium" if day => "%b %d, %Y",
+        "long" if day => "%B %d, %Y",
+        "full" if day => "%A, %B %d, %Y",
+        "short" => "%H:%M",
+        "medium" => "%H:%M:%S",
+        "long" | "full" => "%H:%M:%S %Z",
+        _ => return None,
+    })
+}
+
+fn as_usize(v: Option<&Value>) -> Option<usize> {
+    let v = v?;
+    v.as_u64()
+        .map(|n| n as usize)
+        .or_else(|| v.as_f64().filter(|f| *f >= 0.0).map(|f| f.round() as usize))
+}
+
+fn as_f64(v: Option<&Value>) -> Option<f64> {
+    v?.as_f64()
+}
+
+fn as_bool(v: Option<&Value>) -> Option<bool> {
+    v?.as_bool()
+}
+
+fn is_shown(map: &Mapping) -> bool {
+    as_bool(map.get("SHOW")).unwrap_or(false)
+}
+
+// Convert a theme color, "R, G, B", [R, G, B] or "#RRGGBB", to hex.
+fn color(v: &Value) -> Option<String> {
+    let rgb: Vec<u64> = match v {
+        Value::String(s) if s.starts_with('#') => {
+            return crate::colors::from_hex(s).map(|_| s.to_lowercase());
+        }
+        Value::String(s) => s
+            .split(',')
+            .map(|c| c.trim().parse().ok())
+            .collect::<Option<_>>()?,
+        Value::Sequence(seq) => seq.iter().map(Value::as_u64).collect::<Option<_>>()?,
+        _ => return None,
+    };
+    match rgb[..] {
+        [r, g, b] if r < 256 && g < 256 && b < 256 => Some(format!("#{:02x}{:02x}{:02x}", r, g, b)),
+        _ => None,
+    }
+}
+
+struct Importer<'a> {
+    dir: &'a Path,
+    config: MonitorConfig,
+    warnings: Vec<String>,
+}
+
+impl Importer<'_> {
+    fn warn(&mut self, path: &str, msg: &str) {
+        self.warnings.push(format!("{}: {}", path, msg));
+    }
+
+    fn check_keys(&mut self, path: &str, map: &Mapping, known: &[&str]) {
+        for key in map.keys() {
+            match key.as_str() {
+                Some(key) if known.contains(&key) => {}
+                Some(key) => self.warn(&format!("{}.{}", path, key), "unsupported key, ignored"),
+                None => self.warn(path, "invalid key, ignored"),
+            }
+        }
+    }
+
+    fn color(&mut self, path: &str, map: &Mapping, key: &str) -> Option<String> {
+        let v = map.get(key)?;
+        let c = color(v);
+        if c.is_none() {
+            self.warn(&format!("{}.{}", path, key), "invalid color, ignored");
+        }
+        c
+    }
+
+    // The background color, unless the background image of the theme is
+    // restored under the element.
+    fn background(&mut self, path: &str, map: &Mapping) -> Option<String> {
+        if map.contains_key("BACKGROUND_IMAGE") {
+            return None;
+        }
+        let c = self.color(path, map, "BACKGROUND_COLOR");
+        Some(c.unwrap_or_else(|| DEFAULT_BACKGROUND.to_string()))
+    }
+
+    // Register a theme font, named after its path in the fonts directory.
+    fn font(&mut self, name: &str) -> String {
+        if !self.config.fonts.contains_key(name) {
+            let shared = Path::new(FONTS_DIR).join(name);
+            let path = match self.dir.join(&shared).exists() {
+                true => shared.to_string_lossy().into_owned(),
+                false => name.to_string(),
+            };
+            self.config.fonts.insert(name.to_string(), path);
+        }
+        name.to_string()
+    }
+
+    fn display(&mut self, v: &Value) {
+        let Some(map) = v.as_mapping() else {
+            return self.warn("display", "invalid section, ignored");
+        };
+        for (key, v) in map {
+            let key = key.as_str().unwrap_or_default();
+            match key {
+                "DISPLAY_ORIENTATION" => match v.as_str() {
+                    Some(o) => self.config.device.orientation = Some(o.replace('_', "-")),
+                    None => self.warn("display.DISPLAY_ORIENTATION", "invalid value, ignored"),
+                },
+                // the size is detected
+                "DISPLAY_SIZE" => {}
+                _ => self.warn(&format!("display.{}", key), "unsupported key, ignored"),
+            }
+        }
+    }
+
+    fn static_image(&mut self, path: &str, map: &Mapping) {
+        self.check_keys(path, map, IMAGE_KEYS);
+        let Some(image) = map.get("PATH").and_then(Value::as_str) else {
+            return self.warn(path, "missing PATH, skipped");
+        };
+        self.config.widgets.push(WidgetConfig {
+            kind: WidgetKind::Image,
+            x: as_usize(map.get("X")).unwrap_or(0),
+            y: as_usize(map.get("Y")).unwrap_or(0),
+            width: as_usize(map.get("WIDTH")),
+            height: as_usize(map.get("HEIGHT")),
+            path: Some(image.to_string()),
+            ..Default::default()
+        });
+    }
+
+    fn text(&mut self, path: &str, map: &Mapping) -> WidgetConfig {
+        let size = as_f64(map.get("FONT_SIZE")).map_or(DEFAULT_FONT_SIZE, |s| s as f32);
+        let font = map
+            .get("FONT")
+            .and_then(Value::as_str)
+            .unwrap_or(DEFAULT_FONT);
+        let mut w = WidgetConfig {
+            kind: WidgetKind::Text,
+            x: as_usize(map.get("X")).unwrap_or(0),
+            y: as_usize(map.get("Y")).unwrap_or(0),
+            width: as_usize(map.get("WIDTH")),
+            height: as_usize(map.get("HEIGHT")),
+            font: Some(self.font(font)),
+            size: Some(size),
+            color: self.color(path, map, "FONT_COLOR"),
+            background: self.background(path, map),
+            ..Default::default()
+        };
+        w.color.get_or_insert_with(|| DEFAULT_COLOR.to_string());
+
+        // the anchor is given as in PIL: horizontal then vertical
+        let anchor = map.get("ANCHOR").and_then(Value::as_str).unwrap_or("lt");
+        let mut chars = anchor.chars();
+        let align = match (
+            w.width.and(w.height),
+            map.get("ALIGN").and_then(Value::as_str),
+        ) {
+            (Some(_), Some(align)) => align.to_string(),
+            _ => chars.next().unwrap_or('l').to_string(),
+        };
+        w.align = match align.as_str() {
+            "m" | "center" => Some(Align::Center),
+            "r" | "right" => Some(Align::Right),
+            _ => None,
+        };
+        match anchor.chars().nth(1) {
+            Some('m') => w.y = w.y.saturating_sub((size / 2.0) as usize),
+            Some('b' | 's' | 'd') => w.y = w.y.saturating_sub(size as usize),
+            _ => {}
+        }
+        w
+    }
+
+    fn static_text(&mut self, path: &str, map: &Mapping) {
+        let mut known = TEXT_KEYS.to_vec();
+        known.push("TEXT");
+        self.check_keys(path, map, &known);
+        let mut w = self.text(path, map);
+        w.text = Some(match map.get("TEXT") {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Number(n)) => n.to_string(),
+            _ => return self.warn(path, "missing TEXT, skipped"),
+        });
+        self.config.widgets.push(w);
+    }
+
+    // A graph, radial or text element of the STATS section.
+    fn stat(&mut self, path: &str, stat: &Stat, map: &Mapping, interval: Option<f64>) {
+        let show_unit = as_bool(map.get("SHOW_UNIT")).unwrap_or(true);
+        let format = match show_unit {
+            true => format!("{}{}", stat.format, stat.unit),
+            false => stat.format.to_string(),
+        };
+        let format = (!format.is_empty()).then_some(format);
+        let source = Some(stat.source.to_string());
+        let scale = (stat.scale != 1.0).then_some(stat.scale);
+
+        if path.ends_with(".GRAPH") {
+            self.check_keys(path, map, GRAPH_KEYS);
+            let size = |key| as_usize(map.get(key)).filter(|v| *v > 0);
+            let (Some(width), Some(height)) = (size("WIDTH"), size("HEIGHT")) else {
+                return self.warn(path, "missing WIDTH/HEIGHT, skipped");
+            };
+            let bar_color = self.color(path, map, "BAR_COLOR");
+            let w = WidgetConfig {
+                kind: WidgetKind::Bar,
+                x: as_usize(map.get("X")).unwrap_or(0),
+                y: as_usize(map.get("Y")).unwrap_or(0),
+                width: Some(width),
+                height: Some(height),
+                interval,
+                source,
+                scale,
+                background: self.background(path, map),
+                min: as_f64(map.get("MIN_VALUE")),
+                max: as_f64(map.get("MAX_VALUE")),
+                outline: match as_bool(map.get("BAR_OUTLINE")) {
+                    Some(true) => Some(bar_color.clone().unwrap_or(DEFAULT_COLOR.to_string())),
+                    _ => None,
+                },
+                color: Some(bar_color.unwrap_or(DEFAULT_COLOR.to_string())),
+                ..Default::default()
+            };
+            self.config.widgets.push(w);
+        } else if path.ends_with(".RADIAL") {
+            self.check_keys(path, map, RADIAL_KEYS);
+            let radius = as_usize(map.get("RADIUS"));
+            let w = WidgetConfig {
+                kind: WidgetKind::Radial,
+                x: as_usize(map.get("X")).unwrap_or(0),
+                y: as_usize(map.get("Y")).unwrap_or(0),
+                interval,
+                source: source.clone(),
+                scale,
+                color: Some(
+                    self.color(path, map, "BAR_COLOR")
+                        .unwrap_or(DEFAULT_COLOR.to_string()),
+                ),
+                background: self.background(path, map),
+                min: as_f64(map.get("MIN_VALUE")),
+                max: as_f64(map.get("MAX_VALUE")),
+                radius,
+                thickness: as_usize(map.get("WIDTH")),
+                angle_start: as_f64(map.get("ANGLE_START")),
+                angle_end: as_f64(map.get("ANGLE_END")),
+                clockwise: as_bool(map.get("CLOCKWISE")),
+                steps: as_usize(map.get("ANGLE_STEPS")).filter(|n| *n > 1),
+                gap: as_f64(map.get("ANGLE_SEP")),
+                ..Default::default()
+            };
+            let (x, y) = (w.x, w.y);
+            self.config.widgets.push(w);
+
+            // the value is shown at the center
+            if as_bool(map.get("SHOW_TEXT")).unwrap_or(false) {
+                let mut text = self.text(path, map);
+                let size = text.size.unwrap_or(DEFAULT_FONT_SIZE);
+                (text.x, text.y) = (x, y.saturating_sub((size / 2.0) as usize));
+                (text.width, text.height) = (None, None);
+                text.align = Some(Align::Center);
+                text.background = None;
+                text.interval = interval;
+                text.source = source;
+                text.scale = scale;
+                text.format = format;
+                self.config.widgets.push(text);
+            }
+        } else {
+            let mut known = TEXT_KEYS.to_vec();
+            if stat.source == "clock" {
+                known.push("FORMAT");
+            }
+            self.check_keys(path, map, &known);
+            let mut w = self.text(path, map);
+            w.interval = interval;
+            w.source = source;
+            w.scale = scale;
+            w.format = format;
+            if stat.source == "clock" {
+                let format = map
+                    .get("FORMAT")
+                    .and_then(Value::as_str)
+                    .unwrap_or("medium");
+                w.format = Some(
+                    match clock_format(path, format) {
+                        Some(f) => f,
+                        None => {
+                            self.warn(
+                                &format!("{}.FORMAT", path),
+                                "unsupported format, using medium",
+                            );
+                            clock_format(path, "medium").unwrap_or_default()
+                        }
+                    }
+                    .to_string(),
+                );
+            }
+            self.config.widgets.push(w);
+        }
+    }
+
+    fn stats(&mut self, path: &str, v: &Value, interval: Option<f64>) {
+        let Some(map) = v.as_mapping() else {
+            return self.warn(path, "unsupported key, ignored");
+        };
+        let interval = as_f64(map.get("INTERVAL")).or(interval);
+
+        if let Some(stat) = STATS
+            .iter()
+            .find(|s| path.strip_prefix("STATS.") == Some(s.path))
+        {
+            if is_shown(map) {
+                self.stat(path, stat, map, interval);
+            }
+            return;
+        }
+        if map.contains_key("SHOW") {
+            if is_shown(map) {
+                self.warn(path, "not supported, skipped");
+            }
+            return;
+        }
+        for (key, v) in map {
+            match key.as_str() {
+                Some("INTERVAL") => {}
+                Some(key) => self.stats(&format!("{}.{}", path, key), v, interval),
+                None => self.warn(path, "invalid key, ignored"),
+            }
+        }
+    }
+
+    fn theme(&mut self, theme: &Value) {
+        let Some(theme) = theme.as_mapping() else {
+            return self.warn("theme", "not a mapping, ignored");
+        };
+        let section = |name: &str| theme.get(name).and_then(Value::as_mapping);
+
+        if let Some(display) = theme.get("display") {
+            self.display(display);
+        }
+        for (name, v) in section("static_images").into_iter().flatten() {
+            let path = format!("static_images.{}", name.as_str().unwrap_or_default());
+            match v.as_mapping() {
+                Some(map) => self.static_image(&path, map),
+                None => self.warn(&path, "invalid image, skipped"),
+            }
+        }
+        for (name, v) in section("static_text").into_iter().flatten() {
+            let path = format!("static_text.{}", name.as_str().unwrap_or_default());
+            match v.as_mapping() {
+                Some(map) => self.static_text(&path, map),
+                None => self.warn(&path, "invalid text, skipped"),
+            }
+        }
+        if let Some(stats) = theme.get("STATS") {
+            self.stats("STATS", stats, None);
+        }
+        for key in theme.keys() {
+            match key.as_str() {
+                Some("author" | "display" | "static_images" | "static_text" | "STATS") => {}
+                Some(key) => self.warn(key, "unsupported section, ignored"),
+                None => self.warn("theme", "invalid key, ignored"),
+            }
+        }
+    }
+}
+
+impl MonitorConfig {
+    /// Convert a turing-smart-screen-python theme, with relative paths
+    /// relative to the theme directory.
+    ///
+    /// Returns the configuration and warnings about the parts of the theme
+    /// that are not supported.
+    pub fn from_theme<P: AsRef<Path>>(s: &str, theme_dir: P) -> Res<(Self, Vec<String>)> {
+        let theme: Value = serde_norway::from_str(s)?;
+        let mut importer = Importer {
+            dir: theme_dir.as_ref(),
+            config: MonitorConfig {
+                base_dir: theme_dir.as_ref().to_path_buf(),
+                ..Default::default()
+            },
+            warnings: Vec::new(),
+        };
+        importer.theme(&theme);
+        Ok((importer.config, importer.warnings))
+    }
+
+    /// Read a theme.yaml file.
+    pub fn load_theme<P: AsRef<Path>>(path: P) -> Res<(Self, Vec<String>)> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path)?;
+        Self::from_theme(&s, path.parent().unwrap_or(Path::new(".")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THEME: &str = r##"
+---
+author: "@mathoudebine"
+
+display:
+  DISPLAY_SIZE: 3.5"
+  DISPLAY_ORIENTATION: reverse_landscape
+  DISPLAY_RGB_LED: 255, 0, 0
+
+static_images:
+  BACKGROUND:
+    PATH: background.png
+    X: 0
+    Y: 0
+    WIDTH: 480
+    HEIGHT: 320
+
+static_text:
+  TITLE:
+    TEXT: "CPU"
+    X: 10
+    Y: 20
+    FONT: roboto/Roboto-Bold.ttf
+    FONT_SIZE: 16
+    FONT_COLOR: 255, 200, 0
+    BACKGROUND_IMAGE: background.png
+
+STATS:
+  CPU:
+    PERCENTAGE:
+      INTERVAL: 1
+      TEXT:
+        SHOW: True
+        X: 100
+        Y: 10
+      GRAPH:
+        SHOW: True
+        X: 100
+        Y: 40
+        HEIGHT: 8
+  GPU:
+    PERCENTAGE:
+      GRAPH:
+        SHOW: True
+  DATE:
+    INTERVAL: 1
+    DAY:
+      TEXT:
+        SHOW: True
+        FORMAT: long
+        X: 240
+        Y: 160
+        ANCHOR: mm
+        FONT_SIZE: 20
+        BACKGROUND_COLOR: "#102030"
+        BLINK: True
+    HOUR:
+      TEXT:
+        SHOW: False
+"##;
+
+    #[test]
+    fn test_from_theme() -> Res<()> {
+        let (config, warnings) = MonitorConfig::from_theme(THEME, "themes/3.5inchTheme2")?;
+        assert_eq!(
+            config.device.orientation.as_deref(),
+            Some("reverse-landscape")
+        );
+        assert_eq!(
+            config.fonts["roboto/Roboto-Bold.ttf"],
+            "roboto/Roboto-Bold.ttf"
+        );
+        assert_eq!(config.widgets.len(), 4);
+
+        let image = &config.widgets[0];
+        assert_eq!(image.kind, WidgetKind::Image);
+        assert_eq!(image.path.as_deref(), Some("background.png"));
+        assert_eq!((image.width, image.height), (Some(480), Some(320)));
+
+        let title = &config.widgets[1];
+        assert_eq!(title.text.as_deref(), Some("CPU"));
+        assert_eq!(title.color.as_deref(), Some("#ffc800"));
+        assert_eq!(title.size, Some(16.0));
+        assert_eq!(title.background, None);
+
+        let cpu = &config.widgets[2];
+        assert_eq!(cpu.source.as_deref(), Some("cpu.percent"));
+        assert_eq!(cpu.format.as_deref(), Some("{:.0}%"));
+        assert_eq!((cpu.x, cpu.y, cpu.interval), (100, 10, Some(1.0)));
+
+        let date = &config.widgets[3];
+        assert_eq!(date.source.as_deref(), Some("clock"));
+        assert_eq!(date.format.as_deref(), Some("%B %d, %Y"));
+        assert_eq!((date.x, date.y), (240, 150));
+        assert_eq!(date.align, Some(Align::Center));
+        assert_eq!(date.interval, Some(1.0));
+        assert_eq!(date.background.as_deref(), Some("#102030"));
+        assert_eq!(date.font.as_deref(), Some(DEFAULT_FONT));
+
+        assert_eq!(
+            warnings,
+            [
+                "display.DISPLAY_RGB_LED: unsupported key, ignored",
+                "STATS.CPU.PERCENTAGE.GRAPH: missing WIDTH/HEIGHT, skipped",
+                "STATS.GPU.PERCENTAGE.GRAPH: not supported, skipped",
+                "STATS.DATE.DAY.TEXT.BLINK: unsupported key, ignored",
+            ]
+        );
+
+        // the result is a valid configuration
+        let toml = config.to_toml()?;
+        assert_eq!(
+            MonitorConfig::from_toml(&toml, "themes/3.5inchTheme2")?,
+            config
+        );
+        Ok(())
+    }
+
+    fn stats(yaml: &str) -> (Vec<WidgetConfig>, Vec<String>) {
+        let theme = format!("STATS:\n{}", yaml);
+        let (config, warnings) = MonitorConfig::from_theme(&theme, "").unwrap();
+        (config.widgets, warnings)
+    }
+
+    #[test]
+    fn test_radial() {
+        let (widgets, warnings) = stats(
+            r#"
+  CPU:
+    PERCENTAGE:
+      RADIAL:
+        SHOW: True
+        X: 100
+        Y: 80
+        RADIUS: 40
+        WIDTH: 8
+        ANGLE_START: 110
+        ANGLE_END: 70
+        ANGLE_STEPS: 20
+        ANGLE_SEP: 5
+        CLOCKWISE: False
+        BAR_COLOR: 0, 255, 0
+        SHOW_TEXT: True
+        FONT_SIZE: 16
+        FONT_COLOR: 255, 255, 255
+        BACKGROUND_IMAGE: background.png
+"#,
+        );
+        assert!(warnings.is_empty());
+        assert_eq!(widgets.len(), 2);
+
+        let radial = &widgets[0];
+        assert_eq!(radial.kind, WidgetKind::Radial);
+        assert_eq!((radial.x, radial.y), (100, 80));
+        assert_eq!((radial.radius, radial.thickness), (Some(40), Some(8)));
+        assert_eq!(
+            (radial.angle_start, radial.angle_end),
+            (Some(110.0), Some(70.0))
+        );
+        assert_eq!((radial.steps, radial.gap), (Some(20), Some(5.0)));
+        assert_eq!(radial.clockwise, Some(false));
+        assert_eq!(radial.color.as_deref(), Some("#00ff00"));
+        assert_eq!(radial.background, None);
+
+        // the value centered in the ring
+        let text = &widgets[1];
+        assert_eq!(text.kind, WidgetKind::Text);
+        assert_eq!((text.x, text.y), (100, 72));
+        assert_eq!(text.align, Some(Align::Center));
+        assert_eq!(text.source.as_deref(), Some("cpu.percent"));
+        assert_eq!(text.format.as_deref(), Some("{:.0}%"));
+        assert_eq!(text.color.as_deref(), Some("#ffffff"));
+        assert_eq!(text.background, None);
+
+        // a single step is a continuous ring
+        let (widgets, _) = stats(
+            "  DISK:\n    USED:\n      RADIAL:\n        SHOW: True\n        ANGLE_STEPS: 1\n",
+        );
+        assert_eq!(widgets.len(), 1);
+        assert_eq!(widgets[0].steps, None);
+        assert_eq!(widgets[0].source.as_deref(), Some("disk.percent"));
+    }
+
+    #[test]
+    fn test_graph_outline() {
+        let (widgets, _) = stats(
+            r#"
+  MEMORY:
+    VIRTUAL:
+      GRAPH:
+        SHOW: True
+        WIDTH: 100
+        HEIGHT: 10
+        BAR_COLOR: 255, 0, 0
+        BAR_OUTLINE: True
+    SWAP:
+      GRAPH:
+        SHOW: True
+        WIDTH: 100
+        HEIGHT: 10
+        BAR_OUTLINE: False
+"#,
+        );
+        assert_eq!(widgets.len(), 2);
+        assert_eq!(widgets[0].kind, WidgetKind::Bar);
+        assert_eq!(widgets[0].color.as_deref(), Some("#ff0000"));
+        assert_eq!(widgets[0].outline.as_deref(), Some("#ff0000"));
+        assert_eq!(widgets[0].background.as_deref(), Some(DEFAULT_BACKGROUND));
+        assert_eq!(widgets[1].color.as_deref(), Some(DEFAULT_COLOR));
+        assert_eq!(widgets[1].outline, None);
+    }
+
+    #[test]
+    fn test_units() {
+        let (widgets, _) = stats(
+            r#"
+  CPU:
+    PERCENTAGE:
+      TEXT:
+        SHOW: True
+        SHOW_UNIT: False
+  MEMORY:
+    VIRTUAL:
+      USED:
+        SHOW: True
+      PERCENT_TEXT:
+        SHOW: True
+  DISK:
+    TOTAL:
+      TEXT:
+        SHOW: True
+"#,
+        );
+        let formats: Vec<_> = widgets.iter().map(|w| w.format.as_deref()).collect();
+        assert_eq!(
+            formats,
+            [
+                Some("{:.0}"),
+                Some("{:.0} M"),
+                Some("{:.0}%"),
+                Some("{:.0} G")
+            ]
+        );
+        // bytes to the units of the theme
+        let scales: Vec<_> = widgets.iter().map(|w| w.scale).collect();
+        assert_eq!(scales, [None, Some(1e-6), None, Some(1e-9)]);
+    }
+
+    #[test]
+    fn test_fonts_dir() -> Res<()> {
+        let dir = std::env::temp_dir().join(format!("turing-theme-{}", std::process::id()));
+        let theme_dir = dir.join("themes/Test");
+        fs::create_dir_all(&theme_dir)?;
+        fs::create_dir_all(dir.join("fonts/roboto"))?;
+        fs::write(dir.join("fonts/roboto/Roboto-Bold.ttf"), "")?;
+        fs::write(theme_dir.join("Roboto-Light.ttf"), "")?;
+
+        let theme = r#"
+static_text:
+  SHARED:
+    TEXT: a
+    FONT: roboto/Roboto-Bold.ttf
+  LOCAL:
+    TEXT: b
+    FONT: Roboto-Light.ttf
+"#;
+        let (config, _) = MonitorConfig::from_theme(theme, &theme_dir)?;
+        assert_eq!(
+            config.fonts["roboto/Roboto-Bold.ttf"],
+            "../../fonts/roboto/Roboto-Bold.ttf"
+        );
+        assert_eq!(config.fonts["Roboto-Light.ttf"], "Roboto-Light.ttf");
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_color() {
+        assert_eq!(
+            color(&Value::from("255, 0, 16")).as_deref(),
+            Some("#ff0010")
+        );
+        assert_eq!(color(&Value::from("#A0B0C0")).as_deref(), Some("#a0b0c0"));
+        assert_eq!(color(&Value::from("256, 0, 0")), None);
+        assert_eq!(color(&Value::from("red")), None);
+    }
+}
@@ -0,0 +1,403 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{EmulatorRevA, Error, Res, ScreenPort};
+
+// Trace file layout: the magic string followed by one record per
+// operation, each with a direction byte, a u64 LE timestamp in
+// microseconds, a u32 LE length and the data.
+const MAGIC: &[u8] = b"TURINGTRACE1";
+const HEADER_SIZE: usize = 1 + 8 + 4;
+
+/// Direction of the data in a trace event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Write, // host to device
+    Read,  // device to host
+}
+
+impl Direction {
+    fn tag(self) -> u8 {
+        match self {
+            Direction::Write => b'W',
+            Direction::Read => b'R',
+        }
+    }
+}
+
+/// A recorded port operation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub time: Duration, // since the port was opened
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+/// A port wrapper that records all the traffic to a trace file.
+pub struct RecordingPort {
+    port: Box<dyn ScreenPort>,
+    trace: Box<dyn Write + Send>,
+    start: Instant,
+}
+
+impl RecordingPort {
+    pub fn new(port: Box<dyn ScreenPort>, mut trace: Box<dyn Write + Send>) -> Res<Self> {
+        trace.write_all(MAGIC)?;
+        Ok(Self {
+            port,
+            trace,
+            start: Instant::now(),
+        })
+    }
+
+    /// Record the traffic to the given file.
+    pub fn create<P: AsRef<Path>>(port: Box<dyn ScreenPort>, path: P) -> Res<Self> {
+        let file = File::create(path)?;
+        Self::new(port, Box::new(BufWriter::new(file)))
+    }
+
+    // Record the traffic to a trace file shared with other ports.
+    pub(crate) fn with_trace(port: Box<dyn ScreenPort>, trace: &TraceFile) -> Self {
+        Self {
+            port,
+            trace: Box::new(trace.clone()),
+            start: trace.start,
+        }
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        let time = self.start.elapsed().as_micros() as u64;
+        let mut record = Vec::with_capacity(HEADER_SIZE + data.len());
+        record.push(direction.tag());
+        record.extend_from_slice(&time.to_le_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(data);
+        self.trace.write_all(&record)
+    }
+}
+
+/// A trace file recording the ports opened one after the other while
+/// probing a device.
+#[derive(Clone)]
+pub(crate) struct TraceFile {
+    file: Arc<Mutex<BufWriter<File>>>,
+    start: Instant,
+}
+
+impl TraceFile {
+    pub fn create<P: AsRef<Path>>(path: P) -> Res<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            start: Instant::now(),
+        })
+    }
+}
+
+impl Write for TraceFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.lock().unwrap().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        // keep the records whole
+        self.file.lock().unwrap().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.lock().unwrap().flush()
+    }
+}
+
+impl Read for RecordingPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.port.read(buf)?;
+        if n > 0 {
+            self.record(Direction::Read, &buf[..n])?;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for RecordingPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.port.write(buf)?;
+        if n > 0 {
+            self.record(Direction::Write, &buf[..n])?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()?;
+        self.trace.flush()
+    }
+}
+
+impl ScreenPort for RecordingPort {
+    fn get_buf(&self) -> Vec<u8> {
+        self.port.get_buf()
+    }
+}
+
+impl Drop for RecordingPort {
+    fn drop(&mut self) {
+        let _ = self.trace.flush();
+    }
+}
+
+fn invalid_trace(msg: &str) -> Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid trace: {}", msg),
+    )
+    .into()
+}
+
+/// Parse a trace recorded by [`RecordingPort`].
+pub fn read_trace<R: Read>(mut r: R) -> Res<Vec<TraceEvent>> {
+    let mut magic = [0u8; MAGIC.len()];
+    r.read_exact(&mut magic)
+        .map_err(|_| invalid_trace("missing header"))?;
+    if magic != MAGIC {
+        return Err(invalid_trace("bad magic"));
+    }
+
+    let mut events = Vec::new();
+    let mut header = [0u8; HEADER_SIZE];
+    loop {
+        // a trace cut short by a crash ends with a partial record
+        match r.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        }
+        let direction = match header[0] {
+            b'W' => Direction::Write,
+            b'R' => Direction::Read,
+            _ => return Err(invalid_trace("bad record direction")),
+        };
+        let time = u64::from_le_bytes(header[1..9].try_into().unwrap());
+        let len = u32::from_le_bytes(header[9..13].try_into().unwrap()) as usize;
+        // the length is not trusted before the data is read
+        let mut data = Vec::new();
+        r.by_ref().take(len as u64).read_to_end(&mut data)?;
+        if data.len() < len {
+            break;
+        }
+        events.push(TraceEvent {
+            time: Duration::from_micros(time),
+            direction,
+            data,
+        });
+    }
+    Ok(events)
+}
+
+/// Load a trace file recorded by [`RecordingPort`].
+pub fn load_trace<P: AsRef<Path>>(path: P) -> Res<Vec<TraceEvent>> {
+    read_trace(BufReader::new(File::open(path)?))
+}
+
+/// Send the recorded writes to a port.
+///
+/// The recorded reads are consumed from the port and compared with the
+/// trace, differences are logged as warnings. If `timing` is set, the
+/// original delays between writes are reproduced.
+pub fn replay(events: &[TraceEvent], port: &mut dyn ScreenPort, timing: bool) -> Res<()> {
+    let start = Instant::now();
+    for ev in events {
+        if timing {
+            if let Some(delay) = ev.time.checked_sub(start.elapsed()) {
+                thread::sleep(delay);
+            }
+        }
+        match ev.direction {
+            Direction::Write => {
+                port.write_all(&ev.data)?;
+                port.flush()?;
+            }
+            Direction::Read => {
+                let mut data = vec![0u8; ev.data.len()];
+                port.read_exact(&mut data)?;
+                if data != ev.data {
+                    log::warn!(
+                        "replay response differs: got {:02x?}, recorded {:02x?}",
+                        data,
+                        ev.data
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+const DUMP_BYTES: usize = 16;
+
+/// Write a human-readable dump of a trace.
+///
+/// Writes are decoded as rev A commands, and each event is followed by the
+/// commands completed by its data.
+pub fn dump<W: Write>(events: &[TraceEvent], mut out: W) -> Res<()> {
+    let mut decoder = EmulatorRevA::new();
+    for ev in events {
+        let hex: Vec<String> = ev.data[..ev.data.len().min(DUMP_BYTES)]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let more = if ev.data.len() > DUMP_BYTES {
+            " ..."
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "{:12.6} {} {:7}  {}{}",
+            ev.time.as_secs_f64(),
+            ev.direction.tag() as char,
+            ev.data.len(),
+            hex.join(" "),
+            more
+        )?;
+
+        if ev.direction == Direction::Write {
+            decoder.write_all(&ev.data)?;
+            for cmd in decoder.take_commands() {
+                writeln!(out, "{:24}{}", "", cmd)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use crate::tests::FakePort;
+    use crate::{Coord, Image, Rect, Screen, ScreenRevA};
+
+    // A trace writer shared with the test
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record(f: impl FnOnce(&mut ScreenRevA) -> Res<()>) -> Res<Vec<TraceEvent>> {
+        let buf = SharedBuf::default();
+        let port = FakePort::new(vec![0u8, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1]);
+        let port = RecordingPort::new(Box::new(port), Box::new(buf.clone()))?;
+        let mut scr = ScreenRevA::with_port(Box::new(port));
+        f(&mut scr)?;
+        drop(scr);
+        let data = buf.0.lock().unwrap().clone();
+        read_trace(data.as_slice())
+    }
+
+    #[test]
+    fn test_record() -> Res<()> {
+        let events = record(|scr| scr.init())?;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].direction, Direction::Write);
+        assert_eq!(events[0].data, [0, 0, 0, 0, 0, 69]);
+        assert_eq!(events[1].direction, Direction::Read);
+        assert_eq!(events[1].data, [1, 1, 1, 1, 1, 1]);
+        assert!(events[0].time <= events[1].time);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_trace_truncated() -> Res<()> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[b'W', 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0xaa, 0xbb]);
+        data.extend_from_slice(&[b'W', 2, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0xcc]);
+        let events = read_trace(data.as_slice())?;
+        assert_eq!(
+            events,
+            [TraceEvent {
+                time: Duration::from_micros(1),
+                direction: Direction::Write,
+                data: vec![0xaa, 0xbb],
+            }]
+        );
+
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[b'R', 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xaa]);
+        assert_eq!(read_trace(data.as_slice())?, []);
+
+        assert!(read_trace(&b"NOTATRACE"[..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_trace_file() -> Res<()> {
+        let path = std::env::temp_dir().join(format!("turing-trace-{}", std::process::id()));
+        let trace = TraceFile::create(&path)?;
+        for answer in [0u8, 1] {
+            // the hello command overwrites the first 6 bytes
+            let port = FakePort::new([[0; 6], [answer; 6]].concat());
+            let mut scr =
+                ScreenRevA::with_port(Box::new(RecordingPort::with_trace(Box::new(port), &trace)));
+            // a failed probe, then the right revision
+            assert_eq!(scr.init().is_ok(), answer == 1);
+        }
+        drop(trace);
+
+        // both ports are recorded, after a single header
+        let events = load_trace(&path)?;
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[1].data, [0; 6]);
+        assert_eq!(events[3].data, [1; 6]);
+        assert!(events[1].time <= events[2].time);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay() -> Res<()> {
+        let events = record(|scr| {
+            scr.init()?;
+            scr.set_brightness(0x55)
+        })?;
+
+        let emu = EmulatorRevA::new();
+        replay(&events, &mut emu.clone(), false)?;
+        assert_eq!(emu.brightness(), 0x55);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump() -> Res<()> {
+        let events = record(|scr| {
+            scr.init()?;
+            let image = Image::new(10, 2);
+            scr.display_image(&image, &Rect::new(0, 0, 10, 2), &Coord::new(1, 2))
+        })?;
+
+        let mut out = Vec::new();
+        dump(&events, &mut out)?;
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0][13..], *"W       6  00 00 00 00 00 45");
+        assert_eq!(lines[1].trim(), "Hello");
+        assert_eq!(lines[2][13..], *"R       6  01 01 01 01 01 01");
+        assert_eq!(lines.last().unwrap().trim(), "DisplayBitmap @1,2+10x2");
+        Ok(())
+    }
+}
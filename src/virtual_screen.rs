@@ -0,0 +1,372 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::{Coord, Image, Rect, Rgba};
+use crate::{Error, Orientation, Res, Screen};
+
+/// Pixel format of the virtual screen framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelFormat {
+    Rgba,   // 4 bytes per pixel, lossless
+    Rgb565, // 2 bytes per pixel, little endian, as sent to the hardware
+}
+
+impl PixelFormat {
+    fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba => 4,
+            PixelFormat::Rgb565 => 2,
+        }
+    }
+}
+
+/// A screen that renders to an in-memory framebuffer.
+///
+/// The framebuffer holds the panel contents in its native portrait layout,
+/// so changing the orientation rotates the existing contents as on the
+/// hardware. Snapshots show the panel as seen by the user, with the current
+/// brightness applied and black when the screen is off.
+pub struct VirtualScreen {
+    width: usize,  // panel width in portrait orientation
+    height: usize, // panel height in portrait orientation
+    format: PixelFormat,
+    orientation: Orientation,
+    brightness: usize,
+    on: bool,
+    fb: Vec<u8>,
+}
+
+impl VirtualScreen {
+    /// Create a virtual screen with the given portrait size.
+    pub fn new(width: usize, height: usize) -> Self {
+        let format = PixelFormat::Rgb565;
+        Self {
+            width,
+            height,
+            format,
+            orientation: Orientation::Portrait,
+            brightness: 255,
+            on: true,
+            fb: vec![0u8; format.bytes_per_pixel() * width * height],
+        }
+    }
+
+    /// Set the framebuffer pixel format, clearing its contents.
+    pub fn format(mut self, format: PixelFormat) -> Self {
+        self.format = format;
+        self.fb = vec![0u8; format.bytes_per_pixel() * self.width * self.height];
+        self
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation.clone()
+    }
+
+    pub fn brightness(&self) -> usize {
+        self.brightness
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    /// The screen contents as seen by the user.
+    pub fn snapshot(&self) -> Image {
+        let (width, height) = self.screen_size();
+        let mut image = Image::new(width, height);
+        if !self.on {
+            return image;
+        }
+        let level = self.brightness as u16;
+        for y in 0..height {
+            for x in 0..width {
+                let p = self.get_pixel(self.to_panel(x, y));
+                image.buffer[y * width + x] = Rgba::new(
+                    (p.r as u16 * level / 255) as u8,
+                    (p.g as u16 * level / 255) as u8,
+                    (p.b as u16 * level / 255) as u8,
+                    255,
+                );
+            }
+        }
+        image
+    }
+
+    /// Encode a snapshot of the screen as PNG.
+    pub fn write_png<W: Write>(&self, w: W) -> Res<()> {
+        let image = self.snapshot();
+        let mut rgb = Vec::with_capacity(3 * image.width * image.height);
+        for p in &image.buffer {
+            rgb.extend_from_slice(&[p.r, p.g, p.b]);
+        }
+
+        let mut encoder = png::Encoder::new(w, image.width as u32, image.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&rgb)?;
+        Ok(())
+    }
+
+    /// Save a snapshot of the screen to a PNG file.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Res<()> {
+        let file = File::create(path)?;
+        self.write_png(BufWriter::new(file))
+    }
+
+    // Map screen coordinates to panel coordinates.
+    fn to_panel(&self, x: usize, y: usize) -> (usize, usize) {
+        let (w, h) = (self.width, self.height);
+        match self.orientation {
+            Orientation::Portrait => (x, y),
+            Orientation::ReversePortrait => (w - 1 - x, h - 1 - y),
+            Orientation::Landscape => (w - 1 - y, x),
+            Orientation::ReverseLandscape => (y, h - 1 - x),
+        }
+    }
+
+    fn get_pixel(&self, (x, y): (usize, usize)) -> Rgba {
+        let bpp = self.format.bytes_per_pixel();
+        let p = &self.fb[bpp * (y * self.width + x)..];
+        match self.format {
+            PixelFormat::Rgba => Rgba::new(p[0], p[1], p[2], p[3]),
+            PixelFormat::Rgb565 => {
+                let (lo, hi) = (p[0], p[1]);
+                let r = hi & 0xf8;
+                let g = ((hi & 0x07) << 5) | ((lo & 0xe0) >> 3);
+                let b = lo << 3;
+                Rgba::new(r | (r >> 5), g | (g >> 6), b | (b >> 5), 255)
+            }
+        }
+    }
+
+    fn set_pixel(&mut self, (x, y): (usize, usize), p: Rgba) {
+        let bpp = self.format.bytes_per_pixel();
+        let ofs = bpp * (y * self.width + x);
+        match self.format {
+            PixelFormat::Rgba => self.fb[ofs..ofs + 4].copy_from_slice(&[p.r, p.g, p.b, 255]),
+            PixelFormat::Rgb565 => {
+                self.fb[ofs] = ((p.g & 0x1c) << 3) | (p.b >> 3);
+                self.fb[ofs + 1] = (p.r & 0xf8) | (p.g >> 5);
+            }
+        }
+    }
+}
+
+impl Screen for VirtualScreen {
+    fn screen_size(&self) -> (usize, usize) {
+        match self.orientation {
+            Orientation::Portrait | Orientation::ReversePortrait => (self.width, self.height),
+            Orientation::Landscape | Orientation::ReverseLandscape => (self.height, self.width),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Res<usize> {
+        Ok(data.len())
+    }
+
+    // There is no device to answer commands.
+    fn read(&mut self, _n: usize) -> Res<Vec<u8>> {
+        Err(Error::Timeout)
+    }
+
+    fn init(&mut self) -> Res<()> {
+        log::debug!("init virtual screen");
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Res<()> {
+        log::debug!("clear virtual screen");
+        self.fb.fill(0xff);
+        Ok(())
+    }
+
+    fn screen_on(&mut self) -> Res<()> {
+        self.on = true;
+        Ok(())
+    }
+
+    fn screen_off(&mut self) -> Res<()> {
+        self.on = false;
+        Ok(())
+    }
+
+    fn set_orientation(&mut self, o: Orientation) -> Res<()> {
+        log::debug!("set virtual screen orientation to {:?}", o);
+        self.orientation = o;
+        Ok(())
+    }
+
+    fn set_brightness(&mut self, level: usize) -> Res<()> {
+        log::debug!("set virtual screen brightness to {}", level);
+        self.brightness = level.min(255);
+        Ok(())
+    }
+
+    fn display_image(&mut self, image: &Image, crop: &Rect, pos: &Coord) -> Res<()> {
+        let (width, height) = self.screen_size();
+        if pos.x > width || pos.y > height {
+            return Err(Error::OutOfBounds(crop.clone(), pos.clone()));
+        }
+        let r = crop.clip(crop.x + width - pos.x, crop.y + height - pos.y);
+
+        for y in 0..r.h {
+            for x in 0..r.w {
+                let p = image.buffer[(r.y + y) * image.width + r.x + x];
+                self.set_pixel(self.to_panel(pos.x + x, pos.y + y), p);
+            }
+        }
+        Ok(())
+    }
+
+    fn framebuffer(&self) -> &[u8] {
+        &self.fb
+    }
+
+    fn restore_framebuffer(&mut self, fb: &[u8]) -> Res<()> {
+        if fb.len() != self.fb.len() {
+            return Err(Error::FramebufferSize(fb.len(), self.fb.len()));
+        }
+        self.fb.copy_from_slice(fb);
+        Ok(())
+    }
+
+    fn model(&self) -> String {
+        format!("virtual screen {}x{}", self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colors;
+
+    const RED: Rgba = Rgba::new(0xff, 0, 0, 0xff);
+
+    // A 2x3 image with a red pixel at the top left corner
+    fn marked_image() -> Image {
+        let mut image = Image::new(2, 3);
+        image.buffer[0] = RED;
+        image
+    }
+
+    #[test]
+    fn test_display_image() -> Res<()> {
+        let mut scr = VirtualScreen::new(4, 6).format(PixelFormat::Rgba);
+        let image = marked_image();
+        scr.display_image(&image, &image.full(), &Coord::new(1, 2))?;
+
+        let snap = scr.snapshot();
+        assert_eq!((snap.width, snap.height), (4, 6));
+        assert_eq!(snap.buffer[2 * 4 + 1], RED);
+        assert_eq!(snap.buffer[2 * 4 + 2], colors::BLACK);
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_rgb565() -> Res<()> {
+        let mut scr = VirtualScreen::new(4, 6);
+        let mut image = Image::new(1, 1);
+        image.buffer[0] = Rgba::new(0x55, 0xaa, 0xff, 0xff);
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+        assert_eq!(scr.framebuffer()[..2], [0b01011111, 0b01010101]);
+        assert_eq!(scr.snapshot().buffer[0], Rgba::new(0x52, 0xaa, 0xff, 0xff));
+
+        let fb = scr.framebuffer().to_vec();
+        let mut other = VirtualScreen::new(4, 6);
+        other.restore_framebuffer(&fb)?;
+        assert_eq!(other.framebuffer(), fb);
+        assert!(matches!(
+            other.restore_framebuffer(&fb[..4]),
+            Err(Error::FramebufferSize(4, 48))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_clipped() -> Res<()> {
+        let mut scr = VirtualScreen::new(4, 6);
+        let image = marked_image();
+        scr.display_image(&image, &image.full(), &Coord::new(3, 5))?;
+        assert!(matches!(
+            scr.display_image(&image, &image.full(), &Coord::new(5, 0)),
+            Err(Error::OutOfBounds(..))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_image_crop() -> Res<()> {
+        // the crop area is in image coordinates, as when sending part of a
+        // full screen image
+        let mut scr = VirtualScreen::new(4, 6).format(PixelFormat::Rgba);
+        let mut image = Image::new(4, 6);
+        image.buffer[5 * 4 + 3] = RED;
+        scr.display_image(&image, &Rect::new(3, 5, 1, 1), &Coord::new(3, 5))?;
+        assert_eq!(scr.snapshot().buffer[5 * 4 + 3], RED);
+        Ok(())
+    }
+
+    #[test]
+    fn test_orientation() -> Res<()> {
+        let mut scr = VirtualScreen::new(4, 6);
+        let image = marked_image();
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+
+        // top left corner of the panel, seen from each orientation
+        for (o, size, pos) in [
+            (Orientation::Landscape, (6, 4), (0, 3)),
+            (Orientation::ReversePortrait, (4, 6), (3, 5)),
+            (Orientation::ReverseLandscape, (6, 4), (5, 0)),
+        ] {
+            scr.set_orientation(o)?;
+            let snap = scr.snapshot();
+            assert_eq!((snap.width, snap.height), size);
+            assert_eq!(snap.buffer[pos.1 * snap.width + pos.0], RED);
+        }
+
+        // drawing follows the orientation
+        scr.set_orientation(Orientation::Landscape)?;
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+        scr.set_orientation(Orientation::Portrait)?;
+        assert_eq!(scr.snapshot().buffer[3], RED);
+        Ok(())
+    }
+
+    #[test]
+    fn test_brightness_and_power() -> Res<()> {
+        let mut scr = VirtualScreen::new(4, 6).format(PixelFormat::Rgba);
+        scr.clear()?;
+        scr.set_brightness(51)?;
+        assert_eq!(scr.snapshot().buffer[0], Rgba::new(51, 51, 51, 255));
+
+        scr.screen_off()?;
+        assert!(!scr.is_on());
+        assert_eq!(scr.snapshot().buffer[0], colors::BLACK);
+        scr.screen_on()?;
+        assert_eq!(scr.snapshot().buffer[0], Rgba::new(51, 51, 51, 255));
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_png() -> Res<()> {
+        let mut scr = VirtualScreen::new(4, 6);
+        scr.set_orientation(Orientation::Landscape)?;
+        let image = marked_image();
+        scr.display_image(&image, &image.full(), &Coord::new(0, 0))?;
+
+        let mut data = Vec::new();
+        scr.write_png(&mut data)?;
+
+        let decoder = png::Decoder::new(data.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        assert_eq!((info.width, info.height), (6, 4));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        assert_eq!(buf[..6], [0xff, 0, 0, 0, 0, 0]);
+        Ok(())
+    }
+}